@@ -0,0 +1,53 @@
+//! Trains a small MLP on XOR, or on MNIST-style CSV files:
+//!
+//!     cargo run --release --example classify -- train.csv [test.csv]
+//!
+//! Every line holds the label followed by the pixel values in 0..=255.
+use autodiff::nn::{Activation, CsvError, Dataset, Mlp};
+use autodiff::tensor::Matrix;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+fn load(path: &str) -> Result<Dataset, CsvError> {
+    let mut data = Dataset::from_csv(BufReader::new(File::open(path)?))?;
+    data.inputs = data.inputs.map(|x| x / 255.0);
+    Ok(data)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(train_path) = args.first() else {
+        let inputs = Matrix::from_rows(&[[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]]);
+        let xor = Dataset::new(inputs, vec![0, 1, 1, 0]);
+        let mut mlp = Mlp::new(&[2, 8, 2], Activation::Tanh, 0);
+        for epoch in (0..500).step_by(100) {
+            let loss = mlp.fit(&xor, 100, 4, 0.5)?;
+            println!("epoch {:>3}: loss {loss:.4}", epoch + 100);
+        }
+        println!("{:?}", mlp.predict(&xor.inputs).argmax_rows());
+        return Ok(());
+    };
+
+    let mut train = load(train_path)?;
+    let test = match args.get(1) {
+        Some(path) => load(path)?,
+        None => train.clone(),
+    };
+    // one-hot targets need a column for every class the network predicts
+    train.classes = train.classes.max(test.classes);
+    let mut mlp = Mlp::new(
+        &[train.inputs.cols(), 64, train.classes],
+        Activation::Relu,
+        0,
+    );
+    for epoch in 1..=10 {
+        let loss = mlp.fit(&train, 1, 32, 0.1)?;
+        println!(
+            "epoch {epoch:>2}: loss {loss:.4}, accuracy {:.3}",
+            mlp.accuracy(&test)
+        );
+    }
+    Ok(())
+}
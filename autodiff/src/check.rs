@@ -0,0 +1,396 @@
+//! Compares [`Var`] derivatives with central finite differences.
+use crate::diff::value_and_gradient;
+use crate::Var;
+
+/// Comparison for a single input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputCheck {
+    pub analytic: f64,
+    pub numeric: f64,
+    pub abs_error: f64,
+    /// Absolute error over the larger magnitude of both derivatives, 0 when
+    /// both are 0.
+    pub rel_error: f64,
+}
+
+impl InputCheck {
+    fn new(analytic: f64, numeric: f64) -> Self {
+        let abs_error = (analytic - numeric).abs();
+        let scale = analytic.abs().max(numeric.abs());
+        let rel_error = if scale == 0.0 { 0.0 } else { abs_error / scale };
+        Self {
+            analytic,
+            numeric,
+            abs_error,
+            rel_error,
+        }
+    }
+
+    /// Small derivatives are compared absolutely, large ones relatively.
+    pub fn passes(&self, tol: f64) -> bool {
+        self.abs_error <= tol || self.rel_error <= tol
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientCheck {
+    pub value: f64,
+    pub inputs: Vec<InputCheck>,
+}
+
+impl GradientCheck {
+    pub fn max_rel_error(&self) -> f64 {
+        self.inputs.iter().fold(0.0, |acc, c| acc.max(c.rel_error))
+    }
+
+    pub fn passes(&self, tol: f64) -> bool {
+        self.inputs.iter().all(|c| c.passes(tol))
+    }
+}
+
+/// Checks the gradient of `f` at `x`. Input `i` is perturbed by
+/// `step * (1 + |x[i]|)` in both directions.
+pub fn check_gradient(f: impl Fn(&[Var<f64>]) -> Var<f64>, x: &[f64], step: f64) -> GradientCheck {
+    let (value, gradient) = value_and_gradient(&f, x);
+    let inputs = gradient
+        .into_iter()
+        .enumerate()
+        .map(|(i, analytic)| InputCheck::new(analytic, central_difference(&f, x, i, step)))
+        .collect();
+    GradientCheck { value, inputs }
+}
+
+fn eval_at(f: &impl Fn(&[Var<f64>]) -> Var<f64>, x: &[f64]) -> f64 {
+    let inputs: Vec<Var<f64>> = x.iter().map(|&x| Var::constant(x)).collect();
+    f(&inputs).value()
+}
+
+fn central_difference(f: &impl Fn(&[Var<f64>]) -> Var<f64>, x: &[f64], i: usize, step: f64) -> f64 {
+    let h = step * (1.0 + x[i].abs());
+    let mut shifted = x.to_vec();
+    shifted[i] = x[i] + h;
+    let forward = eval_at(f, &shifted);
+    shifted[i] = x[i] - h;
+    let backward = eval_at(f, &shifted);
+    (forward - backward) / (2.0 * h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbolic::{Expr, Func, Func2};
+    use proptest::prelude::*;
+    use std::collections::HashMap;
+
+    const STEP: f64 = 1e-6;
+    const TOL: f64 = 1e-4;
+    const SYMBOLS: [&str; 3] = ["x", "y", "z"];
+
+    #[test]
+    fn reports_error_per_input() {
+        let check = check_gradient(|v| v[0] * v[1].sin(), &[2.0, 0.5], STEP);
+        assert_eq!(check.inputs.len(), 2);
+        assert!(check.passes(1e-8), "{check:?}");
+        assert_eq!(check.value, 2.0 * 0.5_f64.sin());
+    }
+
+    #[test]
+    fn detects_wrong_derivative() {
+        // reports d/dx as 2 instead of 1
+        let broken = |v: &[Var<f64>]| Var::new(v[0].value(), 2.0 * v[0].deriv());
+        let check = check_gradient(broken, &[1.0], STEP);
+        assert!(!check.passes(TOL));
+        assert!((check.max_rel_error() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ln_and_division_near_zero() {
+        for x in [1e-3_f64, -1e-3, 1e-5] {
+            let step = 1e-4 * x.abs();
+            assert!(check_gradient(|v| v[0].abs().ln(), &[x], step).passes(TOL));
+            assert!(check_gradient(|v| 1.0 / v[0], &[x], step).passes(TOL));
+            assert!(check_gradient(|v| 1.0 / (v[0] * v[0]), &[x], step).passes(TOL));
+        }
+    }
+
+    fn expr_strategy() -> impl Strategy<Value = Expr> {
+        let leaf = prop_oneof![
+            prop::sample::select(SYMBOLS.to_vec()).prop_map(Expr::symbol),
+            (-3.0..3.0f64).prop_map(Expr::constant),
+        ];
+        leaf.prop_recursive(4, 24, 2, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(|a| -a),
+                (inner.clone(), -3..5i32).prop_map(|(a, n)| a.powi(n)),
+                (inner.clone(), prop::sample::select(Func::ALL.to_vec()))
+                    .prop_map(|(a, f)| a.call(f)),
+                (inner.clone(), inner.clone()).prop_map(|(a, b)| a + b),
+                (inner.clone(), inner.clone()).prop_map(|(a, b)| a - b),
+                (inner.clone(), inner.clone()).prop_map(|(a, b)| a * b),
+                (inner.clone(), inner.clone()).prop_map(|(a, b)| a / b),
+                (
+                    inner.clone(),
+                    inner,
+                    prop::sample::select(Func2::ALL.to_vec())
+                )
+                    .prop_map(|(a, b, f)| a.call2(f, &b)),
+            ]
+        })
+    }
+
+    // mostly moderate values with some close to the domain edge at 0
+    fn coordinate() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            3 => -2.0..2.0f64,
+            1 => (1e-4..1e-2f64, any::<bool>()).prop_map(|(x, neg)| if neg { -x } else { x }),
+        ]
+    }
+
+    fn eval(expr: &Expr, point: &[f64]) -> f64 {
+        let env = SYMBOLS
+            .iter()
+            .map(|s| s.to_string())
+            .zip(point.iter().copied())
+            .collect();
+        expr.eval(&env).unwrap()
+    }
+
+    // rounding error grows with the largest intermediate rather than the
+    // result, None when one underflows or overflows and loses all precision
+    fn largest_intermediate(expr: &Expr, point: &[f64]) -> Option<f64> {
+        let v = eval(expr, point).abs();
+        if !v.is_finite() || (v != 0.0 && !(1e-200..1e200).contains(&v)) {
+            return None;
+        }
+        let children = match expr {
+            Expr::Const(_) | Expr::Symbol(_) => 0.0,
+            Expr::Neg(a) | Expr::Powi(a, _) | Expr::Call(_, a) => largest_intermediate(a, point)?,
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Call2(_, a, b) => {
+                largest_intermediate(a, point)?.max(largest_intermediate(b, point)?)
+            }
+        };
+        Some(v.max(children))
+    }
+
+    // first-order bound on the rounding error of evaluating `expr`. Every
+    // operation adds an ulp of its result and passes on the errors of its
+    // operands scaled by its partial derivatives, so ill-conditioned calls
+    // like tan of a huge argument amplify what came before
+    fn rounding_error(expr: &Expr, point: &[f64]) -> f64 {
+        let (a, b) = (Expr::symbol("a"), Expr::symbol("b"));
+        let (local, operands): (Expr, Vec<&Expr>) = match expr {
+            Expr::Const(_) | Expr::Symbol(_) => return 0.0,
+            Expr::Neg(u) => (-&a, vec![u]),
+            Expr::Powi(u, n) => (a.powi(*n), vec![u]),
+            Expr::Call(f, u) => (a.call(*f), vec![u]),
+            Expr::Add(u, v) => (&a + &b, vec![u, v]),
+            Expr::Sub(u, v) => (&a - &b, vec![u, v]),
+            Expr::Mul(u, v) => (&a * &b, vec![u, v]),
+            Expr::Div(u, v) => (&a / &b, vec![u, v]),
+            Expr::Call2(f, u, v) => (a.call2(*f, &b), vec![u, v]),
+        };
+        let values: Vec<f64> = operands.iter().map(|u| eval(u, point)).collect();
+        let mut error = f64::EPSILON * eval(expr, point).abs();
+        for (k, operand) in operands.iter().enumerate() {
+            let env: HashMap<String, Var<f64>> = ["a", "b"]
+                .iter()
+                .zip(&values)
+                .enumerate()
+                .map(|(j, (s, &x))| {
+                    let dual = if j == k {
+                        Var::variable(x)
+                    } else {
+                        Var::constant(x)
+                    };
+                    (s.to_string(), dual)
+                })
+                .collect();
+            let partial = local.eval(&env).unwrap().deriv();
+            error += partial.abs() * rounding_error(operand, point);
+        }
+        error
+    }
+
+    // log with a base of 0 or 1, and a negative base raised to a varying
+    // exponent, are outside their domain, yet rounding can still leave a
+    // finite value, which has no derivative to check
+    fn outside_domain(expr: &Expr, point: &[f64]) -> bool {
+        match expr {
+            Expr::Const(_) | Expr::Symbol(_) => false,
+            Expr::Neg(a) | Expr::Powi(a, _) | Expr::Call(_, a) => outside_domain(a, point),
+            Expr::Call2(Func2::Log, _, base)
+                if eval(base, point) == 0.0 || eval(base, point) == 1.0 =>
+            {
+                true
+            }
+            Expr::Call2(Func2::Pow, base, exponent)
+                if !matches!(**exponent, Expr::Const(_)) && eval(base, point) < 0.0 =>
+            {
+                true
+            }
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Call2(_, a, b) => outside_domain(a, point) || outside_domain(b, point),
+        }
+    }
+
+    // why a central difference cannot check the derivative at a point
+    #[derive(Debug)]
+    enum Unresolved {
+        // f is not finite somewhere in the stencil, the point sits on the edge
+        // of the domain or next to a pole
+        LeavesDomain,
+        // the one-sided differences disagree, there is a kink or a pole
+        // between the stencil points
+        Kink,
+        // rounding of an intermediate hides any change over the stencil, as
+        // within an ulp of a branch point like asin(1). Var is still compared
+        // with the symbolic derivative at such points
+        Flat,
+    }
+
+    // estimates the truncation error of the central difference by halving
+    // the step
+    fn truncation_error(expr: &Expr, point: &[f64], i: usize, h: f64) -> Result<f64, Unresolved> {
+        let mut shifted = point.to_vec();
+        let samples = [-2.0, -1.0, 0.0, 1.0, 2.0].map(|k| {
+            shifted[i] = point[i] + k * h;
+            eval(expr, &shifted)
+        });
+        if samples.iter().any(|s| !s.is_finite()) {
+            return Err(Unresolved::LeavesDomain);
+        }
+        if samples.iter().all(|&s| s == samples[2]) {
+            return Err(Unresolved::Flat);
+        }
+        let backward = (samples[2] - samples[0]) / (2.0 * h);
+        let forward = (samples[4] - samples[2]) / (2.0 * h);
+        if (forward - backward).abs() > 1e-2 * (1.0 + forward.abs().max(backward.abs())) {
+            return Err(Unresolved::Kink);
+        }
+        let central = (samples[3] - samples[1]) / (2.0 * h);
+        let wide = (samples[4] - samples[0]) / (4.0 * h);
+        Ok((central - wide).abs())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            cases: 512,
+            // random expressions often leave their domain, those are rejected
+            max_global_rejects: 100_000,
+            ..ProptestConfig::default()
+        })]
+
+        #[test]
+        fn var_gradient_matches_finite_differences(
+            expr in expr_strategy(),
+            point in prop::array::uniform3(coordinate()),
+        ) {
+            let value = eval(&expr, &point);
+            prop_assume!(value.is_finite() && value.abs() < 1e6);
+            prop_assume!(!outside_domain(&expr, &point));
+            prop_assume!(largest_intermediate(&expr, &point).is_some());
+
+            let f = |v: &[Var<f64>]| {
+                let env: HashMap<String, Var<f64>> =
+                    SYMBOLS.iter().map(|s| s.to_string()).zip(v.iter().copied()).collect();
+                expr.eval(&env).unwrap()
+            };
+            let used = expr.symbols();
+            for (i, name) in SYMBOLS.iter().enumerate() {
+                // f is flat along a symbol it does not use
+                if !used.iter().any(|s| s == name) {
+                    let analytic = check_gradient(f, &point, 1e-7).inputs[i].analytic;
+                    prop_assert_eq!(analytic, 0.0, "d/d{} of {}", name, expr);
+                    continue;
+                }
+                // points close to a pole, a branch point or a kink, and fast
+                // oscillations that alias at the first step, are retried with
+                // smaller steps until the stencil resolves them. A mismatch
+                // counts unless a smaller step shows that f is not smooth at
+                // the scale of the step, then the point is rejected like one
+                // that no step resolves
+                let mut verdict = None;
+                for step in [1e-7, 1e-9, 1e-11] {
+                    let h = step * (1.0 + point[i].abs());
+                    let truncation = match truncation_error(&expr, &point, i, h) {
+                        Ok(truncation) => truncation,
+                        Err(Unresolved::LeavesDomain | Unresolved::Kink | Unresolved::Flat) => {
+                            verdict = None;
+                            continue;
+                        }
+                    };
+                    let check = check_gradient(f, &point, step);
+                    let input = &check.inputs[i];
+                    // the point lies right on a branch point or pole such as
+                    // asin(1), the derivative does not exist there
+                    if input.analytic.is_infinite() {
+                        verdict = None;
+                        break;
+                    }
+                    // rounding in f(x ± h) limits how close the difference can get
+                    let roundoff = 2.0 * rounding_error(&expr, &point) / h;
+                    if input.passes(TOL) || input.abs_error <= roundoff + truncation {
+                        verdict = Some(Ok(()));
+                        break;
+                    }
+                    verdict = Some(Err(input.clone()));
+                }
+                prop_assume!(verdict.is_some());
+                prop_assert!(
+                    matches!(verdict, Some(Ok(()))),
+                    "d/d{} of {} at {:?}: {:?}", name, expr, point, verdict
+                );
+            }
+        }
+
+        #[test]
+        fn symbolic_derivative_matches_var(
+            expr in expr_strategy(),
+            point in prop::array::uniform3(coordinate()),
+        ) {
+            let largest = largest_intermediate(&expr, &point);
+            prop_assume!(largest.is_some());
+            let partials: Vec<_> = (0..SYMBOLS.len())
+                .map(|i| {
+                    let env: HashMap<String, Var<f64>> = SYMBOLS
+                        .iter()
+                        .zip(point)
+                        .enumerate()
+                        .map(|(j, (s, x))| {
+                            let dual = if i == j { Var::variable(x) } else { Var::constant(x) };
+                            (s.to_string(), dual)
+                        })
+                        .collect();
+                    let dual = expr.eval(&env).unwrap().deriv();
+                    let symbolic = eval(&expr.diff(SYMBOLS[i]), &point);
+                    (dual, symbolic)
+                })
+                .collect();
+            prop_assume!(partials.iter().all(|(d, s)| d.is_finite() && s.is_finite()));
+
+            // both sides round differently inside ill-conditioned calls, by up
+            // to the rounding error of the largest intermediate, or of an
+            // intermediate amplified by a steep call like asin next to ±1,
+            // relative to the derivative
+            let scale = largest
+                .unwrap()
+                .max(rounding_error(&expr, &point) / f64::EPSILON);
+            for (name, &(dual, symbolic)) in SYMBOLS.iter().zip(&partials) {
+                let check = InputCheck::new(symbolic, dual);
+                prop_assert!(
+                    check.passes(1e-9)
+                        || check.abs_error <= 1e-9 * scale
+                        || check.rel_error <= 1e-9 * scale,
+                    "d/d{} of {} at {:?}: {:?}", name, expr, point, check
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Derivatives of closures written against [`Var`].
+//!
+//! Each helper seeds the inputs itself and reruns the closure once per input
+//! direction, returning plain values and dense row-major matrices.
+use crate::{Scalar, Var};
+
+/// Inputs with the `i`-th one seeded as the differentiation variable.
+fn seed<T: Scalar>(x: &[T], i: usize) -> Vec<Var<T>> {
+    x.iter()
+        .enumerate()
+        .map(|(j, &xj)| {
+            if i == j {
+                Var::variable(xj)
+            } else {
+                Var::constant(xj)
+            }
+        })
+        .collect()
+}
+
+pub fn derivative<T: Scalar>(f: impl Fn(Var<T>) -> Var<T>, x: T) -> T {
+    f(Var::variable(x)).dx
+}
+
+/// Value of `f` at `x` together with its gradient. With no inputs `f` is
+/// still called once, on an empty slice, for the value.
+pub fn value_and_gradient<T: Scalar>(f: impl Fn(&[Var<T>]) -> Var<T>, x: &[T]) -> (T, Vec<T>) {
+    if x.is_empty() {
+        return (f(&[]).x, vec![]);
+    }
+
+    let mut value = T::zero();
+    let gradient = (0..x.len())
+        .map(|i| {
+            let y = f(&seed(x, i));
+            value = y.x;
+            y.dx
+        })
+        .collect();
+    (value, gradient)
+}
+
+/// Empty for no inputs, `f` is not called then.
+pub fn gradient<T: Scalar>(f: impl Fn(&[Var<T>]) -> Var<T>, x: &[T]) -> Vec<T> {
+    if x.is_empty() {
+        return vec![];
+    }
+    value_and_gradient(f, x).1
+}
+
+/// `jacobian[i][j]` is the derivative of the `i`-th output by the `j`-th input.
+/// With no inputs `f` is still called once, on an empty slice, to get the
+/// number of empty rows.
+pub fn jacobian<T: Scalar>(f: impl Fn(&[Var<T>]) -> Vec<Var<T>>, x: &[T]) -> Vec<Vec<T>> {
+    // one pass per input fills a column
+    let columns: Vec<Vec<T>> = (0..x.len())
+        .map(|j| f(&seed(x, j)).iter().map(|y| y.dx).collect())
+        .collect();
+    let n_outputs = match columns.first() {
+        Some(column) => column.len(),
+        None => f(&[]).len(),
+    };
+
+    (0..n_outputs)
+        .map(|i| columns.iter().map(|column| column[i]).collect())
+        .collect()
+}
+
+/// Dense Hessian of `f` using nested duals, constants inside `f` can be made
+/// with [`Scalar::from_f64`].
+#[allow(clippy::needless_range_loop)]
+pub fn hessian<T: Scalar>(f: impl Fn(&[Var<Var<T>>]) -> Var<Var<T>>, x: &[T]) -> Vec<Vec<T>> {
+    let n = x.len();
+    let mut hessian = vec![vec![T::zero(); n]; n];
+
+    // symmetric, only the upper triangle is evaluated
+    for i in 0..n {
+        for j in i..n {
+            let inputs: Vec<Var<Var<T>>> = x
+                .iter()
+                .enumerate()
+                .map(|(k, &xk)| {
+                    let inner = if k == i { T::one() } else { T::zero() };
+                    let outer = if k == j { T::one() } else { T::zero() };
+                    Var::new(Var::new(xk, inner), Var::constant(outer))
+                })
+                .collect();
+            let h = f(&inputs).dx.dx;
+            hessian[i][j] = h;
+            hessian[j][i] = h;
+        }
+    }
+
+    hessian
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn rosenbrock<T: Scalar>(x: &[T]) -> T {
+        let one = T::one();
+        let hundred = T::from_f64(100.0);
+        (one - x[0]) * (one - x[0]) + hundred * (x[1] - x[0] * x[0]) * (x[1] - x[0] * x[0])
+    }
+
+    #[test]
+    fn derivative_of_scalar_closure_ok() {
+        let d = derivative(|x: Var<f64>| x.powi(3) * 2.0 + x.sin(), 1.2);
+        assert_approx_eq!(d, 6.0 * 1.2 * 1.2 + 1.2_f64.cos(), 1e-12);
+    }
+
+    #[test]
+    fn gradient_matches_mixed_expression_partials() {
+        let expr = |v: &[Var<f64>]| {
+            let (x, a, c) = (v[0], v[1], v[2]);
+            ((a * x.powi(3) + a * c * x).powi(4) * (a * x).sin()) / (256.0 * a.powi(5) * x.powi(9))
+                - (c * x).cos()
+        };
+
+        let (value, grad) = value_and_gradient(expr, &[10.0, 2.0, 1.5]);
+        assert_approx_eq!(value, 2.652201219184028, 1e-12);
+        assert_approx_eq!(grad[0], 3.2126995936768443, 1e-12);
+        assert_approx_eq!(grad[1], 7.513185262318497, 1e-12);
+        assert_approx_eq!(grad[2], 6.577460206746546, 1e-12);
+    }
+
+    #[test]
+    fn jacobian_of_polar_to_cartesian_ok() {
+        let (r, theta) = (2.0_f64, 0.3_f64);
+        let j = jacobian(
+            |v: &[Var<f64>]| vec![v[0] * v[1].cos(), v[0] * v[1].sin(), v[0]],
+            &[r, theta],
+        );
+
+        let expected = [
+            [theta.cos(), -r * theta.sin()],
+            [theta.sin(), r * theta.cos()],
+            [1.0, 0.0],
+        ];
+        assert_eq!(j.len(), 3);
+        for (row, expected_row) in j.iter().zip(expected) {
+            assert_eq!(row.len(), 2);
+            for (value, expected) in row.iter().zip(expected_row) {
+                assert_approx_eq!(value, expected, 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn hessian_of_rosenbrock_ok() {
+        let (x, y) = (0.5_f64, -0.2_f64);
+        let h = hessian(rosenbrock, &[x, y]);
+        assert_approx_eq!(h[0][0], 2.0 - 400.0 * (y - x * x) + 800.0 * x * x, 1e-12);
+        assert_approx_eq!(h[0][1], -400.0 * x, 1e-12);
+        assert_approx_eq!(h[1][0], -400.0 * x, 1e-12);
+        assert_approx_eq!(h[1][1], 200.0, 1e-12);
+    }
+
+    #[test]
+    fn gradient_of_rosenbrock_vanishes_at_minimum() {
+        assert_eq!(gradient(rosenbrock, &[1.0_f64, 1.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn empty_inputs_give_empty_results() {
+        let never = |_: &[Var<f64>]| -> Var<f64> { unreachable!() };
+        assert!(gradient(never, &[]).is_empty());
+        let (value, gradient) = value_and_gradient(|_: &[Var<f64>]| Var::constant(1.0), &[]);
+        assert_eq!((value, gradient), (1.0, vec![]));
+        let rows = jacobian(|_: &[Var<f64>]| vec![Var::constant(1.0); 2], &[]);
+        assert_eq!(rows, vec![Vec::<f64>::new(); 2]);
+        assert!(hessian(|_: &[Var<Var<f64>>]| Var::from_f64(1.0), &[]).is_empty());
+    }
+}
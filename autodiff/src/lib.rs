@@ -1,13 +1,29 @@
 use std::ops;
 
+pub mod check;
+pub mod diff;
+pub mod multi;
+pub mod nn;
+pub mod ode;
+pub mod optim;
+pub mod parser;
+pub mod reverse;
+pub mod scalar;
+pub mod solve;
+pub mod symbolic;
+pub mod tensor;
+
+pub use diff::{derivative, gradient, hessian, jacobian};
+pub use scalar::Scalar;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub struct Var {
-    x: f32,
-    dx: f32,
+pub struct Var<T: Scalar = f32> {
+    x: T,
+    dx: T,
 }
 
-impl ops::Add for Var {
-    type Output = Var;
+impl<T: Scalar> ops::Add for Var<T> {
+    type Output = Var<T>;
     fn add(self, rhs: Self) -> Self::Output {
         Var {
             x: self.x + rhs.x,
@@ -16,8 +32,8 @@ impl ops::Add for Var {
     }
 }
 
-impl ops::Sub for Var {
-    type Output = Var;
+impl<T: Scalar> ops::Sub for Var<T> {
+    type Output = Var<T>;
     fn sub(self, rhs: Self) -> Self::Output {
         Var {
             x: self.x - rhs.x,
@@ -26,8 +42,8 @@ impl ops::Sub for Var {
     }
 }
 
-impl ops::Mul for Var {
-    type Output = Var;
+impl<T: Scalar> ops::Mul for Var<T> {
+    type Output = Var<T>;
     fn mul(self, rhs: Self) -> Self::Output {
         Var {
             x: self.x * rhs.x,
@@ -36,22 +52,104 @@ impl ops::Mul for Var {
     }
 }
 
-impl ops::Mul<f32> for Var {
-    type Output = Var;
-    fn mul(self, rhs: f32) -> Self::Output {
+impl<T: Scalar> ops::Neg for Var<T> {
+    type Output = Var<T>;
+    fn neg(self) -> Self::Output {
+        Var {
+            x: -self.x,
+            dx: -self.dx,
+        }
+    }
+}
+
+impl<T: Scalar> ops::Add<T> for Var<T> {
+    type Output = Var<T>;
+    fn add(self, rhs: T) -> Self::Output {
+        Var {x: self.x + rhs, dx: self.dx}
+    }
+}
+
+impl<T: Scalar> ops::Sub<T> for Var<T> {
+    type Output = Var<T>;
+    fn sub(self, rhs: T) -> Self::Output {
+        Var {x: self.x - rhs, dx: self.dx}
+    }
+}
+
+impl<T: Scalar> ops::Mul<T> for Var<T> {
+    type Output = Var<T>;
+    fn mul(self, rhs: T) -> Self::Output {
         Var {x: self.x * rhs, dx: self.dx * rhs}
     }
 }
 
-impl ops::Mul<Var> for f32 {
-    type Output = Var;
-    fn mul(self, rhs: Var) -> Self::Output {
-        Var {x: self* rhs.x, dx: self * rhs.dx}
+impl<T: Scalar> ops::Div<T> for Var<T> {
+    type Output = Var<T>;
+    fn div(self, rhs: T) -> Self::Output {
+        Var {x: self.x / rhs, dx: self.dx / rhs}
     }
 }
 
-impl ops::Div for Var {
-    type Output = Var;
+// scalar on the left can't be generic over T because of the orphan rule
+macro_rules! impl_scalar_ops_var {
+    ($t:ty) => {
+        impl ops::Add<Var<$t>> for $t {
+            type Output = Var<$t>;
+            fn add(self, rhs: Var<$t>) -> Self::Output {
+                Var {x: self + rhs.x, dx: rhs.dx}
+            }
+        }
+
+        impl ops::Sub<Var<$t>> for $t {
+            type Output = Var<$t>;
+            fn sub(self, rhs: Var<$t>) -> Self::Output {
+                Var {x: self - rhs.x, dx: -rhs.dx}
+            }
+        }
+
+        impl ops::Mul<Var<$t>> for $t {
+            type Output = Var<$t>;
+            fn mul(self, rhs: Var<$t>) -> Self::Output {
+                Var {x: self* rhs.x, dx: self * rhs.dx}
+            }
+        }
+
+        impl ops::Div<Var<$t>> for $t {
+            type Output = Var<$t>;
+            fn div(self, rhs: Var<$t>) -> Self::Output {
+                Var {x: self / rhs.x, dx: -self * rhs.dx / (rhs.x * rhs.x)}
+            }
+        }
+    };
+}
+
+impl_scalar_ops_var!(f32);
+impl_scalar_ops_var!(f64);
+
+// `a op= b` is `a = a op b` for both Var and scalar right hand sides
+macro_rules! impl_assign_op {
+    ($assign:ident, $assign_fn:ident, $op:tt) => {
+        impl<T: Scalar> ops::$assign for Var<T> {
+            fn $assign_fn(&mut self, rhs: Self) {
+                *self = *self $op rhs;
+            }
+        }
+
+        impl<T: Scalar> ops::$assign<T> for Var<T> {
+            fn $assign_fn(&mut self, rhs: T) {
+                *self = *self $op rhs;
+            }
+        }
+    };
+}
+
+impl_assign_op!(AddAssign, add_assign, +);
+impl_assign_op!(SubAssign, sub_assign, -);
+impl_assign_op!(MulAssign, mul_assign, *);
+impl_assign_op!(DivAssign, div_assign, /);
+
+impl<T: Scalar> ops::Div for Var<T> {
+    type Output = Var<T>;
     fn div(self, rhs: Self) -> Self::Output {
         Var {
             x: self.x / rhs.x,
@@ -60,11 +158,28 @@ impl ops::Div for Var {
     }
 }
 
-impl Var {
-    pub fn new(x: f32, dx: f32) -> Self {
+impl<T: Scalar> Var<T> {
+    pub fn new(x: T, dx: T) -> Self {
         Self { x, dx }
     }
 
+    /// Input we differentiate with respect to, seeded with `dx = 1`.
+    pub fn variable(x: T) -> Self {
+        Self::new(x, T::one())
+    }
+
+    pub fn constant(x: T) -> Self {
+        Self::new(x, T::zero())
+    }
+
+    pub fn value(&self) -> T {
+        self.x
+    }
+
+    pub fn deriv(&self) -> T {
+        self.dx
+    }
+
     pub fn sin(self) -> Self {
         Var {
             x: self.x.sin(),
@@ -93,14 +208,255 @@ impl Var {
         }
     }
     pub fn powi(self, n: i32) -> Self {
+        // x^-1 is infinite at x = 0, skip it when the result is constant
+        let dx = if n == 0 {
+            T::zero()
+        } else {
+            T::from_f64(n as f64) * self.x.powi(n - 1) * self.dx
+        };
         Var {
             x: self.x.powi(n),
-            dx: (n as f32) * self.x.powi(n - 1) * self.dx,
+            dx,
+        }
+    }
+    /// `self^n` where the exponent may carry a derivative as well.
+    pub fn powf(self, n: Self) -> Self {
+        let x = self.x.powf(n.x);
+        // x^n ln(x) is NaN for x <= 0, skip it when n is constant or where
+        // x^n is 0 and so is its limit
+        let dn = if n.dx == T::zero() || x == T::zero() {
+            T::zero()
+        } else {
+            x * self.x.ln() * n.dx
+        };
+        // x^(n - 1) is infinite at x = 0 for n < 1, skip it when x is constant
+        let dx = if self.dx == T::zero() {
+            T::zero()
+        } else {
+            n.x * self.x.powf(n.x - T::one()) * self.dx
+        };
+        Var { x, dx: dx + dn }
+    }
+    pub fn sqrt(self) -> Self {
+        let x = self.x.sqrt();
+        // keep constants finite at x = 0
+        let dx = if self.dx == T::zero() {
+            T::zero()
+        } else {
+            self.dx / (T::from_f64(2.0) * x)
+        };
+        Var { x, dx }
+    }
+    /// Uses the subgradient 0 at `x = 0`.
+    pub fn abs(self) -> Self {
+        Var {
+            x: self.x.abs(),
+            dx: T::from_f64(sign(self.x)) * self.dx,
+        }
+    }
+    pub fn tanh(self) -> Self {
+        let x = self.x.tanh();
+        Var {
+            x,
+            dx: (T::one() - x * x) * self.dx,
+        }
+    }
+    pub fn sigmoid(self) -> Self {
+        let x = self.x.sigmoid();
+        Var {
+            x,
+            dx: x * (T::one() - x) * self.dx,
+        }
+    }
+    pub fn sinh(self) -> Self {
+        Var {
+            x: self.x.sinh(),
+            dx: self.x.cosh() * self.dx,
+        }
+    }
+    pub fn cosh(self) -> Self {
+        Var {
+            x: self.x.cosh(),
+            dx: self.x.sinh() * self.dx,
+        }
+    }
+    pub fn asin(self) -> Self {
+        // keep constants finite at x = ±1
+        let dx = if self.dx == T::zero() {
+            T::zero()
+        } else {
+            self.dx / (T::one() - self.x * self.x).sqrt()
+        };
+        Var {
+            x: self.x.asin(),
+            dx,
+        }
+    }
+    pub fn acos(self) -> Self {
+        // keep constants finite at x = ±1
+        let dx = if self.dx == T::zero() {
+            T::zero()
+        } else {
+            -self.dx / (T::one() - self.x * self.x).sqrt()
+        };
+        Var {
+            x: self.x.acos(),
+            dx,
+        }
+    }
+    pub fn atan(self) -> Self {
+        Var {
+            x: self.x.atan(),
+            dx: self.dx / (T::one() + self.x * self.x),
+        }
+    }
+    /// Angle of the point `(x, self)`, uses the subgradient 0 at the origin.
+    pub fn atan2(self, x: Self) -> Self {
+        let r2 = self.x * self.x + x.x * x.x;
+        Var {
+            x: self.x.atan2(x.x),
+            dx: if r2 == T::zero() {
+                T::zero()
+            } else {
+                (x.x * self.dx - self.x * x.dx) / r2
+            },
+        }
+    }
+    pub fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+    /// At a tie the derivative is the average of both sides.
+    pub fn min(self, other: Self) -> Self {
+        select(self, other, |a, b| a < b)
+    }
+    /// At a tie the derivative is the average of both sides.
+    pub fn max(self, other: Self) -> Self {
+        select(self, other, |a, b| a > b)
+    }
+    /// Uses the subgradient 0 at the origin.
+    pub fn hypot(self, other: Self) -> Self {
+        let x = self.x.hypot(other.x);
+        Var {
+            x,
+            dx: if x == T::zero() {
+                T::zero()
+            } else {
+                (self.x * self.dx + other.x * other.dx) / x
+            },
         }
     }
 }
 
+fn sign<T: Scalar>(x: T) -> f64 {
+    let x = x.to_f64();
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else if x == 0.0 {
+        0.0
+    } else {
+        f64::NAN
+    }
+}
+
+// picks the operand `a_wins` prefers and averages the tangents at a tie, a NaN
+// operand loses like in `f64::min` and `f64::max`
+fn select<T: Scalar>(a: Var<T>, b: Var<T>, a_wins: fn(f64, f64) -> bool) -> Var<T> {
+    let (x, y) = (a.x.to_f64(), b.x.to_f64());
+    if y.is_nan() || a_wins(x, y) {
+        a
+    } else if x.is_nan() || a_wins(y, x) {
+        b
+    } else {
+        Var {
+            x: a.x,
+            dx: (a.dx + b.dx) / T::from_f64(2.0),
+        }
+    }
+}
+
+impl<T: Scalar> Scalar for Var<T> {
+    fn zero() -> Self {
+        Var::constant(T::zero())
+    }
+    fn one() -> Self {
+        Var::constant(T::one())
+    }
+    fn from_f64(x: f64) -> Self {
+        Var::constant(T::from_f64(x))
+    }
+    fn to_f64(self) -> f64 {
+        self.x.to_f64()
+    }
+
+    fn sin(self) -> Self {
+        Var::sin(self)
+    }
+    fn cos(self) -> Self {
+        Var::cos(self)
+    }
+    fn tan(self) -> Self {
+        Var::tan(self)
+    }
+    fn ln(self) -> Self {
+        Var::ln(self)
+    }
+    fn exp(self) -> Self {
+        Var::exp(self)
+    }
+    fn powi(self, n: i32) -> Self {
+        Var::powi(self, n)
+    }
+    fn powf(self, n: Self) -> Self {
+        Var::powf(self, n)
+    }
+    fn sqrt(self) -> Self {
+        Var::sqrt(self)
+    }
+    fn abs(self) -> Self {
+        Var::abs(self)
+    }
+    fn tanh(self) -> Self {
+        Var::tanh(self)
+    }
+    fn sinh(self) -> Self {
+        Var::sinh(self)
+    }
+    fn cosh(self) -> Self {
+        Var::cosh(self)
+    }
+    fn asin(self) -> Self {
+        Var::asin(self)
+    }
+    fn acos(self) -> Self {
+        Var::acos(self)
+    }
+    fn atan(self) -> Self {
+        Var::atan(self)
+    }
+    fn atan2(self, x: Self) -> Self {
+        Var::atan2(self, x)
+    }
+    fn log(self, base: Self) -> Self {
+        Var::log(self, base)
+    }
+    fn min(self, other: Self) -> Self {
+        Var::min(self, other)
+    }
+    fn max(self, other: Self) -> Self {
+        Var::max(self, other)
+    }
+    fn hypot(self, other: Self) -> Self {
+        Var::hypot(self, other)
+    }
+    fn sigmoid(self) -> Self {
+        Var::sigmoid(self)
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::excessive_precision)]
 mod tests {
     use super::*;
     use assert_approx_eq::assert_approx_eq;
@@ -136,6 +492,187 @@ mod tests {
         //for df/da 7.513185262318497
         //for df/dc 6.577460206746546
     }
-} 
 
+    #[test]
+    fn mixed_expression_partials_exact_in_f64() {
+        let expr = |x: Var<f64>, a: Var<f64>, c: Var<f64>| {
+            ((a * x.powi(3) + a * c * x).powi(4) * (a * x).sin()) / (256.0 * a.powi(5) * x.powi(9))
+                - (c * x).cos()
+        };
+        let (x, a, c) = (10.0, 2.0, 1.5);
+
+        let df_dx = expr(Var::variable(x), Var::constant(a), Var::constant(c));
+        let df_da = expr(Var::constant(x), Var::variable(a), Var::constant(c));
+        let df_dc = expr(Var::constant(x), Var::constant(a), Var::variable(c));
+        assert_approx_eq!(df_dx.x, 2.652201219184028, 1e-12);
+        assert_approx_eq!(df_dx.dx, 3.2126995936768443, 1e-12);
+        assert_approx_eq!(df_da.dx, 7.513185262318497, 1e-12);
+        assert_approx_eq!(df_dc.dx, 6.577460206746546, 1e-12);
+    }
+
+    #[test]
+    fn nested_var_gives_second_derivative() {
+        // f(x) = x^3 sin(x), f''(x) = 6x sin(x) + 6x^2 cos(x) - x^3 sin(x)
+        let x0: f64 = 1.3;
+        let x = Var::new(Var::variable(x0), Var::constant(1.0));
+        let f = x.powi(3) * x.sin();
+
+        let expected = 6.0 * x0 * x0.sin() + 6.0 * x0 * x0 * x0.cos() - x0.powi(3) * x0.sin();
+        assert_eq!(f.x.x, x0.powi(3) * x0.sin());
+        assert_approx_eq!(f.dx.dx, expected, 1e-12);
+    }
+
+    #[test]
+    fn nested_var_gives_hessian_vector_product() {
+        // f(x, y) = x^2 y + exp(x y), H v for v = (1, 2)
+        let f = |x: Var<Var<f64>>, y: Var<Var<f64>>| x * x * y + (x * y).exp();
+        let (x0, y0, v) = (0.5_f64, -1.5_f64, [1.0, 2.0]);
+        let e = (x0 * y0).exp();
+        let hessian = [
+            [2.0 * y0 + y0 * y0 * e, 2.0 * x0 + e + x0 * y0 * e],
+            [2.0 * x0 + e + x0 * y0 * e, x0 * x0 * e],
+        ];
+
+        for (i, row) in hessian.iter().enumerate() {
+            // inner tangent follows v, outer tangent picks the gradient component
+            let x = Var::new(Var::new(x0, v[0]), Var::constant(if i == 0 { 1.0 } else { 0.0 }));
+            let y = Var::new(Var::new(y0, v[1]), Var::constant(if i == 1 { 1.0 } else { 0.0 }));
+            let hv = f(x, y).dx.dx;
+            assert_approx_eq!(hv, row[0] * v[0] + row[1] * v[1], 1e-12);
+        }
+    }
+
+    #[test]
+    fn scalar_on_the_left_works_for_f32_and_f64() {
+        assert_eq!(2.0_f32 * Var::new(3.0_f32, 1.0), Var::new(6.0, 2.0));
+        assert_eq!(2.0_f64 * Var::new(3.0_f64, 1.0), Var::new(6.0, 2.0));
+        assert_eq!(Var::new(3.0_f64, 1.0) * 2.0, Var::new(6.0, 2.0));
+    }
+
+    fn derivative(f: impl Fn(Var<f64>) -> Var<f64>, x: f64) -> f64 {
+        f(Var::variable(x)).dx
+    }
+
+    #[test]
+    fn unary_functions_have_correct_derivatives() {
+        let x = 0.4;
+        type Unary = fn(Var<f64>) -> Var<f64>;
+        let cases: [(Unary, f64); 10] = [
+            (Var::sqrt, 0.5 / x.sqrt()),
+            (Var::abs, 1.0),
+            (Var::tanh, 1.0 - x.tanh().powi(2)),
+            (Var::sigmoid, (-x).exp() / (1.0 + (-x).exp()).powi(2)),
+            (Var::sinh, x.cosh()),
+            (Var::cosh, x.sinh()),
+            (Var::asin, 1.0 / (1.0 - x * x).sqrt()),
+            (Var::acos, -1.0 / (1.0 - x * x).sqrt()),
+            (Var::atan, 1.0 / (1.0 + x * x)),
+            (|v| -v, -1.0),
+        ];
+        for (f, expected) in cases {
+            assert_approx_eq!(derivative(f, x), expected, 1e-12);
+        }
+        assert_approx_eq!(derivative(Var::abs, -x), -1.0, 1e-12);
+    }
+
+    #[test]
+    fn binary_functions_have_correct_partials() {
+        let (a, b) = (1.5_f64, 0.8_f64);
+        let partials = |f: fn(Var<f64>, Var<f64>) -> Var<f64>| {
+            (
+                f(Var::variable(a), Var::constant(b)).dx,
+                f(Var::constant(a), Var::variable(b)).dx,
+            )
+        };
+
+        let (da, db) = partials(Var::powf);
+        assert_approx_eq!(da, b * a.powf(b - 1.0), 1e-12);
+        assert_approx_eq!(db, a.powf(b) * a.ln(), 1e-12);
+
+        let (da, db) = partials(Var::atan2);
+        assert_approx_eq!(da, b / (a * a + b * b), 1e-12);
+        assert_approx_eq!(db, -a / (a * a + b * b), 1e-12);
+
+        let (da, db) = partials(Var::log);
+        assert_approx_eq!(da, 1.0 / (a * b.ln()), 1e-12);
+        assert_approx_eq!(db, -a.ln() / (b * b.ln().powi(2)), 1e-12);
+
+        let (da, db) = partials(Var::hypot);
+        assert_approx_eq!(da, a / a.hypot(b), 1e-12);
+        assert_approx_eq!(db, b / a.hypot(b), 1e-12);
 
+        assert_eq!(partials(Var::min), (0.0, 1.0));
+        assert_eq!(partials(Var::max), (1.0, 0.0));
+    }
+
+    #[test]
+    fn non_differentiable_points_use_subgradients() {
+        assert_eq!(derivative(Var::abs, 0.0), 0.0);
+        assert_eq!(Var::sqrt(Var::constant(0.0_f64)), Var::constant(0.0));
+
+        let tie = Var::new(2.0_f64, 1.0).min(Var::new(2.0, 0.0));
+        assert_eq!(tie, Var::new(2.0, 0.5));
+        let tie = Var::new(2.0_f64, 1.0).max(Var::new(2.0, 0.0));
+        assert_eq!(tie, Var::new(2.0, 0.5));
+
+        assert_eq!(Var::variable(0.0_f64).powi(0), Var::constant(1.0));
+        let zero = Var::constant(0.0_f64);
+        assert_eq!(zero.powf(Var::constant(0.5)), zero);
+        assert_eq!(zero.powf(Var::variable(0.5)), zero);
+        assert_eq!(
+            Var::constant(1.0_f64).asin(),
+            Var::new(std::f64::consts::FRAC_PI_2, 0.0)
+        );
+        assert_eq!(
+            Var::constant(-1.0_f64).acos(),
+            Var::new(std::f64::consts::PI, 0.0)
+        );
+
+        let origin = Var::variable(0.0_f64);
+        assert_eq!(origin.atan2(Var::constant(0.0)).dx, 0.0);
+        assert_eq!(origin.hypot(Var::constant(0.0)).dx, 0.0);
+    }
+
+    #[test]
+    fn nan_operands_lose_min_and_max() {
+        // like f64::min and f64::max, the other operand and its tangent win
+        let nan = Var::new(f64::NAN, 0.0);
+        assert_eq!(Var::new(1.0, 1.0).min(nan), Var::new(1.0, 1.0));
+        assert_eq!(nan.max(Var::new(1.0, 1.0)), Var::new(1.0, 1.0));
+        assert!(Var::new(f64::NAN, 1.0).abs().dx.is_nan());
+    }
+
+    #[test]
+    fn mixed_scalar_operators_ok() {
+        let x = Var::new(2.0_f64, 1.0);
+        assert_eq!(x + 1.0, Var::new(3.0, 1.0));
+        assert_eq!(1.0 + x, Var::new(3.0, 1.0));
+        assert_eq!(x - 1.0, Var::new(1.0, 1.0));
+        assert_eq!(1.0 - x, Var::new(-1.0, -1.0));
+        assert_eq!(x / 4.0, Var::new(0.5, 0.25));
+        assert_eq!(4.0 / x, Var::new(2.0, -1.0));
+        assert_eq!(1.0_f32 / Var::new(2.0_f32, 1.0), Var::new(0.5, -0.25));
+    }
+
+    #[test]
+    fn compound_assign_operators_ok() {
+        let mut x = Var::new(2.0_f64, 1.0);
+        x += Var::new(1.0, 1.0);
+        assert_eq!(x, Var::new(3.0, 2.0));
+        x -= 1.0;
+        assert_eq!(x, Var::new(2.0, 2.0));
+        x *= Var::new(3.0, 0.0);
+        assert_eq!(x, Var::new(6.0, 6.0));
+        x /= 2.0;
+        assert_eq!(x, Var::new(3.0, 3.0));
+    }
+
+    #[test]
+    fn nested_var_supports_new_functions() {
+        // d2/dx2 tanh(x) = -2 tanh(x) (1 - tanh(x)^2)
+        let x0: f64 = 0.3;
+        let x = Var::new(Var::variable(x0), Var::constant(1.0));
+        let t = x0.tanh();
+        assert_approx_eq!(x.tanh().dx.dx, -2.0 * t * (1.0 - t * t), 1e-12);
+    }
+}
@@ -1,3 +1,125 @@
-fn main() {
-    println!("yo");
+use autodiff::parser::{parse, ParseError};
+use autodiff::symbolic::Expr;
+use autodiff::Var;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+enter a formula like `x*a/(x^2+b)` to print its value and derivatives,
+bind symbols with `x = 24`, list bindings with `:vars`, quit with `:q`";
+
+// points at the error position below the echoed source, the position is a
+// byte offset so the caret is indented by the characters before it
+fn describe(src: &str, error: &ParseError) -> String {
+    let column = src[..error.position].chars().count();
+    format!("  {src}\n  {}^\nerror {error}", " ".repeat(column))
+}
+
+fn evaluate(expr: &Expr, values: &HashMap<String, f64>) -> Result<String, String> {
+    let value = expr.eval(values).map_err(|e| e.to_string())?;
+    let mut report = format!("f = {value}");
+
+    for name in expr.symbols() {
+        // seed one symbol at a time, the others are constants
+        let duals: HashMap<String, Var<f64>> = values
+            .iter()
+            .map(|(k, &v)| {
+                let dual = if *k == name {
+                    Var::variable(v)
+                } else {
+                    Var::constant(v)
+                };
+                (k.clone(), dual)
+            })
+            .collect();
+        let dual = expr.eval(&duals).map_err(|e| e.to_string())?;
+        report += &format!("\ndf/d{name} = {}    [{}]", dual.deriv(), expr.diff(&name));
+    }
+
+    Ok(report)
+}
+
+fn run_line(line: &str, values: &mut HashMap<String, f64>) -> Result<String, String> {
+    if let Some((name, src)) = line.split_once('=') {
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("`{name}` is not a valid symbol name"));
+        }
+        let expr = parse(src.trim()).map_err(|e| describe(src.trim(), &e))?;
+        let value = expr.eval(values).map_err(|e| e.to_string())?;
+        values.insert(name.to_string(), value);
+        return Ok(format!("{name} = {value}"));
+    }
+
+    let expr = parse(line).map_err(|e| describe(line, &e))?;
+    evaluate(&expr, values)
+}
+
+fn repl() -> io::Result<()> {
+    let mut values: HashMap<String, f64> = HashMap::new();
+    let stdin = io::stdin();
+    println!("{HELP}");
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => continue,
+            ":q" | ":quit" => return Ok(()),
+            ":help" => println!("{HELP}"),
+            ":vars" => {
+                let mut names: Vec<_> = values.iter().collect();
+                names.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, value) in names {
+                    println!("{name} = {value}");
+                }
+            }
+            line => match run_line(line, &mut values) {
+                Ok(output) => println!("{output}"),
+                Err(error) => println!("{error}"),
+            },
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    // one-shot mode: autodiff "x*a/(x^2+b)" x=24 a=22 b=2
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((formula, bindings)) = args.split_first() else {
+        return repl();
+    };
+
+    let mut values = HashMap::new();
+    for line in bindings.iter().chain([formula]) {
+        match run_line(line, &mut values) {
+            Ok(output) if line == formula => println!("{output}"),
+            Ok(_) => {}
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_counts_characters() {
+        let src = "αβ + )";
+        let description = describe(src, &parse(src).unwrap_err());
+        assert!(
+            description.starts_with("  αβ + )\n       ^\n"),
+            "{description}"
+        );
+    }
 }
@@ -0,0 +1,508 @@
+//! Forward-mode dual numbers carrying a whole tangent vector.
+//!
+//! [`MultiVar`] behaves like [`crate::Var`] but propagates `N` directional
+//! derivatives at once, so seeding each input with its own unit direction
+//! yields the full gradient from a single evaluation. Like `Var` it is
+//! generic over the [`crate::Scalar`] it is built on and is one itself.
+use std::ops;
+
+use crate::Scalar;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MultiVar<const N: usize, T: Scalar = f32> {
+    x: T,
+    dx: [T; N],
+}
+
+// df * d, zero along directions that do not move so that an infinite f' at a
+// pole or branch point leaves them finite, like Var skips constant terms
+fn scale<T: Scalar>(df: T, d: T) -> T {
+    if d == T::zero() {
+        T::zero()
+    } else {
+        df * d
+    }
+}
+
+impl<const N: usize, T: Scalar> MultiVar<N, T> {
+    pub fn new(x: T, dx: [T; N]) -> Self {
+        Self { x, dx }
+    }
+
+    /// Input seeded with the `i`-th unit direction.
+    pub fn variable(x: T, i: usize) -> Self {
+        let mut dx = [T::zero(); N];
+        dx[i] = T::one();
+        Self { x, dx }
+    }
+
+    /// Input that does not vary in any direction.
+    pub fn constant(x: T) -> Self {
+        Self {
+            x,
+            dx: [T::zero(); N],
+        }
+    }
+
+    pub fn value(&self) -> T {
+        self.x
+    }
+
+    pub fn gradient(&self) -> [T; N] {
+        self.dx
+    }
+
+    // applies the chain rule for f(self) with f' evaluated at self.x
+    fn chain(self, x: T, df: T) -> Self {
+        MultiVar {
+            x,
+            dx: self.dx.map(|d| scale(df, d)),
+        }
+    }
+
+    // applies the chain rule for f(self, rhs) with partials df/dself, df/drhs
+    fn chain2(self, rhs: Self, x: T, dfa: T, dfb: T) -> Self {
+        let mut dx = [T::zero(); N];
+        for (i, d) in dx.iter_mut().enumerate() {
+            *d = scale(dfa, self.dx[i]) + scale(dfb, rhs.dx[i]);
+        }
+        MultiVar { x, dx }
+    }
+
+    pub fn sin(self) -> Self {
+        self.chain(self.x.sin(), self.x.cos())
+    }
+    pub fn cos(self) -> Self {
+        self.chain(self.x.cos(), -self.x.sin())
+    }
+    pub fn tan(self) -> Self {
+        self.sin() / self.cos()
+    }
+    pub fn ln(self) -> Self {
+        self.chain(self.x.ln(), T::one() / self.x)
+    }
+    pub fn exp(self) -> Self {
+        self.chain(self.x.exp(), self.x.exp())
+    }
+    pub fn powi(self, n: i32) -> Self {
+        // x^-1 is infinite at x = 0, skip it when the result is constant
+        let df = if n == 0 {
+            T::zero()
+        } else {
+            T::from_f64(n as f64) * self.x.powi(n - 1)
+        };
+        self.chain(self.x.powi(n), df)
+    }
+    /// `self^n` where the exponent may carry a gradient as well.
+    pub fn powf(self, n: Self) -> Self {
+        let x = self.x.powf(n.x);
+        // x^n ln(x) is NaN for x <= 0, skip it where x^n is 0 and so is its
+        // limit
+        let dn = if x == T::zero() {
+            T::zero()
+        } else {
+            x * self.x.ln()
+        };
+        self.chain2(n, x, n.x * self.x.powf(n.x - T::one()), dn)
+    }
+    pub fn sqrt(self) -> Self {
+        let x = self.x.sqrt();
+        self.chain(x, T::one() / (T::from_f64(2.0) * x))
+    }
+    /// Uses the subgradient 0 at `x = 0`.
+    pub fn abs(self) -> Self {
+        self.chain(self.x.abs(), T::from_f64(crate::sign(self.x)))
+    }
+    pub fn tanh(self) -> Self {
+        let x = self.x.tanh();
+        self.chain(x, T::one() - x * x)
+    }
+    pub fn sigmoid(self) -> Self {
+        let x = self.x.sigmoid();
+        self.chain(x, x * (T::one() - x))
+    }
+    pub fn sinh(self) -> Self {
+        self.chain(self.x.sinh(), self.x.cosh())
+    }
+    pub fn cosh(self) -> Self {
+        self.chain(self.x.cosh(), self.x.sinh())
+    }
+    pub fn asin(self) -> Self {
+        self.chain(
+            self.x.asin(),
+            T::one() / (T::one() - self.x * self.x).sqrt(),
+        )
+    }
+    pub fn acos(self) -> Self {
+        self.chain(
+            self.x.acos(),
+            -T::one() / (T::one() - self.x * self.x).sqrt(),
+        )
+    }
+    pub fn atan(self) -> Self {
+        self.chain(self.x.atan(), T::one() / (T::one() + self.x * self.x))
+    }
+    /// Angle of the point `(x, self)`, uses the subgradient 0 at the origin.
+    pub fn atan2(self, x: Self) -> Self {
+        let r2 = self.x * self.x + x.x * x.x;
+        let (dfa, dfb) = if r2 == T::zero() {
+            (T::zero(), T::zero())
+        } else {
+            (x.x / r2, -self.x / r2)
+        };
+        self.chain2(x, self.x.atan2(x.x), dfa, dfb)
+    }
+    pub fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+    /// At a tie the gradient is the average of both sides.
+    pub fn min(self, other: Self) -> Self {
+        self.select(other, |a, b| a < b)
+    }
+    /// At a tie the gradient is the average of both sides.
+    pub fn max(self, other: Self) -> Self {
+        self.select(other, |a, b| a > b)
+    }
+    /// Uses the subgradient 0 at the origin.
+    pub fn hypot(self, other: Self) -> Self {
+        let x = self.x.hypot(other.x);
+        let (dfa, dfb) = if x == T::zero() {
+            (T::zero(), T::zero())
+        } else {
+            (self.x / x, other.x / x)
+        };
+        self.chain2(other, x, dfa, dfb)
+    }
+
+    // picks the operand `a_wins` prefers and averages the gradients at a tie,
+    // a NaN operand loses like in `f64::min` and `f64::max`
+    fn select(self, other: Self, a_wins: fn(f64, f64) -> bool) -> Self {
+        let (x, y) = (self.x.to_f64(), other.x.to_f64());
+        if y.is_nan() || a_wins(x, y) {
+            self
+        } else if x.is_nan() || a_wins(y, x) {
+            other
+        } else {
+            let half = T::from_f64(0.5);
+            self.chain2(other, self.x, half, half)
+        }
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Add for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn add(self, rhs: Self) -> Self::Output {
+        self.chain2(rhs, self.x + rhs.x, T::one(), T::one())
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Sub for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.chain2(rhs, self.x - rhs.x, T::one(), -T::one())
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Mul for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.chain2(rhs, self.x * rhs.x, rhs.x, self.x)
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Div for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn div(self, rhs: Self) -> Self::Output {
+        self.chain2(
+            rhs,
+            self.x / rhs.x,
+            T::one() / rhs.x,
+            -self.x / (rhs.x * rhs.x),
+        )
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Neg for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn neg(self) -> Self::Output {
+        self.chain(-self.x, -T::one())
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Add<T> for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn add(self, rhs: T) -> Self::Output {
+        MultiVar {
+            x: self.x + rhs,
+            dx: self.dx,
+        }
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Sub<T> for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn sub(self, rhs: T) -> Self::Output {
+        MultiVar {
+            x: self.x - rhs,
+            dx: self.dx,
+        }
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Mul<T> for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn mul(self, rhs: T) -> Self::Output {
+        self.chain(self.x * rhs, rhs)
+    }
+}
+
+impl<const N: usize, T: Scalar> ops::Div<T> for MultiVar<N, T> {
+    type Output = MultiVar<N, T>;
+    fn div(self, rhs: T) -> Self::Output {
+        self.chain(self.x / rhs, T::one() / rhs)
+    }
+}
+
+// scalar on the left can't be generic over T because of the orphan rule
+macro_rules! impl_scalar_ops_multi_var {
+    ($t:ty) => {
+        impl<const N: usize> ops::Add<MultiVar<N, $t>> for $t {
+            type Output = MultiVar<N, $t>;
+            fn add(self, rhs: MultiVar<N, $t>) -> Self::Output {
+                rhs + self
+            }
+        }
+
+        impl<const N: usize> ops::Sub<MultiVar<N, $t>> for $t {
+            type Output = MultiVar<N, $t>;
+            fn sub(self, rhs: MultiVar<N, $t>) -> Self::Output {
+                -rhs + self
+            }
+        }
+
+        impl<const N: usize> ops::Mul<MultiVar<N, $t>> for $t {
+            type Output = MultiVar<N, $t>;
+            fn mul(self, rhs: MultiVar<N, $t>) -> Self::Output {
+                rhs * self
+            }
+        }
+
+        impl<const N: usize> ops::Div<MultiVar<N, $t>> for $t {
+            type Output = MultiVar<N, $t>;
+            fn div(self, rhs: MultiVar<N, $t>) -> Self::Output {
+                rhs.chain(self / rhs.x, -self / (rhs.x * rhs.x))
+            }
+        }
+    };
+}
+
+impl_scalar_ops_multi_var!(f32);
+impl_scalar_ops_multi_var!(f64);
+
+// `a op= b` is `a = a op b` for both MultiVar and scalar right hand sides
+macro_rules! impl_assign_op {
+    ($assign:ident, $assign_fn:ident, $op:tt) => {
+        impl<const N: usize, T: Scalar> ops::$assign for MultiVar<N, T> {
+            fn $assign_fn(&mut self, rhs: Self) {
+                *self = *self $op rhs;
+            }
+        }
+
+        impl<const N: usize, T: Scalar> ops::$assign<T> for MultiVar<N, T> {
+            fn $assign_fn(&mut self, rhs: T) {
+                *self = *self $op rhs;
+            }
+        }
+    };
+}
+
+impl_assign_op!(AddAssign, add_assign, +);
+impl_assign_op!(SubAssign, sub_assign, -);
+impl_assign_op!(MulAssign, mul_assign, *);
+impl_assign_op!(DivAssign, div_assign, /);
+
+impl<const N: usize, T: Scalar> Scalar for MultiVar<N, T> {
+    fn zero() -> Self {
+        MultiVar::constant(T::zero())
+    }
+    fn one() -> Self {
+        MultiVar::constant(T::one())
+    }
+    fn from_f64(x: f64) -> Self {
+        MultiVar::constant(T::from_f64(x))
+    }
+    fn to_f64(self) -> f64 {
+        self.x.to_f64()
+    }
+
+    fn sin(self) -> Self {
+        MultiVar::sin(self)
+    }
+    fn cos(self) -> Self {
+        MultiVar::cos(self)
+    }
+    fn tan(self) -> Self {
+        MultiVar::tan(self)
+    }
+    fn ln(self) -> Self {
+        MultiVar::ln(self)
+    }
+    fn exp(self) -> Self {
+        MultiVar::exp(self)
+    }
+    fn powi(self, n: i32) -> Self {
+        MultiVar::powi(self, n)
+    }
+    fn powf(self, n: Self) -> Self {
+        MultiVar::powf(self, n)
+    }
+    fn sqrt(self) -> Self {
+        MultiVar::sqrt(self)
+    }
+    fn abs(self) -> Self {
+        MultiVar::abs(self)
+    }
+    fn tanh(self) -> Self {
+        MultiVar::tanh(self)
+    }
+    fn sinh(self) -> Self {
+        MultiVar::sinh(self)
+    }
+    fn cosh(self) -> Self {
+        MultiVar::cosh(self)
+    }
+    fn asin(self) -> Self {
+        MultiVar::asin(self)
+    }
+    fn acos(self) -> Self {
+        MultiVar::acos(self)
+    }
+    fn atan(self) -> Self {
+        MultiVar::atan(self)
+    }
+    fn atan2(self, x: Self) -> Self {
+        MultiVar::atan2(self, x)
+    }
+    fn log(self, base: Self) -> Self {
+        MultiVar::log(self, base)
+    }
+    fn min(self, other: Self) -> Self {
+        MultiVar::min(self, other)
+    }
+    fn max(self, other: Self) -> Self {
+        MultiVar::max(self, other)
+    }
+    fn hypot(self, other: Self) -> Self {
+        MultiVar::hypot(self, other)
+    }
+    fn sigmoid(self) -> Self {
+        MultiVar::sigmoid(self)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::excessive_precision)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn seeded_variables_are_unit_directions() {
+        let x = MultiVar::<3>::variable(2.0, 1);
+        assert_eq!(x, MultiVar::new(2.0, [0.0, 1.0, 0.0]));
+        assert_eq!(MultiVar::<3>::constant(2.0).gradient(), [0.0; 3]);
+    }
+
+    #[test]
+    fn algebraic_expression_gradient_ok() {
+        let x = MultiVar::<3>::variable(24.0, 0);
+        let a = MultiVar::<3>::variable(22.0, 1);
+        let b = MultiVar::<3>::variable(2.0, 2);
+        let result = x * a / (x * x + b);
+        assert_approx_eq!(result.x, 0.9134948096885813);
+        assert_approx_eq!(result.dx[0], -0.03779887692915554);
+        assert_approx_eq!(result.dx[1], 24.0 / 578.0);
+        assert_approx_eq!(result.dx[2], -24.0 * 22.0 / (578.0 * 578.0));
+    }
+
+    #[test]
+    fn mixed_expression_gradient_in_one_pass_ok() {
+        let x = MultiVar::<3>::variable(10.0, 0);
+        let a = MultiVar::<3>::variable(2.0, 1);
+        let c = MultiVar::<3>::variable(1.5, 2);
+        let expr = |x: MultiVar<3>, a: MultiVar<3>, c: MultiVar<3>| {
+            ((a * x.powi(3) + a * c * x).powi(4) * (a * x).sin()) / (256.0 * a.powi(5) * x.powi(9))
+                - (c * x).cos()
+        };
+
+        let result = expr(x, a, c);
+        assert_approx_eq!(result.x, 2.652201219184028);
+        assert_approx_eq!(result.dx[0], 3.2126995936768443, 1e-4);
+        assert_approx_eq!(result.dx[1], 7.513185262318497, 1e-4);
+        assert_approx_eq!(result.dx[2], 6.577460206746546, 1e-4);
+    }
+
+    #[test]
+    fn elementary_functions_match_single_direction_var() {
+        let x = MultiVar::<2>::new(0.7, [1.0, -2.0]);
+        let y = x.sin() + x.cos() + x.tan() + x.ln() + x.exp() + x.powi(3) - x * 2.0;
+        let forward = |dx: f32| {
+            let x = crate::Var::new(0.7, dx);
+            x.sin() + x.cos() + x.tan() + x.ln() + x.exp() + x.powi(3) - x * 2.0
+        };
+
+        assert_approx_eq!(y.x, forward(1.0).x);
+        assert_approx_eq!(y.dx[0], forward(1.0).dx);
+        assert_approx_eq!(y.dx[1], forward(-2.0).dx);
+    }
+
+    #[test]
+    fn every_function_matches_var_in_each_direction() {
+        fn all<S: Scalar + ops::Add<f64, Output = S> + ops::Mul<f64, Output = S>>(x: S, y: S) -> S {
+            let unit = x * 0.3;
+            (x.sqrt() + x.abs() + x.tanh() + x.sigmoid() + x.sinh() + x.cosh())
+                * (unit.asin() + unit.acos() + unit.atan() + x.atan2(y) + x.log(y))
+                + x.powf(y)
+                + x.min(y)
+                + x.max(y)
+                + x.hypot(y)
+                + (-x) / y
+                + 2.0
+        }
+        let (x0, y0) = (0.7, 1.9);
+        let m = all(
+            MultiVar::<2, f64>::variable(x0, 0),
+            MultiVar::variable(y0, 1),
+        );
+        let dx = all(crate::Var::variable(x0), crate::Var::constant(y0));
+        let dy = all(crate::Var::constant(x0), crate::Var::variable(y0));
+
+        assert_approx_eq!(m.value(), dx.value(), 1e-12);
+        assert_approx_eq!(m.gradient()[0], dx.deriv(), 1e-12);
+        assert_approx_eq!(m.gradient()[1], dy.deriv(), 1e-12);
+    }
+
+    #[test]
+    fn scalar_operators_and_assignment() {
+        let x = MultiVar::<2, f64>::new(3.0, [1.0, 2.0]);
+        assert_eq!(2.0 - x, MultiVar::new(-1.0, [-1.0, -2.0]));
+        assert_eq!(x + 1.0, 1.0 + x);
+        assert_eq!(x * 2.0, 2.0 * x);
+        assert_eq!(x / 2.0, MultiVar::new(1.5, [0.5, 1.0]));
+        assert_eq!(6.0 / x, MultiVar::new(2.0, [-6.0 / 9.0, -12.0 / 9.0]));
+
+        let mut y = x;
+        y *= x;
+        y -= 1.0;
+        assert_eq!(y, MultiVar::new(8.0, [6.0, 12.0]));
+    }
+
+    #[test]
+    fn constant_directions_stay_finite() {
+        let x = MultiVar::<2, f64>::new(0.0, [1.0, 0.0]);
+        let root = x.sqrt();
+        assert_eq!(root.gradient(), [f64::INFINITY, 0.0]);
+        let zero = MultiVar::<2, f64>::constant(0.0);
+        assert_eq!(zero.hypot(zero).gradient(), [0.0; 2]);
+    }
+}
@@ -0,0 +1,454 @@
+//! Fully connected classifiers trained with [`crate::tensor`].
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::tensor::{Matrix, Tape, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    Relu,
+    Tanh,
+    Sigmoid,
+}
+
+impl Activation {
+    fn apply(self, x: Tensor) -> Tensor {
+        match self {
+            Activation::Relu => x.relu(),
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => x.sigmoid(),
+        }
+    }
+}
+
+/// Affine layer `x * weight + bias` for a batch `x` with one sample per row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linear {
+    /// inputs×outputs
+    pub weight: Matrix,
+    /// 1×outputs
+    pub bias: Matrix,
+}
+
+impl Linear {
+    /// Glorot-uniform weights and zero bias.
+    pub fn new(inputs: usize, outputs: usize, rng: &mut impl Rng) -> Self {
+        let limit = (6.0 / (inputs + outputs) as f32).sqrt();
+        let weight = (0..inputs * outputs)
+            .map(|_| rng.gen_range(-limit..limit))
+            .collect();
+        Self {
+            weight: Matrix::new(inputs, outputs, weight),
+            bias: Matrix::zeros(1, outputs),
+        }
+    }
+}
+
+/// Multi-layer perceptron that outputs one logit per class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mlp {
+    pub layers: Vec<Linear>,
+    pub activation: Activation,
+}
+
+impl Mlp {
+    /// Network with layer widths `sizes`, from the number of features to the
+    /// number of classes. The same `seed` gives the same initial weights.
+    pub fn new(sizes: &[usize], activation: Activation, seed: u64) -> Self {
+        assert!(
+            sizes.len() >= 2,
+            "need at least an input and an output size"
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let layers = sizes
+            .windows(2)
+            .map(|w| Linear::new(w[0], w[1], &mut rng))
+            .collect();
+        Self { layers, activation }
+    }
+
+    // weight and bias of every layer in order
+    fn parameters<'t>(&self, tape: &'t Tape) -> Vec<Tensor<'t>> {
+        self.layers
+            .iter()
+            .flat_map(|layer| [tape.var(layer.weight.clone()), tape.var(layer.bias.clone())])
+            .collect()
+    }
+
+    fn logits<'t>(&self, tape: &'t Tape, parameters: &[Tensor<'t>], inputs: &Matrix) -> Tensor<'t> {
+        let layers = parameters.len() / 2;
+        parameters
+            .chunks(2)
+            .enumerate()
+            .fold(tape.var(inputs.clone()), |x, (i, layer)| {
+                let z = x.matmul(layer[0]) + layer[1];
+                if i + 1 == layers {
+                    z
+                } else {
+                    self.activation.apply(z)
+                }
+            })
+    }
+
+    /// Class probabilities for every row of `inputs`.
+    pub fn predict(&self, inputs: &Matrix) -> Matrix {
+        let tape = Tape::new();
+        let parameters = self.parameters(&tape);
+        self.logits(&tape, &parameters, inputs).softmax().value()
+    }
+
+    /// Fraction of rows whose most likely class is the label.
+    pub fn accuracy(&self, data: &Dataset) -> f32 {
+        let predicted = self.predict(&data.inputs).argmax_rows();
+        let correct = predicted
+            .iter()
+            .zip(&data.labels)
+            .filter(|(p, l)| p == l)
+            .count();
+        correct as f32 / data.labels.len() as f32
+    }
+
+    /// One gradient descent step on the cross-entropy of a batch with one-hot
+    /// `targets`. Returns the loss before the step.
+    pub fn train_step(&mut self, inputs: &Matrix, targets: &Matrix, learning_rate: f32) -> f32 {
+        let tape = Tape::new();
+        let parameters = self.parameters(&tape);
+        let loss = self
+            .logits(&tape, &parameters, inputs)
+            .cross_entropy(targets);
+        let grads = loss.backward();
+
+        let values = self
+            .layers
+            .iter_mut()
+            .flat_map(|l| [&mut l.weight, &mut l.bias]);
+        for (value, parameter) in values.zip(&parameters) {
+            *value = value.zip(&grads.wrt(parameter), |w, g| w - learning_rate * g);
+        }
+        loss.value()[(0, 0)]
+    }
+
+    /// Runs `epochs` passes of mini-batch gradient descent over `data` and
+    /// returns the mean loss of the last one, NaN for zero epochs.
+    pub fn fit(
+        &mut self,
+        data: &Dataset,
+        epochs: usize,
+        batch_size: usize,
+        learning_rate: f32,
+    ) -> Result<f32, FitError> {
+        if self.layers.is_empty() {
+            return Err(FitError::NoLayers);
+        }
+        if batch_size == 0 {
+            return Err(FitError::ZeroBatchSize);
+        }
+        if data.is_empty() {
+            return Err(FitError::EmptyDataset);
+        }
+        let expected = self.layers[0].weight.rows();
+        if data.inputs.cols() != expected {
+            return Err(FitError::InputWidth {
+                expected,
+                found: data.inputs.cols(),
+            });
+        }
+        let outputs = self.layers[self.layers.len() - 1].weight.cols();
+        if data.classes != outputs {
+            return Err(FitError::ClassCount {
+                expected: outputs,
+                found: data.classes,
+            });
+        }
+        let targets = data.one_hot();
+        let mut mean_loss = f32::NAN;
+        for _ in 0..epochs {
+            let mut total = 0.0;
+            let mut batches = 0;
+            for start in (0..data.len()).step_by(batch_size) {
+                let end = (start + batch_size).min(data.len());
+                total += self.train_step(
+                    &data.inputs.slice_rows(start, end),
+                    &targets.slice_rows(start, end),
+                    learning_rate,
+                );
+                batches += 1;
+            }
+            mean_loss = total / batches as f32;
+        }
+        Ok(mean_loss)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitError {
+    /// The network has no layers to train.
+    NoLayers,
+    ZeroBatchSize,
+    /// The dataset has no samples.
+    EmptyDataset,
+    /// The samples have a different number of features than the first layer
+    /// has inputs.
+    InputWidth {
+        expected: usize,
+        found: usize,
+    },
+    /// The dataset has a different number of classes than the last layer has
+    /// outputs.
+    ClassCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FitError::NoLayers => write!(f, "network has no layers"),
+            FitError::ZeroBatchSize => write!(f, "batch size must be positive"),
+            FitError::EmptyDataset => write!(f, "dataset has no samples"),
+            FitError::InputWidth { expected, found } => {
+                write!(f, "expected {expected} features per sample, found {found}")
+            }
+            FitError::ClassCount { expected, found } => {
+                write!(f, "network outputs {expected} classes, dataset has {found}")
+            }
+        }
+    }
+}
+
+impl Error for FitError {}
+
+/// Labelled samples, one per row of `inputs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dataset {
+    pub inputs: Matrix,
+    pub labels: Vec<usize>,
+    pub classes: usize,
+}
+
+impl Dataset {
+    pub fn new(inputs: Matrix, labels: Vec<usize>) -> Self {
+        assert_eq!(inputs.rows(), labels.len(), "one label per row expected");
+        let classes = labels.iter().max().map_or(0, |&l| l + 1);
+        Self {
+            inputs,
+            labels,
+            classes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Labels as rows with a 1 in the column of the class.
+    pub fn one_hot(&self) -> Matrix {
+        let mut targets = Matrix::zeros(self.len(), self.classes);
+        for (r, &label) in self.labels.iter().enumerate() {
+            targets[(r, label)] = 1.0;
+        }
+        targets
+    }
+
+    /// Reads MNIST-style CSV: the class label in the first column followed
+    /// by the features. A first line that does not start with a number is
+    /// taken as a header and skipped.
+    pub fn from_csv(reader: impl BufRead) -> Result<Self, CsvError> {
+        let mut data = Vec::new();
+        let mut labels = Vec::new();
+        let mut features = None;
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let number = i + 1;
+            let mut fields = line.split(',').map(str::trim);
+            let label = fields.next().unwrap_or_default();
+            if line.trim().is_empty() || (number == 1 && label.parse::<f32>().is_err()) {
+                continue;
+            }
+            labels.push(
+                label
+                    .parse()
+                    .map_err(|_| CsvError::InvalidLabel { line: number })?,
+            );
+
+            let before = data.len();
+            for (column, field) in fields.enumerate() {
+                let x = field.parse().map_err(|_| CsvError::InvalidNumber {
+                    line: number,
+                    column: column + 2,
+                })?;
+                data.push(x);
+            }
+            let found = data.len() - before;
+            match features {
+                None => features = Some(found),
+                Some(expected) if expected != found => {
+                    return Err(CsvError::RaggedRow {
+                        line: number,
+                        expected,
+                        found,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        let features = features.ok_or(CsvError::Empty)?;
+        Ok(Self::new(Matrix::new(labels.len(), features, data), labels))
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    Io(io::Error),
+    /// Labels must be non-negative integers.
+    InvalidLabel {
+        line: usize,
+    },
+    InvalidNumber {
+        line: usize,
+        column: usize,
+    },
+    RaggedRow {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    Empty,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "{e}"),
+            CsvError::InvalidLabel { line } => write!(f, "line {line}: invalid class label"),
+            CsvError::InvalidNumber { line, column } => {
+                write!(f, "line {line}, column {column}: invalid number")
+            }
+            CsvError::RaggedRow {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: expected {expected} features, found {found}"
+            ),
+            CsvError::Empty => write!(f, "no samples"),
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xor() -> Dataset {
+        let inputs = Matrix::from_rows(&[[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]]);
+        Dataset::new(inputs, vec![0, 1, 1, 0])
+    }
+
+    #[test]
+    fn learns_xor() {
+        let data = xor();
+        let mut mlp = Mlp::new(&[2, 8, 2], Activation::Tanh, 7);
+        let loss = mlp.fit(&data, 500, 4, 0.5).unwrap();
+
+        assert!(loss < 0.05, "loss {loss}");
+        assert_eq!(mlp.accuracy(&data), 1.0);
+    }
+
+    #[test]
+    fn train_step_reduces_loss() {
+        let data = xor();
+        let targets = data.one_hot();
+        let mut mlp = Mlp::new(&[2, 4, 2], Activation::Relu, 1);
+        let first = mlp.train_step(&data.inputs, &targets, 0.1);
+        let second = mlp.train_step(&data.inputs, &targets, 0.1);
+        assert!(second < first);
+    }
+
+    #[test]
+    fn fit_rejects_bad_settings() {
+        let data = xor();
+        let mut mlp = Mlp::new(&[2, 4, 2], Activation::Relu, 1);
+        assert_eq!(mlp.fit(&data, 1, 0, 0.1), Err(FitError::ZeroBatchSize));
+        assert!(mlp.fit(&data, 0, 4, 0.1).unwrap().is_nan());
+
+        let empty = Dataset::new(Matrix::zeros(0, 2), vec![]);
+        assert_eq!(mlp.fit(&empty, 1, 4, 0.1), Err(FitError::EmptyDataset));
+        let wide = Dataset::new(Matrix::zeros(4, 3), data.labels.clone());
+        assert_eq!(
+            mlp.fit(&wide, 1, 4, 0.1),
+            Err(FitError::InputWidth {
+                expected: 2,
+                found: 3
+            })
+        );
+        let three = Dataset::new(data.inputs.clone(), vec![0, 1, 2, 1]);
+        let error = mlp.fit(&three, 1, 4, 0.1).unwrap_err();
+        assert_eq!(
+            error,
+            FitError::ClassCount {
+                expected: 2,
+                found: 3
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "network outputs 2 classes, dataset has 3"
+        );
+
+        mlp.layers.clear();
+        assert_eq!(mlp.fit(&data, 1, 4, 0.1), Err(FitError::NoLayers));
+        // no layers leave the inputs as the logits
+        assert_eq!(mlp.predict(&data.inputs).row_slice(0), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn same_seed_same_weights() {
+        let a = Mlp::new(&[3, 5, 2], Activation::Sigmoid, 42);
+        assert_eq!(a, Mlp::new(&[3, 5, 2], Activation::Sigmoid, 42));
+        assert_ne!(a, Mlp::new(&[3, 5, 2], Activation::Sigmoid, 43));
+    }
+
+    #[test]
+    fn reads_csv_with_header() {
+        let csv = "label,p0,p1,p2\n2,0,128,255\n0, 1, 2, 3\n\n";
+        let data = Dataset::from_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(data.labels, vec![2, 0]);
+        assert_eq!(data.classes, 3);
+        assert_eq!(
+            data.inputs,
+            Matrix::from_rows(&[[0.0, 128.0, 255.0], [1.0, 2.0, 3.0]])
+        );
+        assert_eq!(data.one_hot().row_slice(0), &[0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn csv_errors_point_at_the_line() {
+        let error = |csv: &str| Dataset::from_csv(csv.as_bytes()).unwrap_err().to_string();
+        assert_eq!(error("1,2\n0,x"), "line 2, column 2: invalid number");
+        assert_eq!(error("1,2,3\n0,1"), "line 2: expected 2 features, found 1");
+        assert_eq!(error("1,2\n-1,2"), "line 2: invalid class label");
+        assert_eq!(error("label,a\n"), "no samples");
+    }
+}
@@ -0,0 +1,420 @@
+//! Explicit integrators for `y' = f(t, y)`, generic over [`Scalar`].
+//!
+//! Integrating with [`crate::Var`] states, or with `f` closing over `Var`
+//! parameters, carries the derivative of the solution along with it, so the
+//! sensitivities of the final state to the initial conditions and parameters
+//! come out of a single run, e.g. through [`crate::jacobian`].
+use std::error::Error;
+use std::fmt;
+
+use crate::Scalar;
+
+/// `y + h * sum(c * k)` over the given stages.
+fn step_with<T: Scalar>(y: &[T], h: f64, stages: &[(f64, &[T])]) -> Vec<T> {
+    y.iter()
+        .enumerate()
+        .map(|(i, &yi)| {
+            let slope = stages
+                .iter()
+                .filter(|(c, _)| *c != 0.0)
+                .fold(T::zero(), |acc, &(c, k)| acc + T::from_f64(c) * k[i]);
+            yi + T::from_f64(h) * slope
+        })
+        .collect()
+}
+
+fn call<T: Scalar>(f: &impl Fn(T, &[T]) -> Vec<T>, t: f64, y: &[T]) -> Vec<T> {
+    let dy = f(T::from_f64(t), y);
+    assert_eq!(dy.len(), y.len(), "f must return one derivative per state");
+    dy
+}
+
+/// Explicit Euler with `steps` equal steps from `t0` to `t1`, returns the
+/// state at `t1`.
+pub fn euler<T: Scalar>(
+    f: impl Fn(T, &[T]) -> Vec<T>,
+    t0: f64,
+    y0: &[T],
+    t1: f64,
+    steps: usize,
+) -> Vec<T> {
+    let h = (t1 - t0) / steps as f64;
+    (0..steps).fold(y0.to_vec(), |y, i| {
+        let k = call(&f, t0 + i as f64 * h, &y);
+        step_with(&y, h, &[(1.0, &k)])
+    })
+}
+
+/// Classic fourth-order Runge-Kutta with `steps` equal steps from `t0` to
+/// `t1`, returns the state at `t1`.
+pub fn rk4<T: Scalar>(
+    f: impl Fn(T, &[T]) -> Vec<T>,
+    t0: f64,
+    y0: &[T],
+    t1: f64,
+    steps: usize,
+) -> Vec<T> {
+    let h = (t1 - t0) / steps as f64;
+    (0..steps).fold(y0.to_vec(), |y, i| {
+        let t = t0 + i as f64 * h;
+        let k1 = call(&f, t, &y);
+        let k2 = call(&f, t + h / 2.0, &step_with(&y, h / 2.0, &[(1.0, &k1)]));
+        let k3 = call(&f, t + h / 2.0, &step_with(&y, h / 2.0, &[(1.0, &k2)]));
+        let k4 = call(&f, t + h, &step_with(&y, h, &[(1.0, &k3)]));
+        step_with(
+            &y,
+            h / 6.0,
+            &[(1.0, &k1), (2.0, &k2), (2.0, &k3), (1.0, &k4)],
+        )
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OdeOptions {
+    /// Relative tolerance on the local error of every component.
+    pub rtol: f64,
+    /// Absolute tolerance on the local error of every component.
+    pub atol: f64,
+    /// First step size to try, a hundredth of the interval when `None`.
+    pub initial_step: Option<f64>,
+    /// Accepted and rejected steps together.
+    pub max_steps: usize,
+}
+
+impl Default for OdeOptions {
+    fn default() -> Self {
+        Self {
+            rtol: 1e-6,
+            atol: 1e-9,
+            initial_step: None,
+            max_steps: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OdeError {
+    /// The step size needed to meet the tolerances vanished at `t`.
+    StepSizeUnderflow {
+        t: f64,
+    },
+    MaxSteps {
+        t: f64,
+    },
+    /// The state stopped being finite at `t`.
+    NonFinite {
+        t: f64,
+    },
+}
+
+impl fmt::Display for OdeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OdeError::StepSizeUnderflow { t } => write!(f, "step size underflow at t = {t}"),
+            OdeError::MaxSteps { t } => write!(f, "step limit reached at t = {t}"),
+            OdeError::NonFinite { t } => write!(f, "state is not finite at t = {t}"),
+        }
+    }
+}
+
+impl Error for OdeError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution<T> {
+    /// State at the end of the interval.
+    pub y: Vec<T>,
+    pub accepted: usize,
+    pub rejected: usize,
+}
+
+// Dormand-Prince 5(4) tableau, the last row of A doubles as the weights of
+// the fifth-order solution
+const C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const A: [[f64; 6]; 7] = [
+    [0.0; 6],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+        0.0,
+        0.0,
+    ],
+    [
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+        0.0,
+    ],
+    [
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+// fifth- minus fourth-order weights
+const E: [f64; 7] = [
+    35.0 / 384.0 - 5179.0 / 57600.0,
+    0.0,
+    500.0 / 1113.0 - 7571.0 / 16695.0,
+    125.0 / 192.0 - 393.0 / 640.0,
+    -2187.0 / 6784.0 + 92097.0 / 339200.0,
+    11.0 / 84.0 - 187.0 / 2100.0,
+    -1.0 / 40.0,
+];
+
+/// Adaptive Dormand-Prince 5(4) from `t0` to `t1`.
+///
+/// Step sizes are chosen from the values alone, see [`Scalar::to_f64`], so a
+/// run with duals takes the same steps as one with plain floats and its
+/// derivatives are those of the computed solution.
+pub fn rk45<T: Scalar>(
+    f: impl Fn(T, &[T]) -> Vec<T>,
+    t0: f64,
+    y0: &[T],
+    t1: f64,
+    options: &OdeOptions,
+) -> Result<Solution<T>, OdeError> {
+    let direction = if t1 < t0 { -1.0 } else { 1.0 };
+    let mut h = options
+        .initial_step
+        .map_or((t1 - t0).abs() / 100.0, f64::abs);
+    let mut t = t0;
+    let mut y = y0.to_vec();
+    // the last stage of an accepted step is the first stage of the next one
+    let mut k1 = call(&f, t, &y);
+    let (mut accepted, mut rejected) = (0, 0);
+
+    while (t1 - t) * direction > 0.0 {
+        if accepted + rejected == options.max_steps {
+            return Err(OdeError::MaxSteps { t });
+        }
+        // do not step past t1, and do not leave a sliver behind it either
+        let remaining = (t1 - t).abs();
+        let last = h >= remaining * (1.0 - 1e-12);
+        let step = direction * if last { remaining } else { h };
+        if t + step == t {
+            return Err(OdeError::StepSizeUnderflow { t });
+        }
+
+        let mut k: Vec<Vec<T>> = vec![k1.clone()];
+        for s in 1..7 {
+            let stages: Vec<(f64, &[T])> = (0..s).map(|j| (A[s][j], k[j].as_slice())).collect();
+            k.push(call(&f, t + C[s] * step, &step_with(&y, step, &stages)));
+        }
+        let y_new = step_with(
+            &y,
+            step,
+            &(0..6)
+                .map(|j| (A[6][j], k[j].as_slice()))
+                .collect::<Vec<_>>(),
+        );
+        let error = step_with(
+            &vec![T::zero(); y.len()],
+            step,
+            &(0..7).map(|j| (E[j], k[j].as_slice())).collect::<Vec<_>>(),
+        );
+
+        // root mean square of the error relative to the tolerances
+        let norm = if y.is_empty() {
+            0.0
+        } else {
+            let sum: f64 = (0..y.len())
+                .map(|i| {
+                    let scale = options.atol
+                        + options.rtol * y[i].to_f64().abs().max(y_new[i].to_f64().abs());
+                    (error[i].to_f64() / scale).powi(2)
+                })
+                .sum();
+            (sum / y.len() as f64).sqrt()
+        };
+        if norm.is_nan() || y_new.iter().any(|x| !x.to_f64().is_finite()) {
+            return Err(OdeError::NonFinite { t });
+        }
+
+        if norm <= 1.0 {
+            t = if last { t1 } else { t + step };
+            y = y_new;
+            k1 = k.swap_remove(6);
+            accepted += 1;
+        } else {
+            rejected += 1;
+        }
+        let factor = if norm == 0.0 {
+            5.0
+        } else {
+            0.9 * norm.powf(-0.2)
+        };
+        h = step.abs() * factor.clamp(0.2, 5.0);
+    }
+
+    Ok(Solution {
+        y,
+        accepted,
+        rejected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::check_gradient;
+    use crate::optim::{Lbfgs, Optimizer, Options};
+    use crate::{jacobian, Var};
+    use assert_approx_eq::assert_approx_eq;
+
+    fn oscillator<T: Scalar>(_t: T, y: &[T]) -> Vec<T> {
+        vec![y[1], -y[0]]
+    }
+
+    #[test]
+    fn euler_converges_linearly() {
+        let decay = |_t: f64, y: &[f64]| vec![-y[0]];
+        let error = |steps| (euler(decay, 0.0, &[1.0], 1.0, steps)[0] - (-1.0f64).exp()).abs();
+        let ratio = error(100) / error(200);
+        assert_approx_eq!(ratio, 2.0, 0.05);
+    }
+
+    #[test]
+    fn rk4_converges_with_fourth_order() {
+        let error = |steps| (rk4(oscillator, 0.0, &[1.0, 0.0], 2.0, steps)[0] - 2.0f64.cos()).abs();
+        let ratio = error(20) / error(40);
+        assert_approx_eq!(ratio, 16.0, 0.5);
+    }
+
+    #[test]
+    fn time_dependent_rhs() {
+        // y' = 2t, y(0) = 1
+        let y = rk4(|t: f64, _y: &[f64]| vec![2.0 * t], 0.0, &[1.0], 3.0, 10);
+        assert_approx_eq!(y[0], 10.0, 1e-12);
+    }
+
+    #[test]
+    fn rk45_meets_tolerance() {
+        let options = OdeOptions {
+            rtol: 1e-10,
+            atol: 1e-12,
+            ..OdeOptions::default()
+        };
+        let solution = rk45(oscillator, 0.0, &[1.0, 0.0], 10.0, &options).unwrap();
+        assert_approx_eq!(solution.y[0], 10.0f64.cos(), 1e-8);
+        assert_approx_eq!(solution.y[1], -(10.0f64.sin()), 1e-8);
+
+        let loose = rk45(oscillator, 0.0, &[1.0, 0.0], 10.0, &OdeOptions::default()).unwrap();
+        assert!(loose.accepted < solution.accepted);
+    }
+
+    #[test]
+    fn rk45_integrates_backwards() {
+        let solution = rk45(
+            oscillator,
+            1.0,
+            &[1.0f64.cos(), -(1.0f64.sin())],
+            0.0,
+            &OdeOptions::default(),
+        )
+        .unwrap();
+        assert_approx_eq!(solution.y[0], 1.0, 1e-6);
+        assert_approx_eq!(solution.y[1], 0.0, 1e-6);
+    }
+
+    #[test]
+    fn rk45_reports_blow_up() {
+        // y' = y^2, y(0) = 1 blows up at t = 1
+        let result = rk45(
+            |_t: f64, y: &[f64]| vec![y[0] * y[0]],
+            0.0,
+            &[1.0],
+            2.0,
+            &OdeOptions::default(),
+        );
+        match result {
+            Err(OdeError::StepSizeUnderflow { t } | OdeError::NonFinite { t }) => {
+                assert_approx_eq!(t, 1.0, 1e-3)
+            }
+            other => panic!("expected a failure near t = 1, got {other:?}"),
+        }
+
+        let options = OdeOptions {
+            max_steps: 3,
+            ..OdeOptions::default()
+        };
+        let result = rk45(oscillator, 0.0, &[1.0, 0.0], 10.0, &options);
+        assert!(matches!(result, Err(OdeError::MaxSteps { .. })));
+    }
+
+    #[test]
+    fn sensitivities_of_exponential_decay() {
+        // y(1) = y0 * exp(-k) for y' = -k y
+        let (y0, k) = (2.0, 0.7_f64);
+        let expected = [(-k).exp(), -y0 * (-k).exp()];
+        let options = OdeOptions {
+            rtol: 1e-10,
+            ..OdeOptions::default()
+        };
+
+        let fixed = jacobian(
+            |v| {
+                rk4(
+                    |_t, y: &[Var<f64>]| vec![-v[1] * y[0]],
+                    0.0,
+                    &v[..1],
+                    1.0,
+                    50,
+                )
+            },
+            &[y0, k],
+        );
+        let adaptive = jacobian(
+            |v| {
+                let rhs = |_t, y: &[Var<f64>]| vec![-v[1] * y[0]];
+                rk45(rhs, 0.0, &v[..1], 1.0, &options).unwrap().y
+            },
+            &[y0, k],
+        );
+        for j in 0..2 {
+            assert_approx_eq!(fixed[0][j], expected[j], 1e-8);
+            assert_approx_eq!(adaptive[0][j], expected[j], 1e-8);
+        }
+    }
+
+    #[test]
+    fn lotka_volterra_sensitivities_match_finite_differences() {
+        // prey and predators with rates a, b, c, d after the initial state
+        let final_prey = |v: &[Var<f64>]| {
+            let (a, b, c, d) = (v[2], v[3], v[4], v[5]);
+            let rhs =
+                |_t, y: &[Var<f64>]| vec![a * y[0] - b * y[0] * y[1], d * y[0] * y[1] - c * y[1]];
+            rk4(rhs, 0.0, &v[..2], 5.0, 500)[0]
+        };
+        let check = check_gradient(final_prey, &[10.0, 5.0, 1.1, 0.4, 0.4, 0.1], 1e-6);
+        assert!(check.passes(1e-5), "{check:?}");
+    }
+
+    #[test]
+    fn fits_decay_rate() {
+        // noiseless samples of y0 = 3, k = 0.5 at t = 1, 2, 3
+        let samples = [1.0, 2.0, 3.0].map(|t: f64| (t, 3.0 * (-0.5 * t).exp()));
+        let loss = |v: &[Var<f64>]| {
+            let rhs = |_t, y: &[Var<f64>]| vec![-v[1] * y[0]];
+            let mut state = v[..1].to_vec();
+            let mut t = 0.0;
+            samples.iter().fold(Var::constant(0.0), |acc, &(ts, ys)| {
+                state = rk4(rhs, t, &state, ts, 20);
+                t = ts;
+                acc + (state[0] - ys).powi(2)
+            })
+        };
+        let fit = Lbfgs::default().minimize(loss, &[1.0, 1.0], &Options::default());
+        assert_approx_eq!(fit.x[0], 3.0, 1e-4);
+        assert_approx_eq!(fit.x[1], 0.5, 1e-4);
+    }
+}
@@ -0,0 +1,557 @@
+//! Gradient-based minimisers for closures written against [`Var`].
+//!
+//! Every optimizer implements [`Optimizer::step`]; the shared driver in
+//! [`Optimizer::minimize_with`] evaluates gradients, checks the stopping
+//! criteria in [`Options`] and reports each iteration to a callback.
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+
+use crate::diff::value_and_gradient;
+use crate::Var;
+
+/// Function being minimised, evaluated together with its gradient.
+pub struct Objective<'f> {
+    f: &'f dyn Fn(&[Var<f64>]) -> Var<f64>,
+}
+
+impl<'f> Objective<'f> {
+    pub fn new(f: &'f dyn Fn(&[Var<f64>]) -> Var<f64>) -> Self {
+        Self { f }
+    }
+
+    pub fn eval(&self, x: &[f64]) -> (f64, Vec<f64>) {
+        value_and_gradient(self.f, x)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub max_iters: usize,
+    /// Stop once the euclidean norm of the gradient drops to this value.
+    pub grad_tol: f64,
+    /// Stop once a step changes the value by at most this much.
+    pub f_tol: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_iters: 1000,
+            grad_tol: 1e-6,
+            f_tol: 0.0,
+        }
+    }
+}
+
+/// State handed to the per-iteration callback.
+#[derive(Debug)]
+pub struct Iteration<'a> {
+    pub iteration: usize,
+    pub x: &'a [f64],
+    pub value: f64,
+    pub gradient: &'a [f64],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    GradientTolerance,
+    ValueTolerance,
+    MaxIterations,
+    Callback,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Minimum {
+    pub x: Vec<f64>,
+    pub value: f64,
+    pub gradient: Vec<f64>,
+    pub iterations: usize,
+    pub reason: StopReason,
+}
+
+pub trait Optimizer {
+    /// Forgets state accumulated by a previous run.
+    fn reset(&mut self);
+
+    /// Moves `x` downhill and returns the value and gradient at the new point.
+    fn step(
+        &mut self,
+        objective: &Objective,
+        x: &mut [f64],
+        value: f64,
+        gradient: &[f64],
+    ) -> (f64, Vec<f64>);
+
+    fn minimize(
+        &mut self,
+        f: impl Fn(&[Var<f64>]) -> Var<f64>,
+        x0: &[f64],
+        options: &Options,
+    ) -> Minimum {
+        self.minimize_with(f, x0, options, |_| ControlFlow::Continue(()))
+    }
+
+    /// Like [`Optimizer::minimize`], `callback` sees every iterate and can
+    /// stop the run by returning `ControlFlow::Break`.
+    fn minimize_with(
+        &mut self,
+        f: impl Fn(&[Var<f64>]) -> Var<f64>,
+        x0: &[f64],
+        options: &Options,
+        mut callback: impl FnMut(&Iteration) -> ControlFlow<()>,
+    ) -> Minimum {
+        self.reset();
+        let objective = Objective::new(&f);
+        let mut x = x0.to_vec();
+        let (mut value, mut gradient) = objective.eval(&x);
+
+        let mut iteration = 0;
+        let reason = loop {
+            let state = Iteration {
+                iteration,
+                x: &x,
+                value,
+                gradient: &gradient,
+            };
+            if callback(&state).is_break() {
+                break StopReason::Callback;
+            }
+            if norm(&gradient) <= options.grad_tol {
+                break StopReason::GradientTolerance;
+            }
+            if iteration == options.max_iters {
+                break StopReason::MaxIterations;
+            }
+
+            let (next_value, next_gradient) = self.step(&objective, &mut x, value, &gradient);
+            iteration += 1;
+            let change = (value - next_value).abs();
+            value = next_value;
+            gradient = next_gradient;
+            if change <= options.f_tol {
+                break StopReason::ValueTolerance;
+            }
+        };
+
+        Minimum {
+            x,
+            value,
+            gradient,
+            iterations: iteration,
+            reason,
+        }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+#[derive(Debug, Clone)]
+pub struct GradientDescent {
+    pub learning_rate: f64,
+}
+
+impl GradientDescent {
+    pub fn new(learning_rate: f64) -> Self {
+        Self { learning_rate }
+    }
+}
+
+impl Optimizer for GradientDescent {
+    fn reset(&mut self) {}
+
+    fn step(
+        &mut self,
+        objective: &Objective,
+        x: &mut [f64],
+        _value: f64,
+        gradient: &[f64],
+    ) -> (f64, Vec<f64>) {
+        for (x, g) in x.iter_mut().zip(gradient) {
+            *x -= self.learning_rate * g;
+        }
+        objective.eval(x)
+    }
+}
+
+/// Gradient descent with a heavy-ball velocity term.
+#[derive(Debug, Clone)]
+pub struct Momentum {
+    pub learning_rate: f64,
+    pub momentum: f64,
+    velocity: Vec<f64>,
+}
+
+impl Momentum {
+    pub fn new(learning_rate: f64, momentum: f64) -> Self {
+        Self {
+            learning_rate,
+            momentum,
+            velocity: vec![],
+        }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn reset(&mut self) {
+        self.velocity.clear();
+    }
+
+    fn step(
+        &mut self,
+        objective: &Objective,
+        x: &mut [f64],
+        _value: f64,
+        gradient: &[f64],
+    ) -> (f64, Vec<f64>) {
+        self.velocity.resize(x.len(), 0.0);
+        for ((x, v), g) in x.iter_mut().zip(&mut self.velocity).zip(gradient) {
+            *v = self.momentum * *v - self.learning_rate * g;
+            *x += *v;
+        }
+        objective.eval(x)
+    }
+}
+
+/// Adam with bias-corrected first and second moment estimates.
+#[derive(Debug, Clone)]
+pub struct Adam {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    m: Vec<f64>,
+    v: Vec<f64>,
+    t: i32,
+}
+
+impl Adam {
+    pub fn new(learning_rate: f64) -> Self {
+        Self {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            m: vec![],
+            v: vec![],
+            t: 0,
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn reset(&mut self) {
+        self.m.clear();
+        self.v.clear();
+        self.t = 0;
+    }
+
+    fn step(
+        &mut self,
+        objective: &Objective,
+        x: &mut [f64],
+        _value: f64,
+        gradient: &[f64],
+    ) -> (f64, Vec<f64>) {
+        self.m.resize(x.len(), 0.0);
+        self.v.resize(x.len(), 0.0);
+        self.t += 1;
+        let m_correction = 1.0 - self.beta1.powi(self.t);
+        let v_correction = 1.0 - self.beta2.powi(self.t);
+
+        for (i, g) in gradient.iter().enumerate() {
+            self.m[i] = self.beta1 * self.m[i] + (1.0 - self.beta1) * g;
+            self.v[i] = self.beta2 * self.v[i] + (1.0 - self.beta2) * g * g;
+            let m_hat = self.m[i] / m_correction;
+            let v_hat = self.v[i] / v_correction;
+            x[i] -= self.learning_rate * m_hat / (v_hat.sqrt() + self.epsilon);
+        }
+        objective.eval(x)
+    }
+}
+
+/// Limited-memory BFGS with a backtracking Armijo line search. When no step
+/// along the gradient decreases the value either, `x` stays where it is and
+/// the run stops with [`StopReason::ValueTolerance`].
+#[derive(Debug, Clone)]
+pub struct Lbfgs {
+    /// Number of `(s, y)` correction pairs kept, at least one.
+    pub memory: usize,
+    history: VecDeque<(Vec<f64>, Vec<f64>)>,
+}
+
+impl Lbfgs {
+    pub fn new(memory: usize) -> Self {
+        Self {
+            memory,
+            history: VecDeque::new(),
+        }
+    }
+
+    // two-loop recursion, approximates -H^-1 g from the stored pairs
+    fn direction(&self, gradient: &[f64]) -> Vec<f64> {
+        let mut q = gradient.to_vec();
+        let mut alphas = Vec::with_capacity(self.history.len());
+        for (s, y) in self.history.iter().rev() {
+            let alpha = dot(s, &q) / dot(y, s);
+            for (q, y) in q.iter_mut().zip(y) {
+                *q -= alpha * y;
+            }
+            alphas.push(alpha);
+        }
+
+        if let Some((s, y)) = self.history.back() {
+            let gamma = dot(s, y) / dot(y, y);
+            q.iter_mut().for_each(|q| *q *= gamma);
+        }
+
+        for ((s, y), alpha) in self.history.iter().zip(alphas.into_iter().rev()) {
+            let beta = dot(y, &q) / dot(y, s);
+            for (q, s) in q.iter_mut().zip(s) {
+                *q += (alpha - beta) * s;
+            }
+        }
+
+        q.iter().map(|q| -q).collect()
+    }
+}
+
+impl Default for Lbfgs {
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl Optimizer for Lbfgs {
+    fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    fn step(
+        &mut self,
+        objective: &Objective,
+        x: &mut [f64],
+        value: f64,
+        gradient: &[f64],
+    ) -> (f64, Vec<f64>) {
+        let mut direction = self.direction(gradient);
+        let mut slope = dot(&direction, gradient);
+        if slope >= 0.0 {
+            // curvature pairs went stale, restart from steepest descent
+            self.history.clear();
+            direction = gradient.iter().map(|g| -g).collect();
+            slope = -dot(gradient, gradient);
+        }
+
+        let start = x.to_vec();
+        let mut trial = line_search(objective, x, &start, value, &direction, slope);
+        if trial.is_none() && !self.history.is_empty() {
+            // the curvature pairs point nowhere useful, retry downhill
+            self.history.clear();
+            direction = gradient.iter().map(|g| -g).collect();
+            slope = -dot(gradient, gradient);
+            trial = line_search(objective, x, &start, value, &direction, slope);
+        }
+        let Some(trial) = trial else {
+            // no step decreases the value, staying put stops the driver on an
+            // unchanged value
+            x.copy_from_slice(&start);
+            return (value, gradient.to_vec());
+        };
+
+        let s: Vec<f64> = x.iter().zip(&start).map(|(x, x0)| x - x0).collect();
+        let y: Vec<f64> = trial.1.iter().zip(gradient).map(|(g, g0)| g - g0).collect();
+        // only keep pairs that preserve a positive definite approximation
+        if dot(&s, &y) > f64::EPSILON * dot(&y, &y) {
+            while self.history.len() >= self.memory.max(1) {
+                self.history.pop_front();
+            }
+            self.history.push_back((s, y));
+        }
+
+        trial
+    }
+}
+
+// backtracks from a full step until the Armijo condition holds, `x` is left
+// at the accepted point
+fn line_search(
+    objective: &Objective,
+    x: &mut [f64],
+    start: &[f64],
+    value: f64,
+    direction: &[f64],
+    slope: f64,
+) -> Option<(f64, Vec<f64>)> {
+    const ARMIJO: f64 = 1e-4;
+    const MAX_HALVINGS: usize = 50;
+
+    let mut step = 1.0;
+    for _ in 0..MAX_HALVINGS {
+        for ((x, x0), d) in x.iter_mut().zip(start).zip(direction) {
+            *x = x0 + step * d;
+        }
+        let trial = objective.eval(x);
+        if trial.0 <= value + ARMIJO * step * slope {
+            return Some(trial);
+        }
+        step *= 0.5;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    // minimum 0 at (1, -2, 0.5)
+    fn bowl(x: &[Var<f64>]) -> Var<f64> {
+        (x[0] - 1.0).powi(2) + 4.0 * (x[1] + 2.0).powi(2) + 0.5 * (x[2] - 0.5).powi(2)
+    }
+
+    // minimum 0 at (1, 1)
+    fn rosenbrock(x: &[Var<f64>]) -> Var<f64> {
+        (1.0 - x[0]).powi(2) + 100.0 * (x[1] - x[0].powi(2)).powi(2)
+    }
+
+    fn assert_near(x: &[f64], expected: &[f64], tol: f64) {
+        for (x, expected) in x.iter().zip(expected) {
+            assert_approx_eq!(x, expected, tol);
+        }
+    }
+
+    #[test]
+    fn gradient_descent_minimizes_bowl() {
+        let result = GradientDescent::new(0.1).minimize(bowl, &[0.0; 3], &Options::default());
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert_near(&result.x, &[1.0, -2.0, 0.5], 1e-5);
+    }
+
+    #[test]
+    fn momentum_minimizes_bowl() {
+        let result = Momentum::new(0.05, 0.8).minimize(bowl, &[0.0; 3], &Options::default());
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert_near(&result.x, &[1.0, -2.0, 0.5], 1e-5);
+    }
+
+    #[test]
+    fn adam_minimizes_bowl() {
+        let options = Options {
+            max_iters: 5000,
+            grad_tol: 1e-4,
+            ..Options::default()
+        };
+        let result = Adam::new(0.05).minimize(bowl, &[0.0; 3], &options);
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert_near(&result.x, &[1.0, -2.0, 0.5], 1e-4);
+    }
+
+    #[test]
+    fn lbfgs_minimizes_bowl_quickly() {
+        let result = Lbfgs::default().minimize(bowl, &[0.0; 3], &Options::default());
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert!(result.iterations < 20);
+        assert_near(&result.x, &[1.0, -2.0, 0.5], 1e-6);
+    }
+
+    #[test]
+    fn lbfgs_minimizes_rosenbrock() {
+        let result = Lbfgs::default().minimize(rosenbrock, &[-1.2, 1.0], &Options::default());
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert_near(&result.x, &[1.0, 1.0], 1e-5);
+        assert!(result.value < 1e-10);
+    }
+
+    #[test]
+    fn lbfgs_without_memory_keeps_one_pair() {
+        let mut lbfgs = Lbfgs::new(0);
+        let result = lbfgs.minimize(bowl, &[0.0; 3], &Options::default());
+        assert_eq!(result.reason, StopReason::GradientTolerance);
+        assert_near(&result.x, &[1.0, -2.0, 0.5], 1e-6);
+        assert_eq!(lbfgs.history.len(), 1);
+    }
+
+    #[test]
+    fn lbfgs_stays_put_when_the_line_search_fails() {
+        // every step of at least 2^-50 overshoots the kink
+        let abs = |x: &[Var<f64>]| x[0].abs();
+        let result = Lbfgs::default().minimize(abs, &[1e-30], &Options::default());
+        assert_eq!(result.reason, StopReason::ValueTolerance);
+        assert_eq!((result.x[0], result.value), (1e-30, 1e-30));
+    }
+
+    #[test]
+    fn adam_minimizes_rosenbrock() {
+        let options = Options {
+            max_iters: 20000,
+            grad_tol: 1e-3,
+            ..Options::default()
+        };
+        let result = Adam::new(0.01).minimize(rosenbrock, &[-1.2, 1.0], &options);
+        assert_near(&result.x, &[1.0, 1.0], 1e-2);
+    }
+
+    #[test]
+    fn momentum_minimizes_rosenbrock() {
+        let options = Options {
+            max_iters: 50000,
+            grad_tol: 1e-4,
+            ..Options::default()
+        };
+        let result = Momentum::new(1e-4, 0.9).minimize(rosenbrock, &[-1.2, 1.0], &options);
+        assert_near(&result.x, &[1.0, 1.0], 1e-2);
+    }
+
+    #[test]
+    fn max_iterations_stops_run() {
+        let options = Options {
+            max_iters: 3,
+            ..Options::default()
+        };
+        let result = GradientDescent::new(1e-3).minimize(rosenbrock, &[-1.2, 1.0], &options);
+        assert_eq!(result.reason, StopReason::MaxIterations);
+        assert_eq!(result.iterations, 3);
+    }
+
+    #[test]
+    fn value_tolerance_stops_run() {
+        let options = Options {
+            f_tol: 1e-3,
+            ..Options::default()
+        };
+        let result = GradientDescent::new(0.1).minimize(bowl, &[0.0; 3], &options);
+        assert_eq!(result.reason, StopReason::ValueTolerance);
+    }
+
+    #[test]
+    fn callback_sees_every_iteration_and_can_stop() {
+        let mut values = vec![];
+        let result = Lbfgs::default().minimize_with(bowl, &[0.0; 3], &Options::default(), |it| {
+            assert_eq!(it.iteration, values.len());
+            values.push(it.value);
+            if it.iteration == 2 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+
+        assert_eq!(result.reason, StopReason::Callback);
+        assert_eq!(values.len(), 3);
+        assert!(values.windows(2).all(|w| w[1] < w[0]));
+    }
+
+    #[test]
+    fn optimizer_state_is_reset_between_runs() {
+        let mut adam = Adam::new(0.05);
+        let first = adam.minimize(bowl, &[0.0; 3], &Options::default());
+        let second = adam.minimize(bowl, &[0.0; 3], &Options::default());
+        assert_eq!(first, second);
+    }
+}
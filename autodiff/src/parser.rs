@@ -0,0 +1,475 @@
+//! Parses infix formulas such as `"x*a/(x^2+b)"` into [`Expr`] trees.
+//!
+//! Precedence from loosest to tightest: `+ -`, `* /`, unary minus, `^`
+//! (right associative). Calls accept every [`Func`] and [`Func2`] name, and
+//! errors carry the byte offset of the offending input.
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::symbolic::{Expr, Func, Func2};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Comma,
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(x) => write!(f, "number {x}"),
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Plus => write!(f, "`+`"),
+            Token::Minus => write!(f, "`-`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Slash => write!(f, "`/`"),
+            Token::Caret => write!(f, "`^`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    InvalidNumber(String),
+    UnexpectedToken { found: String, expected: &'static str },
+    UnexpectedEnd { expected: &'static str },
+    UnknownFunction(String),
+    WrongArity { name: String, expected: usize, found: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the source where the error was detected.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at position {}: ", self.position)?;
+        match &self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character `{c}`"),
+            ParseErrorKind::InvalidNumber(s) => write!(f, "invalid number `{s}`"),
+            ParseErrorKind::UnexpectedToken { found, expected } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            ParseErrorKind::UnexpectedEnd { expected } => {
+                write!(f, "expected {expected}, found end of input")
+            }
+            ParseErrorKind::UnknownFunction(name) => write!(f, "unknown function `{name}`"),
+            ParseErrorKind::WrongArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{name}` takes {expected} argument(s), {found} were given"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<CharIndices> = src.char_indices().peekable();
+
+    while let Some(&(position, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' | '.' => {
+                let mut end = position;
+                while let Some(&(i, c)) = chars.peek() {
+                    // accept exponents like 1e-3 as part of the number
+                    let exponent_sign =
+                        (c == '-' || c == '+') && src[position..i].ends_with(['e', 'E']);
+                    if c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let text = &src[position..end];
+                let number = text.parse().map_err(|_| ParseError {
+                    kind: ParseErrorKind::InvalidNumber(text.to_string()),
+                    position,
+                })?;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = position;
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(src[position..end].to_string())
+            }
+            _ => {
+                chars.next();
+                match c {
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    '*' => Token::Star,
+                    '/' => Token::Slash,
+                    '^' => Token::Caret,
+                    ',' => Token::Comma,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    c => {
+                        return Err(ParseError {
+                            kind: ParseErrorKind::UnexpectedChar(c),
+                            position,
+                        })
+                    }
+                }
+            }
+        };
+        tokens.push((token, position));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    cursor: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.cursor)
+            .map_or(self.end, |(_, position)| *position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.cursor).map(|(token, _)| token.clone());
+        self.cursor += 1;
+        token
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        let kind = match self.peek() {
+            Some(token) => ParseErrorKind::UnexpectedToken {
+                found: token.to_string(),
+                expected,
+            },
+            None => ParseErrorKind::UnexpectedEnd { expected },
+        };
+        ParseError {
+            kind,
+            position: self.position(),
+        }
+    }
+
+    fn expect(&mut self, token: Token, expected: &'static str) -> Result<(), ParseError> {
+        if self.peek() == Some(&token) {
+            self.cursor += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.product()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.cursor += 1;
+                    lhs = lhs + self.product()?;
+                }
+                Some(Token::Minus) => {
+                    self.cursor += 1;
+                    lhs = lhs - self.product()?;
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.cursor += 1;
+                    lhs = lhs * self.unary()?;
+                }
+                Some(Token::Slash) => {
+                    self.cursor += 1;
+                    lhs = lhs / self.unary()?;
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.cursor += 1;
+            Ok(-self.unary()?)
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.primary()?;
+        if self.peek() != Some(&Token::Caret) {
+            return Ok(base);
+        }
+        self.cursor += 1;
+
+        // the exponent may be negated, `x^-2` means `x^(-2)`
+        let exponent = self.unary()?;
+        Ok(match integer_exponent(&exponent) {
+            Some(n) => base.powi(n),
+            None => base.powf(&exponent),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Number(x)) => Ok(Expr::Const(x)),
+            Some(Token::LParen) => {
+                let expr = self.sum()?;
+                self.expect(Token::RParen, "`)`")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.cursor += 1;
+                    self.call(name, position)
+                } else {
+                    Ok(Expr::Symbol(name))
+                }
+            }
+            _ => {
+                self.cursor -= 1;
+                Err(self.unexpected("a number, symbol or `(`"))
+            }
+        }
+    }
+
+    fn call(&mut self, name: String, position: usize) -> Result<Expr, ParseError> {
+        let mut args = vec![];
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                args.push(self.sum()?);
+                if self.peek() == Some(&Token::Comma) {
+                    self.cursor += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen, "`,` or `)`")?;
+
+        let arity_error = |expected| ParseError {
+            kind: ParseErrorKind::WrongArity {
+                name: name.clone(),
+                expected,
+                found: args.len(),
+            },
+            position,
+        };
+        if let Some(func) = Func::ALL.iter().find(|f| f.name() == name) {
+            let [arg] = args.as_slice() else {
+                return Err(arity_error(1));
+            };
+            return Ok(arg.call(*func));
+        }
+        if let Some(func) = Func2::ALL.iter().find(|f| f.name() == name) {
+            let [a, b] = args.as_slice() else {
+                return Err(arity_error(2));
+            };
+            return Ok(a.call2(*func, b));
+        }
+        Err(ParseError {
+            kind: ParseErrorKind::UnknownFunction(name),
+            position,
+        })
+    }
+}
+
+fn integer_exponent(exponent: &Expr) -> Option<i32> {
+    match exponent {
+        Expr::Const(n) if n.fract() == 0.0 && n.abs() <= i32::MAX as f64 => Some(*n as i32),
+        Expr::Neg(inner) => integer_exponent(inner).map(|n| -n),
+        _ => None,
+    }
+}
+
+/// Parses a whole formula, trailing input is an error.
+pub fn parse(src: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        cursor: 0,
+        end: src.len(),
+    };
+    let expr = parser.sum()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected("an operator or end of input"));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Var;
+    use assert_approx_eq::assert_approx_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parses_algebraic_expression_and_differentiates_with_var() {
+        let expr = parse("x*a/(x^2+b)").unwrap();
+        let env: HashMap<String, Var<f64>> = [
+            ("x".to_string(), Var::variable(24.0)),
+            ("a".to_string(), Var::constant(22.0)),
+            ("b".to_string(), Var::constant(2.0)),
+        ]
+        .into();
+        let result = expr.eval(&env).unwrap();
+        assert_approx_eq!(result.value(), 0.9134948096885813, 1e-12);
+        assert_approx_eq!(result.deriv(), -0.03779887692915554, 1e-12);
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(parse("1 + 2 * 3").unwrap().to_string(), "1 + 2 * 3");
+        assert_eq!(parse("(1 + 2) * 3").unwrap().to_string(), "(1 + 2) * 3");
+        assert_eq!(parse("a - b - c").unwrap(), parse("(a - b) - c").unwrap());
+        assert_eq!(parse("a / b / c").unwrap(), parse("(a / b) / c").unwrap());
+        assert_eq!(parse("a ^ b ^ c").unwrap(), parse("a ^ (b ^ c)").unwrap());
+        assert_eq!(parse("-x^2").unwrap(), -Expr::symbol("x").powi(2));
+        assert_eq!(parse("-a * b").unwrap(), -Expr::symbol("a") * Expr::symbol("b"));
+    }
+
+    #[test]
+    fn integer_exponents_become_powi() {
+        let x = Expr::symbol("x");
+        assert_eq!(parse("x^3").unwrap(), x.powi(3));
+        assert_eq!(parse("x^-2").unwrap(), x.powi(-2));
+        assert_eq!(parse("x^(-2)").unwrap(), x.powi(-2));
+        assert_eq!(parse("x^1.5").unwrap(), x.powf(&Expr::Const(1.5)));
+    }
+
+    #[test]
+    fn numbers_with_exponents() {
+        assert_eq!(parse("1.5e-3").unwrap(), Expr::Const(1.5e-3));
+        assert_eq!(parse("2E+2 - .5").unwrap(), Expr::Const(200.0) - Expr::Const(0.5));
+    }
+
+    #[test]
+    fn every_function_can_be_called() {
+        for func in Func::ALL {
+            let src = format!("{}(x)", func.name());
+            assert_eq!(parse(&src).unwrap().to_string(), src);
+        }
+        for func in Func2::ALL {
+            let expr = parse(&format!("{}(x, y + 1)", func.name())).unwrap();
+            assert_eq!(expr, Expr::symbol("x").call2(func, &(Expr::symbol("y") + 1.0)));
+        }
+    }
+
+    #[test]
+    fn printed_expressions_parse_back_into_the_same_tree() {
+        let sources = [
+            "x - (y - z)",
+            "x / (y * z)",
+            "(-x)^2",
+            "-(x * y)",
+            "sin(x)^2 + exp(-x) * atan2(y, x)",
+            "x^(-3) / -2",
+            "((a * x^3 + a * c * x)^4 * sin(a * x)) / (256 * a^5 * x^9) - cos(c * x)",
+        ];
+        for src in sources {
+            let expr = parse(src).unwrap();
+            assert_eq!(parse(&expr.to_string()).unwrap(), expr, "{src}");
+        }
+    }
+
+    #[test]
+    fn errors_report_positions() {
+        let error = |src| parse(src).unwrap_err();
+
+        assert_eq!(
+            error("x + $"),
+            ParseError {
+                kind: ParseErrorKind::UnexpectedChar('$'),
+                position: 4
+            }
+        );
+        assert_eq!(
+            error("(x + 1"),
+            ParseError {
+                kind: ParseErrorKind::UnexpectedEnd { expected: "`)`" },
+                position: 6
+            }
+        );
+        assert_eq!(
+            error("x y").kind,
+            ParseErrorKind::UnexpectedToken {
+                found: "`y`".to_string(),
+                expected: "an operator or end of input"
+            }
+        );
+        assert_eq!(error("x y").position, 2);
+        assert_eq!(
+            error("2 * foo(x)"),
+            ParseError {
+                kind: ParseErrorKind::UnknownFunction("foo".to_string()),
+                position: 4
+            }
+        );
+        assert_eq!(
+            error("atan2(x)"),
+            ParseError {
+                kind: ParseErrorKind::WrongArity {
+                    name: "atan2".to_string(),
+                    expected: 2,
+                    found: 1
+                },
+                position: 0
+            }
+        );
+        assert_eq!(error("1.2.3").kind, ParseErrorKind::InvalidNumber("1.2.3".to_string()));
+        assert_eq!(error("x * * 2").position, 4);
+    }
+
+    #[test]
+    fn error_messages_are_readable() {
+        let message = parse("sin(x").unwrap_err().to_string();
+        assert_eq!(message, "at position 5: expected `,` or `)`, found end of input");
+    }
+}
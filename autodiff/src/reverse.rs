@@ -0,0 +1,275 @@
+//! Reverse-mode automatic differentiation.
+//!
+//! Every operation on a [`Node`] is recorded on a shared [`Tape`]. A single
+//! call to [`Node::backward`] then sweeps the tape from the output back to the
+//! inputs and yields the gradient with respect to every recorded variable,
+//! instead of one forward pass per input as with [`crate::Var`].
+use std::cell::RefCell;
+use std::ops;
+
+// each recorded operation depends on at most two parents, only the first
+// `arity` of them are real. The others would add 0 * adjoint, which is NaN
+// once the adjoint is infinite
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    parents: [usize; 2],
+    weights: [f32; 2],
+    arity: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Tape {
+    entries: RefCell<Vec<Entry>>,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new input variable on the tape.
+    pub fn var(&self, x: f32) -> Node<'_> {
+        let index = self.len();
+        Node {
+            tape: self,
+            index: self.push(0, [index, index], [0.0, 0.0]),
+            x,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Forgets all recorded operations so the tape can be reused.
+    pub fn clear(&mut self) {
+        self.entries.get_mut().clear();
+    }
+
+    fn push(&self, arity: usize, parents: [usize; 2], weights: [f32; 2]) -> usize {
+        let mut entries = self.entries.borrow_mut();
+        entries.push(Entry {
+            parents,
+            weights,
+            arity,
+        });
+        entries.len() - 1
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Node<'t> {
+    tape: &'t Tape,
+    index: usize,
+    x: f32,
+}
+
+impl<'t> Node<'t> {
+    pub fn value(&self) -> f32 {
+        self.x
+    }
+
+    fn record(self, arity: usize, parents: [usize; 2], weights: [f32; 2], x: f32) -> Node<'t> {
+        Node {
+            tape: self.tape,
+            index: self.tape.push(arity, parents, weights),
+            x,
+        }
+    }
+
+    fn unary(self, weight: f32, x: f32) -> Node<'t> {
+        self.record(1, [self.index, self.index], [weight, 0.0], x)
+    }
+
+    fn binary(self, rhs: Node<'t>, weights: [f32; 2], x: f32) -> Node<'t> {
+        debug_assert!(
+            std::ptr::eq(self.tape, rhs.tape),
+            "nodes belong to different tapes"
+        );
+        self.record(2, [self.index, rhs.index], weights, x)
+    }
+
+    /// Propagates adjoints from this node back to every node on the tape.
+    pub fn backward(&self) -> Gradient {
+        let entries = self.tape.entries.borrow();
+        let mut adjoints = vec![0.0; entries.len()];
+        adjoints[self.index] = 1.0;
+
+        for i in (0..=self.index).rev() {
+            let entry = entries[i];
+            let adjoint = adjoints[i];
+            let parents = entry.parents.into_iter().zip(entry.weights);
+            for (parent, weight) in parents.take(entry.arity) {
+                adjoints[parent] += weight * adjoint;
+            }
+        }
+
+        Gradient { adjoints }
+    }
+
+    pub fn sin(self) -> Self {
+        self.unary(self.x.cos(), self.x.sin())
+    }
+    pub fn cos(self) -> Self {
+        self.unary(-self.x.sin(), self.x.cos())
+    }
+    pub fn tan(self) -> Self {
+        let cos = self.x.cos();
+        self.unary(1.0 / (cos * cos), self.x.tan())
+    }
+    pub fn ln(self) -> Self {
+        self.unary(1.0 / self.x, self.x.ln())
+    }
+    pub fn exp(self) -> Self {
+        let exp = self.x.exp();
+        self.unary(exp, exp)
+    }
+    pub fn powi(self, n: i32) -> Self {
+        self.unary((n as f32) * self.x.powi(n - 1), self.x.powi(n))
+    }
+}
+
+impl<'t> ops::Add for Node<'t> {
+    type Output = Node<'t>;
+    fn add(self, rhs: Self) -> Self::Output {
+        self.binary(rhs, [1.0, 1.0], self.x + rhs.x)
+    }
+}
+
+impl<'t> ops::Sub for Node<'t> {
+    type Output = Node<'t>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.binary(rhs, [1.0, -1.0], self.x - rhs.x)
+    }
+}
+
+impl<'t> ops::Mul for Node<'t> {
+    type Output = Node<'t>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.binary(rhs, [rhs.x, self.x], self.x * rhs.x)
+    }
+}
+
+impl<'t> ops::Mul<f32> for Node<'t> {
+    type Output = Node<'t>;
+    fn mul(self, rhs: f32) -> Self::Output {
+        self.unary(rhs, self.x * rhs)
+    }
+}
+
+impl<'t> ops::Mul<Node<'t>> for f32 {
+    type Output = Node<'t>;
+    fn mul(self, rhs: Node<'t>) -> Self::Output {
+        rhs.unary(self, self * rhs.x)
+    }
+}
+
+impl<'t> ops::Div for Node<'t> {
+    type Output = Node<'t>;
+    fn div(self, rhs: Self) -> Self::Output {
+        self.binary(
+            rhs,
+            [1.0 / rhs.x, -self.x / (rhs.x * rhs.x)],
+            self.x / rhs.x,
+        )
+    }
+}
+
+/// Adjoints of every node on the tape with respect to the node
+/// [`Node::backward`] was called on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    adjoints: Vec<f32>,
+}
+
+impl Gradient {
+    pub fn wrt(&self, node: &Node) -> f32 {
+        self.adjoints[node.index]
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::excessive_precision)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn algebraic_expression_gradient_ok() {
+        let tape = Tape::new();
+        let x = tape.var(24.0);
+        let a = tape.var(22.0);
+        let b = tape.var(2.0);
+        let result = x * a / (x * x + b);
+        let grad = result.backward();
+
+        assert_approx_eq!(result.value(), 0.9134948096885813);
+        assert_approx_eq!(grad.wrt(&x), -0.03779887692915554);
+        assert_approx_eq!(grad.wrt(&a), 24.0 / 578.0);
+        assert_approx_eq!(grad.wrt(&b), -24.0 * 22.0 / (578.0 * 578.0));
+    }
+
+    #[test]
+    fn mixed_expression_gradient_in_one_sweep_ok() {
+        let tape = Tape::new();
+        let x = tape.var(10.0);
+        let a = tape.var(2.0);
+        let c = tape.var(1.5);
+        fn expr<'t>(x: Node<'t>, a: Node<'t>, c: Node<'t>) -> Node<'t> {
+            ((a * x.powi(3) + a * c * x).powi(4) * (a * x).sin()) / (256.0 * a.powi(5) * x.powi(9))
+                - (c * x).cos()
+        }
+        let result = expr(x, a, c);
+        let grad = result.backward();
+
+        assert_approx_eq!(result.value(), 2.652201219184028);
+        assert_approx_eq!(grad.wrt(&x), 3.2126995936768443, 1e-4);
+        assert_approx_eq!(grad.wrt(&a), 7.513185262318497, 1e-4);
+        assert_approx_eq!(grad.wrt(&c), 6.577460206746546, 1e-4);
+    }
+
+    #[test]
+    fn reused_node_accumulates_adjoints() {
+        let tape = Tape::new();
+        let x = tape.var(3.0);
+        let y = x * x * x;
+        assert_eq!(y.backward().wrt(&x), 27.0);
+    }
+
+    #[test]
+    fn elementary_functions_match_forward_mode() {
+        let tape = Tape::new();
+        let x = tape.var(0.7);
+        let y = x.sin() + x.cos() + x.tan() + x.ln() + x.exp() + x.powi(3) - x * 2.0;
+        let forward = {
+            let x = crate::Var::new(0.7, 1.0);
+            x.sin() + x.cos() + x.tan() + x.ln() + x.exp() + x.powi(3) - x * 2.0
+        };
+
+        assert_approx_eq!(y.value(), forward.x);
+        assert_approx_eq!(y.backward().wrt(&x), forward.dx);
+    }
+
+    #[test]
+    fn infinite_adjoints_stay_infinite() {
+        // d/dx ln(sin(x)) = cot(x), which is infinite at 0
+        let tape = Tape::new();
+        let x = tape.var(0.0);
+        let y = x.sin().ln();
+        assert_eq!(y.backward().wrt(&x), f32::INFINITY);
+    }
+
+    #[test]
+    fn cleared_tape_is_empty() {
+        let mut tape = Tape::new();
+        let x = tape.var(1.0);
+        let _ = x.exp();
+        assert_eq!(tape.len(), 2);
+        tape.clear();
+        assert!(tape.is_empty());
+    }
+}
@@ -0,0 +1,406 @@
+//! Newton-Raphson root finding and implicit differentiation.
+//!
+//! Derivatives come from [`Var`], so only the residual has to be written. The
+//! implicit helpers differentiate a root `x*` of `f(x, θ) = 0` through the
+//! implicit function theorem instead of unrolling the solver iterations.
+use std::error::Error;
+use std::fmt;
+
+use crate::diff::jacobian;
+use crate::Var;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolveOptions {
+    pub max_iters: usize,
+    /// Converged once every residual component is at most this in magnitude.
+    pub tol: f64,
+}
+
+impl Default for SolveOptions {
+    fn default() -> Self {
+        Self {
+            max_iters: 100,
+            tol: 1e-12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolveError {
+    /// The derivative or Jacobian can't be inverted at the current iterate.
+    Singular,
+    /// The line search couldn't reduce the residual along the Newton step.
+    LineSearchFailed,
+    NoConvergence { residual: f64 },
+    /// The system doesn't have one residual per unknown.
+    NotSquare { unknowns: usize, residuals: usize },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::Singular => write!(f, "singular jacobian"),
+            SolveError::LineSearchFailed => write!(f, "line search failed to reduce the residual"),
+            SolveError::NoConvergence { residual } => {
+                write!(f, "no convergence, residual {residual}")
+            }
+            SolveError::NotSquare {
+                unknowns,
+                residuals,
+            } => write!(f, "{residuals} residuals for {unknowns} unknowns"),
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Root<X> {
+    pub x: X,
+    /// Largest residual component at `x`.
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+const MAX_HALVINGS: usize = 30;
+const SUFFICIENT_DECREASE: f64 = 1e-4;
+
+/// Root of a scalar function starting from `x0`.
+pub fn newton(
+    f: impl Fn(Var<f64>) -> Var<f64>,
+    x0: f64,
+    options: &SolveOptions,
+) -> Result<Root<f64>, SolveError> {
+    let mut x = x0;
+    let mut y = f(Var::variable(x));
+
+    for iterations in 0..=options.max_iters {
+        if y.x.abs() <= options.tol {
+            return Ok(Root {
+                x,
+                residual: y.x.abs(),
+                iterations,
+            });
+        }
+        if iterations == options.max_iters {
+            break;
+        }
+        if y.dx == 0.0 || !y.dx.is_finite() {
+            return Err(SolveError::Singular);
+        }
+
+        let direction = -y.x / y.dx;
+        let mut step = 1.0;
+        let mut halvings = 0;
+        let (next_x, next_y) = loop {
+            let next_x = x + step * direction;
+            let next_y = f(Var::variable(next_x));
+            if next_y.x.abs() <= (1.0 - SUFFICIENT_DECREASE * step) * y.x.abs() {
+                break (next_x, next_y);
+            }
+            halvings += 1;
+            if halvings == MAX_HALVINGS {
+                return Err(SolveError::LineSearchFailed);
+            }
+            step *= 0.5;
+        };
+        x = next_x;
+        y = next_y;
+    }
+
+    Err(SolveError::NoConvergence {
+        residual: y.x.abs(),
+    })
+}
+
+/// Root of a square system `f(x) = 0` starting from `x0`.
+pub fn newton_system(
+    f: impl Fn(&[Var<f64>]) -> Vec<Var<f64>>,
+    x0: &[f64],
+    options: &SolveOptions,
+) -> Result<Root<Vec<f64>>, SolveError> {
+    let residual_at = |x: &[f64]| -> Vec<f64> {
+        let inputs: Vec<Var<f64>> = x.iter().map(|&x| Var::constant(x)).collect();
+        f(&inputs).iter().map(|y| y.x).collect()
+    };
+    let mut x = x0.to_vec();
+    let mut residual = residual_at(&x);
+    check_square(x.len(), residual.len())?;
+
+    for iterations in 0..=options.max_iters {
+        if max_norm(&residual) <= options.tol {
+            return Ok(Root {
+                x,
+                residual: max_norm(&residual),
+                iterations,
+            });
+        }
+        if iterations == options.max_iters {
+            break;
+        }
+
+        let minus_residual: Vec<f64> = residual.iter().map(|r| -r).collect();
+        let direction =
+            solve_linear(jacobian(&f, &x), minus_residual).ok_or(SolveError::Singular)?;
+
+        // backtrack on the euclidean norm of the residual
+        let norm = euclidean_norm(&residual);
+        let mut step = 1.0;
+        let mut halvings = 0;
+        let (next_x, next_residual) = loop {
+            let next_x: Vec<f64> = x.iter().zip(&direction).map(|(x, d)| x + step * d).collect();
+            let next_residual = residual_at(&next_x);
+            if euclidean_norm(&next_residual) <= (1.0 - SUFFICIENT_DECREASE * step) * norm {
+                break (next_x, next_residual);
+            }
+            halvings += 1;
+            if halvings == MAX_HALVINGS {
+                return Err(SolveError::LineSearchFailed);
+            }
+            step *= 0.5;
+        };
+        x = next_x;
+        residual = next_residual;
+    }
+
+    Err(SolveError::NoConvergence {
+        residual: max_norm(&residual),
+    })
+}
+
+/// `dx*/dθ` for a root `x*` of the scalar equation `f(x, θ) = 0`.
+pub fn implicit_derivative(
+    f: impl Fn(Var<f64>, Var<f64>) -> Var<f64>,
+    x_star: f64,
+    theta: f64,
+) -> Result<f64, SolveError> {
+    let df_dx = f(Var::variable(x_star), Var::constant(theta)).dx;
+    let df_dtheta = f(Var::constant(x_star), Var::variable(theta)).dx;
+    if df_dx == 0.0 {
+        return Err(SolveError::Singular);
+    }
+    Ok(-df_dtheta / df_dx)
+}
+
+/// `dx*/dθ` for a root `x*` of the system `f(x, θ) = 0`, with
+/// `result[i][j]` the derivative of `x*[i]` by `θ[j]`.
+pub fn implicit_jacobian(
+    f: impl Fn(&[Var<f64>], &[Var<f64>]) -> Vec<Var<f64>>,
+    x_star: &[f64],
+    theta: &[f64],
+) -> Result<Vec<Vec<f64>>, SolveError> {
+    let n = x_star.len();
+    let joint: Vec<f64> = x_star.iter().chain(theta).copied().collect();
+    let full = jacobian(|v: &[Var<f64>]| f(&v[..n], &v[n..]), &joint);
+    check_square(n, full.len())?;
+    let jx: Vec<Vec<f64>> = full.iter().map(|row| row[..n].to_vec()).collect();
+
+    // solve J_x * column = -J_θ column by column
+    let columns = (0..theta.len())
+        .map(|j| {
+            let rhs = full.iter().map(|row| -row[n + j]).collect();
+            solve_linear(jx.clone(), rhs).ok_or(SolveError::Singular)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((0..n)
+        .map(|i| columns.iter().map(|column| column[i]).collect())
+        .collect())
+}
+
+/// Solves `f(x, θ) = 0` for `x` and carries the tangent of `theta` through
+/// the root, so the result can feed further forward-mode computations.
+pub fn differentiable_root(
+    f: impl Fn(Var<f64>, Var<f64>) -> Var<f64>,
+    x0: f64,
+    theta: Var<f64>,
+    options: &SolveOptions,
+) -> Result<Var<f64>, SolveError> {
+    let root = newton(|x| f(x, Var::constant(theta.x)), x0, options)?;
+    let dx_dtheta = implicit_derivative(&f, root.x, theta.x)?;
+    Ok(Var::new(root.x, dx_dtheta * theta.dx))
+}
+
+fn check_square(unknowns: usize, residuals: usize) -> Result<(), SolveError> {
+    if unknowns != residuals {
+        return Err(SolveError::NotSquare {
+            unknowns,
+            residuals,
+        });
+    }
+    Ok(())
+}
+
+fn max_norm(v: &[f64]) -> f64 {
+    v.iter().fold(0.0, |acc, x| acc.max(x.abs()))
+}
+
+fn euclidean_norm(v: &[f64]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+// gaussian elimination with partial pivoting, None if `a` is singular to
+// working precision. Pivots are compared with the largest entry, so scaling
+// the system doesn't change the answer
+fn solve_linear(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    let scale = a.iter().flatten().fold(0.0_f64, |acc, x| acc.max(x.abs()));
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= n as f64 * f64::EPSILON * scale {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let (pivot_rows, rows_below) = a.split_at_mut(col + 1);
+        let pivot_row = &pivot_rows[col];
+        for (offset, row) in rows_below.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            for (value, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[col + 1 + offset] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn newton_finds_square_root() {
+        let root = newton(|x| x * x - 2.0, 1.0, &SolveOptions::default()).unwrap();
+        assert_approx_eq!(root.x, 2.0_f64.sqrt(), 1e-12);
+        assert!(root.iterations < 10);
+    }
+
+    #[test]
+    fn newton_finds_fixed_point_of_cos() {
+        let root = newton(|x| x.cos() - x, 0.0, &SolveOptions::default()).unwrap();
+        assert_approx_eq!(root.x.cos(), root.x, 1e-12);
+    }
+
+    #[test]
+    fn line_search_tames_overshooting_atan() {
+        // plain newton diverges on atan from |x0| > 1.39
+        let root = newton(Var::atan, 3.0, &SolveOptions::default()).unwrap();
+        assert_approx_eq!(root.x, 0.0, 1e-12);
+    }
+
+    #[test]
+    fn newton_reports_singular_derivative() {
+        let result = newton(|x| x * x + 1.0, 0.0, &SolveOptions::default());
+        assert_eq!(result, Err(SolveError::Singular));
+    }
+
+    #[test]
+    fn newton_reports_missing_root() {
+        let result = newton(|x| x * x + 1.0, 1.0, &SolveOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn newton_reports_no_convergence_when_out_of_iterations() {
+        let options = SolveOptions {
+            max_iters: 2,
+            ..SolveOptions::default()
+        };
+        let result = newton(|x| x.exp() - 1000.0, 0.0, &options);
+        assert!(matches!(result, Err(SolveError::NoConvergence { .. })));
+    }
+
+    #[test]
+    fn newton_system_intersects_circle_and_line() {
+        // x^2 + y^2 = 4, y = x
+        let f = |v: &[Var<f64>]| vec![v[0] * v[0] + v[1] * v[1] - 4.0, v[1] - v[0]];
+        let root = newton_system(f, &[1.0, 0.5], &SolveOptions::default()).unwrap();
+        assert_approx_eq!(root.x[0], 2.0_f64.sqrt(), 1e-12);
+        assert_approx_eq!(root.x[1], 2.0_f64.sqrt(), 1e-12);
+    }
+
+    #[test]
+    fn newton_system_reports_singular_jacobian() {
+        let f = |v: &[Var<f64>]| vec![v[0] + v[1] - 1.0, 2.0 * v[0] + 2.0 * v[1]];
+        let result = newton_system(f, &[0.0, 0.0], &SolveOptions::default());
+        assert_eq!(result, Err(SolveError::Singular));
+    }
+
+    #[test]
+    fn newton_system_solves_badly_scaled_systems() {
+        let f = |v: &[Var<f64>]| vec![1e-20 * (v[0] - 1.0), 1e-20 * (v[0] + v[1])];
+        let options = SolveOptions {
+            tol: 1e-32,
+            ..SolveOptions::default()
+        };
+        let root = newton_system(f, &[0.0, 0.0], &options).unwrap();
+        assert_approx_eq!(root.x[0], 1.0, 1e-12);
+        assert_approx_eq!(root.x[1], -1.0, 1e-12);
+    }
+
+    #[test]
+    fn non_square_systems_are_errors() {
+        let f = |v: &[Var<f64>]| vec![v[0] + v[1] - 1.0];
+        let result = newton_system(f, &[0.0, 0.0], &SolveOptions::default());
+        assert_eq!(
+            result,
+            Err(SolveError::NotSquare {
+                unknowns: 2,
+                residuals: 1
+            })
+        );
+        let g = |x: &[Var<f64>], theta: &[Var<f64>]| vec![x[0] - theta[0], x[0] + theta[0]];
+        let result = implicit_jacobian(g, &[1.0], &[1.0]);
+        assert!(matches!(result, Err(SolveError::NotSquare { .. })));
+    }
+
+    #[test]
+    fn implicit_derivative_matches_finite_difference_of_solves() {
+        // x^3 + θ x - 1 = 0
+        let f = |x: Var<f64>, theta: Var<f64>| x.powi(3) + theta * x - 1.0;
+        let solve = |theta: f64| {
+            newton(|x| f(x, Var::constant(theta)), 1.0, &SolveOptions::default())
+                .unwrap()
+                .x
+        };
+
+        let theta = 0.7;
+        let x_star = solve(theta);
+        let d = implicit_derivative(f, x_star, theta).unwrap();
+        assert_approx_eq!(d, -x_star / (3.0 * x_star * x_star + theta), 1e-12);
+
+        let h = 1e-6;
+        assert_approx_eq!(d, (solve(theta + h) - solve(theta - h)) / (2.0 * h), 1e-6);
+    }
+
+    #[test]
+    fn implicit_jacobian_of_linear_system_ok() {
+        // x0 + x1 = θ0, x0 - x1 = θ1
+        let f = |x: &[Var<f64>], theta: &[Var<f64>]| {
+            vec![x[0] + x[1] - theta[0], x[0] - x[1] - theta[1]]
+        };
+        let j = implicit_jacobian(f, &[2.0, 1.0], &[3.0, 1.0]).unwrap();
+        assert_eq!(j, vec![vec![0.5, 0.5], vec![0.5, -0.5]]);
+    }
+
+    #[test]
+    fn differentiable_root_carries_tangent_through_solver() {
+        // root of x^2 - θ is sqrt(θ), composed with sin afterwards
+        let theta = Var::variable(2.0);
+        let x = differentiable_root(|x, t| x * x - t, 1.0, theta, &SolveOptions::default()).unwrap();
+        let y = x.sin();
+
+        let sqrt = 2.0_f64.sqrt();
+        assert_approx_eq!(x.x, sqrt, 1e-12);
+        assert_approx_eq!(y.dx, sqrt.cos() / (2.0 * sqrt), 1e-12);
+    }
+}
@@ -0,0 +1,824 @@
+//! Symbolic expression trees.
+//!
+//! [`Expr`] is built with the same operators and elementary functions as
+//! [`crate::Var`], but records the formula instead of its value. It can be
+//! differentiated symbolically, simplified, printed as infix or LaTeX and
+//! evaluated with any [`Scalar`], including `Var` itself to cross-check the
+//! symbolic derivative against forward mode.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops;
+use std::rc::Rc;
+
+use crate::Scalar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Ln,
+    Exp,
+    Sqrt,
+    Abs,
+    /// -1, 0 or 1, shows up in derivatives of `abs`, `min` and `max`.
+    Sign,
+    Tanh,
+    Sigmoid,
+    Sinh,
+    Cosh,
+    Asin,
+    Acos,
+    Atan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func2 {
+    Pow,
+    Atan2,
+    Log,
+    Min,
+    Max,
+    Hypot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(f64),
+    Symbol(String),
+    Neg(Rc<Expr>),
+    Add(Rc<Expr>, Rc<Expr>),
+    Sub(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+    Div(Rc<Expr>, Rc<Expr>),
+    Powi(Rc<Expr>, i32),
+    Call(Func, Rc<Expr>),
+    Call2(Func2, Rc<Expr>, Rc<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    UnboundSymbol(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnboundSymbol(name) => write!(f, "no value for symbol `{name}`"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Func {
+    pub fn name(self) -> &'static str {
+        match self {
+            Func::Sin => "sin",
+            Func::Cos => "cos",
+            Func::Tan => "tan",
+            Func::Ln => "ln",
+            Func::Exp => "exp",
+            Func::Sqrt => "sqrt",
+            Func::Abs => "abs",
+            Func::Sign => "sign",
+            Func::Tanh => "tanh",
+            Func::Sigmoid => "sigmoid",
+            Func::Sinh => "sinh",
+            Func::Cosh => "cosh",
+            Func::Asin => "asin",
+            Func::Acos => "acos",
+            Func::Atan => "atan",
+        }
+    }
+
+    pub const ALL: [Func; 15] = [
+        Func::Sin,
+        Func::Cos,
+        Func::Tan,
+        Func::Ln,
+        Func::Exp,
+        Func::Sqrt,
+        Func::Abs,
+        Func::Sign,
+        Func::Tanh,
+        Func::Sigmoid,
+        Func::Sinh,
+        Func::Cosh,
+        Func::Asin,
+        Func::Acos,
+        Func::Atan,
+    ];
+
+    fn apply<T: Scalar>(self, x: T) -> T {
+        match self {
+            Func::Sin => x.sin(),
+            Func::Cos => x.cos(),
+            Func::Tan => x.tan(),
+            Func::Ln => x.ln(),
+            Func::Exp => x.exp(),
+            Func::Sqrt => x.sqrt(),
+            Func::Abs => x.abs(),
+            Func::Sign => T::from_f64(sign(x.to_f64())),
+            Func::Tanh => x.tanh(),
+            Func::Sigmoid => x.sigmoid(),
+            Func::Sinh => x.sinh(),
+            Func::Cosh => x.cosh(),
+            Func::Asin => x.asin(),
+            Func::Acos => x.acos(),
+            Func::Atan => x.atan(),
+        }
+    }
+}
+
+impl Func2 {
+    pub fn name(self) -> &'static str {
+        match self {
+            Func2::Pow => "pow",
+            Func2::Atan2 => "atan2",
+            Func2::Log => "log",
+            Func2::Min => "min",
+            Func2::Max => "max",
+            Func2::Hypot => "hypot",
+        }
+    }
+
+    pub const ALL: [Func2; 6] = [
+        Func2::Pow,
+        Func2::Atan2,
+        Func2::Log,
+        Func2::Min,
+        Func2::Max,
+        Func2::Hypot,
+    ];
+
+    fn apply<T: Scalar>(self, a: T, b: T) -> T {
+        match self {
+            Func2::Pow => a.powf(b),
+            Func2::Atan2 => a.atan2(b),
+            Func2::Log => a.log(b),
+            Func2::Min => a.min(b),
+            Func2::Max => a.max(b),
+            Func2::Hypot => a.hypot(b),
+        }
+    }
+}
+
+fn sign(x: f64) -> f64 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else if x == 0.0 {
+        0.0
+    } else {
+        f64::NAN
+    }
+}
+
+impl Expr {
+    pub fn symbol(name: &str) -> Self {
+        Expr::Symbol(name.to_string())
+    }
+
+    pub fn constant(x: f64) -> Self {
+        Expr::Const(x)
+    }
+
+    pub(crate) fn call(&self, func: Func) -> Self {
+        Expr::Call(func, Rc::new(self.clone()))
+    }
+
+    pub(crate) fn call2(&self, func: Func2, other: &Expr) -> Self {
+        Expr::Call2(func, Rc::new(self.clone()), Rc::new(other.clone()))
+    }
+
+    pub fn sin(&self) -> Self {
+        self.call(Func::Sin)
+    }
+    pub fn cos(&self) -> Self {
+        self.call(Func::Cos)
+    }
+    pub fn tan(&self) -> Self {
+        self.call(Func::Tan)
+    }
+    pub fn ln(&self) -> Self {
+        self.call(Func::Ln)
+    }
+    pub fn exp(&self) -> Self {
+        self.call(Func::Exp)
+    }
+    pub fn sqrt(&self) -> Self {
+        self.call(Func::Sqrt)
+    }
+    pub fn abs(&self) -> Self {
+        self.call(Func::Abs)
+    }
+    pub fn sign(&self) -> Self {
+        self.call(Func::Sign)
+    }
+    pub fn tanh(&self) -> Self {
+        self.call(Func::Tanh)
+    }
+    pub fn sigmoid(&self) -> Self {
+        self.call(Func::Sigmoid)
+    }
+    pub fn sinh(&self) -> Self {
+        self.call(Func::Sinh)
+    }
+    pub fn cosh(&self) -> Self {
+        self.call(Func::Cosh)
+    }
+    pub fn asin(&self) -> Self {
+        self.call(Func::Asin)
+    }
+    pub fn acos(&self) -> Self {
+        self.call(Func::Acos)
+    }
+    pub fn atan(&self) -> Self {
+        self.call(Func::Atan)
+    }
+    pub fn powi(&self, n: i32) -> Self {
+        Expr::Powi(Rc::new(self.clone()), n)
+    }
+    pub fn powf(&self, n: &Expr) -> Self {
+        self.call2(Func2::Pow, n)
+    }
+    pub fn atan2(&self, x: &Expr) -> Self {
+        self.call2(Func2::Atan2, x)
+    }
+    pub fn log(&self, base: &Expr) -> Self {
+        self.call2(Func2::Log, base)
+    }
+    pub fn min(&self, other: &Expr) -> Self {
+        self.call2(Func2::Min, other)
+    }
+    pub fn max(&self, other: &Expr) -> Self {
+        self.call2(Func2::Max, other)
+    }
+    pub fn hypot(&self, other: &Expr) -> Self {
+        self.call2(Func2::Hypot, other)
+    }
+
+    /// Names of all symbols in order of first appearance.
+    pub fn symbols(&self) -> Vec<String> {
+        fn collect(expr: &Expr, names: &mut Vec<String>) {
+            match expr {
+                Expr::Const(_) => {}
+                Expr::Symbol(name) => {
+                    if !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+                Expr::Neg(a) | Expr::Powi(a, _) | Expr::Call(_, a) => collect(a, names),
+                Expr::Add(a, b)
+                | Expr::Sub(a, b)
+                | Expr::Mul(a, b)
+                | Expr::Div(a, b)
+                | Expr::Call2(_, a, b) => {
+                    collect(a, names);
+                    collect(b, names);
+                }
+            }
+        }
+
+        let mut names = vec![];
+        collect(self, &mut names);
+        names
+    }
+
+    /// Evaluates with symbols looked up in `env`. Evaluating with
+    /// `T = Var<f64>` gives forward-mode derivatives of the same formula.
+    pub fn eval<T: Scalar>(&self, env: &HashMap<String, T>) -> Result<T, EvalError> {
+        Ok(match self {
+            Expr::Const(c) => T::from_f64(*c),
+            Expr::Symbol(name) => *env
+                .get(name)
+                .ok_or_else(|| EvalError::UnboundSymbol(name.clone()))?,
+            Expr::Neg(a) => -a.eval(env)?,
+            Expr::Add(a, b) => a.eval(env)? + b.eval(env)?,
+            Expr::Sub(a, b) => a.eval(env)? - b.eval(env)?,
+            Expr::Mul(a, b) => a.eval(env)? * b.eval(env)?,
+            Expr::Div(a, b) => a.eval(env)? / b.eval(env)?,
+            Expr::Powi(a, n) => a.eval(env)?.powi(*n),
+            Expr::Call(func, a) => func.apply(a.eval(env)?),
+            Expr::Call2(func, a, b) => func.apply(a.eval(env)?, b.eval(env)?),
+        })
+    }
+
+    /// Simplified symbolic derivative with respect to the symbol `name`.
+    pub fn diff(&self, name: &str) -> Expr {
+        self.derivative(name).simplify()
+    }
+
+    fn derivative(&self, name: &str) -> Expr {
+        let one = || Expr::Const(1.0);
+        let two = || Expr::Const(2.0);
+        match self {
+            Expr::Const(_) => Expr::Const(0.0),
+            Expr::Symbol(s) => Expr::Const(if s == name { 1.0 } else { 0.0 }),
+            Expr::Neg(a) => -a.derivative(name),
+            Expr::Add(a, b) => a.derivative(name) + b.derivative(name),
+            Expr::Sub(a, b) => a.derivative(name) - b.derivative(name),
+            Expr::Mul(a, b) => {
+                a.derivative(name) * b.as_ref().clone() + a.as_ref().clone() * b.derivative(name)
+            }
+            Expr::Div(a, b) => {
+                (a.derivative(name) * b.as_ref().clone() - a.as_ref().clone() * b.derivative(name))
+                    / b.powi(2)
+            }
+            Expr::Powi(a, n) => Expr::Const(*n as f64) * a.powi(n - 1) * a.derivative(name),
+            Expr::Call(func, a) => {
+                let u = a.as_ref();
+                let du = a.derivative(name);
+                let outer = match func {
+                    Func::Sin => u.cos(),
+                    Func::Cos => -u.sin(),
+                    Func::Tan => one() / u.cos().powi(2),
+                    Func::Ln => one() / u.clone(),
+                    Func::Exp => u.exp(),
+                    Func::Sqrt => one() / (two() * u.sqrt()),
+                    Func::Abs => u.sign(),
+                    Func::Sign => Expr::Const(0.0),
+                    Func::Tanh => one() - u.tanh().powi(2),
+                    Func::Sigmoid => u.sigmoid() * (one() - u.sigmoid()),
+                    Func::Sinh => u.cosh(),
+                    Func::Cosh => u.sinh(),
+                    Func::Asin => one() / (one() - u.powi(2)).sqrt(),
+                    Func::Acos => -(one() / (one() - u.powi(2)).sqrt()),
+                    Func::Atan => one() / (one() + u.powi(2)),
+                };
+                outer * du
+            }
+            Expr::Call2(func, a, b) => {
+                let (u, v) = (a.as_ref(), b.as_ref());
+                let (du, dv) = (a.derivative(name), b.derivative(name));
+                match func {
+                    Func2::Pow => {
+                        if dv.clone().simplify() == Expr::Const(0.0) {
+                            v.clone() * u.powf(&(v.clone() - one())) * du
+                        } else {
+                            self.clone() * (dv * u.ln() + v.clone() * du / u.clone())
+                        }
+                    }
+                    Func2::Atan2 => {
+                        (v.clone() * du - u.clone() * dv) / (u.powi(2) + v.powi(2))
+                    }
+                    Func2::Log => (u.ln() / v.ln()).derivative(name),
+                    // weight w = 1 picks du and w = 0 picks dv exactly, w = 1/2
+                    // averages at a tie like Var
+                    Func2::Min | Func2::Max => {
+                        let side = (u.clone() - v.clone()).sign();
+                        let side = if *func == Func2::Min { -side } else { side };
+                        let w = (one() + side) / two();
+                        w.clone() * du + (one() - w) * dv
+                    }
+                    // zero at the origin like Var instead of 0 / 0, sign(h)
+                    // is 0 there and 1 everywhere else
+                    Func2::Hypot => {
+                        let nonzero = self.sign();
+                        nonzero.clone() * (u.clone() * du + v.clone() * dv)
+                            / (self.clone() + (one() - nonzero))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Folds constants and removes neutral elements such as `x * 1`, `x + 0`
+    /// and `--x`, bottom up.
+    pub fn simplify(&self) -> Expr {
+        use Expr::*;
+
+        let node = match self {
+            Const(_) | Symbol(_) => return self.clone(),
+            Neg(a) => -a.simplify(),
+            Add(a, b) => a.simplify() + b.simplify(),
+            Sub(a, b) => a.simplify() - b.simplify(),
+            Mul(a, b) => a.simplify() * b.simplify(),
+            Div(a, b) => a.simplify() / b.simplify(),
+            Powi(a, n) => a.simplify().powi(*n),
+            Call(func, a) => a.simplify().call(*func),
+            Call2(func, a, b) => a.simplify().call2(*func, &b.simplify()),
+        };
+        node.simplify_node()
+    }
+
+    // one folding step for a node whose operands are already simplified, a
+    // rewrite only folds the new node again and never revisits the operands
+    fn simplify_node(self) -> Expr {
+        use Expr::*;
+
+        match self {
+            Neg(a) => match a.as_ref() {
+                Const(c) => Const(-c),
+                Neg(inner) => inner.as_ref().clone(),
+                _ => Neg(a),
+            },
+            Add(a, b) => match (a.as_ref(), b.as_ref()) {
+                (Const(x), Const(y)) => Const(x + y),
+                (Const(z), e) | (e, Const(z)) if *z == 0.0 => e.clone(),
+                (a, Neg(b)) => (a - b.as_ref()).simplify_node(),
+                _ => Add(a, b),
+            },
+            Sub(a, b) => match (a.as_ref(), b.as_ref()) {
+                (Const(x), Const(y)) => Const(x - y),
+                (e, Const(0.0)) => e.clone(),
+                (Const(0.0), e) => (-e).simplify_node(),
+                (a, b) if a == b => Const(0.0),
+                (a, Neg(b)) => (a + b.as_ref()).simplify_node(),
+                _ => Sub(a, b),
+            },
+            Mul(a, b) => match (a.as_ref(), b.as_ref()) {
+                (Const(x), Const(y)) => Const(x * y),
+                (Const(z), _) | (_, Const(z)) if *z == 0.0 => Const(0.0),
+                (Const(o), e) | (e, Const(o)) if *o == 1.0 => e.clone(),
+                (Const(m), e) | (e, Const(m)) if *m == -1.0 => (-e).simplify_node(),
+                // keep constants in front and merge them
+                (e, Const(c)) => (Const(*c) * e.clone()).simplify_node(),
+                (Const(x), Mul(c, e)) if matches!(c.as_ref(), Const(_)) => {
+                    let Const(y) = c.as_ref() else { unreachable!() };
+                    Const(x * y) * e.as_ref().clone()
+                }
+                (Neg(a), Neg(b)) => (a.as_ref() * b.as_ref()).simplify_node(),
+                (a, Div(one, b)) if **one == Const(1.0) => (a / b.as_ref()).simplify_node(),
+                (a, b) if a == b => a.powi(2),
+                _ => Mul(a, b),
+            },
+            Div(a, b) => match (a.as_ref(), b.as_ref()) {
+                (Const(x), Const(y)) => Const(x / y),
+                (Const(0.0), _) => Const(0.0),
+                (e, Const(1.0)) => e.clone(),
+                (a, b) if a == b => Const(1.0),
+                _ => Div(a, b),
+            },
+            Powi(a, n) => match (a.as_ref(), n) {
+                (_, 0) => Const(1.0),
+                (e, 1) => e.clone(),
+                (Const(x), n) => Const(x.powi(n)),
+                // left nested when the combined exponent overflows
+                (Powi(e, m), n) if m.checked_mul(n).is_some() => e.powi(m * n),
+                _ => Powi(a, n),
+            },
+            Call(func, a) => match a.as_ref() {
+                Const(x) => Const(func.apply(*x)),
+                _ => Call(func, a),
+            },
+            Call2(func, a, b) => match (a.as_ref(), b.as_ref()) {
+                (Const(x), Const(y)) => Const(func.apply(*x, *y)),
+                (e, Const(n)) if func == Func2::Pow && *n == 1.0 => e.clone(),
+                (e, Const(n)) if func == Func2::Pow && n.fract() == 0.0 && n.abs() <= 64.0 => {
+                    e.powi(*n as i32)
+                }
+                _ => Call2(func, a, b),
+            },
+            node => node,
+        }
+    }
+
+    /// Renders the expression as LaTeX math.
+    pub fn to_latex(&self) -> String {
+        let wrap = |expr: &Expr, min: u8| {
+            if expr.precedence() < min {
+                format!("\\left({}\\right)", expr.to_latex())
+            } else {
+                expr.to_latex()
+            }
+        };
+
+        match self {
+            Expr::Const(c) => format!("{c}"),
+            Expr::Symbol(name) => name.clone(),
+            Expr::Neg(a) => format!("-{}", wrap(a, POWER)),
+            Expr::Add(a, b) => format!("{} + {}", a.to_latex(), wrap(b, PRODUCT)),
+            Expr::Sub(a, b) => format!("{} - {}", a.to_latex(), wrap(b, PRODUCT)),
+            Expr::Mul(a, b) => format!("{} \\cdot {}", wrap(a, PRODUCT), wrap(b, NEGATION)),
+            Expr::Div(a, b) => format!("\\frac{{{}}}{{{}}}", a.to_latex(), b.to_latex()),
+            Expr::Powi(a, n) => format!("{{{}}}^{{{n}}}", wrap(a, ATOM)),
+            Expr::Call(func, a) => match func {
+                Func::Sqrt => format!("\\sqrt{{{}}}", a.to_latex()),
+                Func::Abs => format!("\\left|{}\\right|", a.to_latex()),
+                Func::Exp => format!("e^{{{}}}", a.to_latex()),
+                Func::Sigmoid => format!("\\sigma\\left({}\\right)", a.to_latex()),
+                Func::Sign => format!("\\operatorname{{sign}}\\left({}\\right)", a.to_latex()),
+                Func::Asin => format!("\\arcsin\\left({}\\right)", a.to_latex()),
+                Func::Acos => format!("\\arccos\\left({}\\right)", a.to_latex()),
+                Func::Atan => format!("\\arctan\\left({}\\right)", a.to_latex()),
+                _ => format!("\\{}\\left({}\\right)", func.name(), a.to_latex()),
+            },
+            Expr::Call2(func, a, b) => match func {
+                Func2::Pow => format!("{{{}}}^{{{}}}", wrap(a, ATOM), b.to_latex()),
+                Func2::Log => format!("\\log_{{{}}}\\left({}\\right)", b.to_latex(), a.to_latex()),
+                Func2::Min | Func2::Max => format!(
+                    "\\{}\\left({}, {}\\right)",
+                    func.name(),
+                    a.to_latex(),
+                    b.to_latex()
+                ),
+                Func2::Atan2 | Func2::Hypot => format!(
+                    "\\operatorname{{{}}}\\left({}, {}\\right)",
+                    func.name(),
+                    a.to_latex(),
+                    b.to_latex()
+                ),
+            },
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Add(..) | Expr::Sub(..) => SUM,
+            Expr::Mul(..) | Expr::Div(..) => PRODUCT,
+            Expr::Neg(_) => NEGATION,
+            Expr::Const(c) if *c < 0.0 => NEGATION,
+            Expr::Powi(..) | Expr::Call2(Func2::Pow, ..) => POWER,
+            _ => ATOM,
+        }
+    }
+}
+
+// binding strength used to decide where parentheses are needed
+const SUM: u8 = 1;
+const PRODUCT: u8 = 2;
+const NEGATION: u8 = 3;
+const POWER: u8 = 4;
+const ATOM: u8 = 5;
+
+/// Infix notation that [`crate::parser::parse`] reads back into the same tree.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wrap = |expr: &Expr, min: u8| {
+            if expr.precedence() < min {
+                format!("({expr})")
+            } else {
+                format!("{expr}")
+            }
+        };
+
+        match self {
+            Expr::Const(c) => write!(f, "{c}"),
+            Expr::Symbol(name) => write!(f, "{name}"),
+            Expr::Neg(a) => write!(f, "-{}", wrap(a, POWER)),
+            // right operands of the same precedence keep their parentheses so
+            // the printed form parses back left-associatively into this tree
+            Expr::Add(a, b) => write!(f, "{} + {}", a, wrap(b, PRODUCT)),
+            Expr::Sub(a, b) => write!(f, "{} - {}", a, wrap(b, PRODUCT)),
+            Expr::Mul(a, b) => write!(f, "{} * {}", wrap(a, PRODUCT), wrap(b, NEGATION)),
+            Expr::Div(a, b) => write!(f, "{} / {}", wrap(a, PRODUCT), wrap(b, NEGATION)),
+            Expr::Powi(a, n) if *n < 0 => write!(f, "{}^({n})", wrap(a, ATOM)),
+            Expr::Powi(a, n) => write!(f, "{}^{n}", wrap(a, ATOM)),
+            Expr::Call(func, a) => write!(f, "{}({a})", func.name()),
+            Expr::Call2(Func2::Pow, a, b) => write!(f, "{}^{}", wrap(a, ATOM), wrap(b, POWER)),
+            Expr::Call2(func, a, b) => write!(f, "{}({a}, {b})", func.name()),
+        }
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(x: f64) -> Self {
+        Expr::Const(x)
+    }
+}
+
+macro_rules! impl_expr_op {
+    ($op:ident, $op_fn:ident, $variant:ident) => {
+        impl ops::$op for Expr {
+            type Output = Expr;
+            fn $op_fn(self, rhs: Expr) -> Self::Output {
+                Expr::$variant(Rc::new(self), Rc::new(rhs))
+            }
+        }
+
+        impl ops::$op<&Expr> for &Expr {
+            type Output = Expr;
+            fn $op_fn(self, rhs: &Expr) -> Self::Output {
+                Expr::$variant(Rc::new(self.clone()), Rc::new(rhs.clone()))
+            }
+        }
+
+        impl ops::$op<f64> for Expr {
+            type Output = Expr;
+            fn $op_fn(self, rhs: f64) -> Self::Output {
+                Expr::$variant(Rc::new(self), Rc::new(Expr::Const(rhs)))
+            }
+        }
+
+        impl ops::$op<Expr> for f64 {
+            type Output = Expr;
+            fn $op_fn(self, rhs: Expr) -> Self::Output {
+                Expr::$variant(Rc::new(Expr::Const(self)), Rc::new(rhs))
+            }
+        }
+    };
+}
+
+impl_expr_op!(Add, add, Add);
+impl_expr_op!(Sub, sub, Sub);
+impl_expr_op!(Mul, mul, Mul);
+impl_expr_op!(Div, div, Div);
+
+impl ops::Neg for Expr {
+    type Output = Expr;
+    fn neg(self) -> Self::Output {
+        Expr::Neg(Rc::new(self))
+    }
+}
+
+impl ops::Neg for &Expr {
+    type Output = Expr;
+    fn neg(self) -> Self::Output {
+        Expr::Neg(Rc::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Var;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn env<T: Scalar>(values: &[(&str, T)]) -> HashMap<String, T> {
+        values.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn operators_build_tree() {
+        let x = Expr::symbol("x");
+        let a = Expr::symbol("a");
+        let b = Expr::symbol("b");
+        let expr = &x * &a / (&x * &x + b);
+        assert_eq!(expr.to_string(), "x * a / (x * x + b)");
+        assert_eq!(expr.symbols(), vec!["x", "a", "b"]);
+    }
+
+    #[test]
+    fn eval_matches_var_on_algebraic_expression() {
+        let x = Expr::symbol("x");
+        let a = Expr::symbol("a");
+        let b = Expr::symbol("b");
+        let expr = &x * &a / (&x * &x + b);
+
+        let value = expr.eval(&env(&[("x", 24.0), ("a", 22.0), ("b", 2.0)])).unwrap();
+        let dual = expr
+            .eval(&env(&[
+                ("x", Var::variable(24.0)),
+                ("a", Var::constant(22.0)),
+                ("b", Var::constant(2.0)),
+            ]))
+            .unwrap();
+        let symbolic = expr.diff("x").eval(&env(&[("x", 24.0), ("a", 22.0), ("b", 2.0)]));
+
+        assert_approx_eq!(value, 0.9134948096885813, 1e-12);
+        assert_approx_eq!(dual.deriv(), -0.03779887692915554, 1e-12);
+        assert_approx_eq!(symbolic.unwrap(), dual.deriv(), 1e-12);
+    }
+
+    #[test]
+    fn symbolic_partials_of_mixed_expression_match_var() {
+        let x = Expr::symbol("x");
+        let a = Expr::symbol("a");
+        let c = Expr::symbol("c");
+        let expr = ((&a * &x.powi(3) + &(&a * &c) * &x).powi(4) * (&a * &x).sin())
+            / (256.0 * a.powi(5) * x.powi(9))
+            - (&c * &x).cos();
+        let point = env(&[("x", 10.0), ("a", 2.0), ("c", 1.5)]);
+
+        let expected = [
+            ("x", 3.2126995936768443),
+            ("a", 7.513185262318497),
+            ("c", 6.577460206746546),
+        ];
+        for (name, expected) in expected {
+            assert_approx_eq!(expr.diff(name).eval(&point).unwrap(), expected, 1e-10);
+        }
+    }
+
+    #[test]
+    fn every_function_derivative_matches_var() {
+        let x = Expr::symbol("x");
+        let y = Expr::symbol("y");
+        let mut exprs: Vec<Expr> = Func::ALL.iter().map(|f| x.call(*f)).collect();
+        exprs.extend(Func2::ALL.iter().map(|f| x.call2(*f, &y)));
+        exprs.push(x.powi(-3));
+        exprs.push(-(&x * &y));
+
+        let point = env(&[("x", 0.3), ("y", 0.8)]);
+        for expr in exprs {
+            for name in ["x", "y"] {
+                let dual = expr
+                    .eval(&env(&[
+                        ("x", Var::new(0.3, if name == "x" { 1.0 } else { 0.0 })),
+                        ("y", Var::new(0.8, if name == "y" { 1.0 } else { 0.0 })),
+                    ]))
+                    .unwrap();
+                let symbolic = expr.diff(name).eval(&point).unwrap();
+                assert_approx_eq!(symbolic, dual.deriv(), 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn min_max_derivative_keeps_the_chosen_side_exactly() {
+        // the other side is steep enough to swallow the chosen derivative if
+        // both are added up before one of them is cancelled out again
+        let x = Expr::symbol("x");
+        let point = env(&[("x", 0.0)]);
+        let above = 1e20 * x.clone() + 1e21;
+        let below = -1e20 * x.clone() - 1e21;
+        assert_eq!(x.min(&above).diff("x").eval(&point).unwrap(), 1.0);
+        assert_eq!(x.max(&below).diff("x").eval(&point).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn hypot_derivative_is_zero_at_the_origin() {
+        let (x, y) = (Expr::symbol("x"), Expr::symbol("y"));
+        let d = x.hypot(&y).diff("x");
+        let at = |x0: f64, y0: f64| d.eval(&env(&[("x", x0), ("y", y0)])).unwrap();
+        assert_eq!(at(0.0, 0.0), 0.0);
+        assert_eq!(at(3.0, 4.0), 0.6);
+        assert_eq!(at(1e-300, 0.0), 1.0);
+        let dual = Var::variable(0.0).hypot(Var::constant(0.0));
+        assert_eq!(dual.deriv(), at(0.0, 0.0));
+    }
+
+    #[test]
+    fn simplify_removes_neutral_elements() {
+        let x = Expr::symbol("x");
+        assert_eq!((&x * &Expr::constant(1.0)).simplify(), x);
+        assert_eq!((x.clone() + 0.0).simplify(), x);
+        assert_eq!((0.0 + x.clone()).simplify(), x);
+        assert_eq!((x.clone() - 0.0).simplify(), x);
+        assert_eq!((x.clone() * 0.0).simplify(), Expr::Const(0.0));
+        assert_eq!((x.clone() / 1.0).simplify(), x);
+        assert_eq!((-(-x.clone())).simplify(), x);
+        assert_eq!((&x - &x).simplify(), Expr::Const(0.0));
+        assert_eq!(x.powi(1).simplify(), x);
+        assert_eq!(x.powi(0).simplify(), Expr::Const(1.0));
+        assert_eq!(x.powi(2).powi(3).simplify(), x.powi(6));
+        let huge = x.powi(1 << 20).powi(1 << 20);
+        assert_eq!(huge.simplify(), huge);
+    }
+
+    #[test]
+    fn simplify_visits_every_node_once() {
+        // every level rewrites x + -y into x - y, the rewritten node is
+        // folded without walking the chain below it again
+        let (x, y) = (Expr::symbol("x"), Expr::symbol("y"));
+        let mut expr = x.clone();
+        let mut expected = x;
+        for _ in 0..64 {
+            expr = expr + -y.clone();
+            expected = expected - y.clone();
+        }
+        assert_eq!(expr.simplify(), expected);
+    }
+
+    #[test]
+    fn simplify_folds_constants() {
+        let x = Expr::symbol("x");
+        let expr = (Expr::constant(2.0) * 3.0 + 1.0) * x.clone() * 2.0;
+        assert_eq!(expr.simplify().to_string(), "14 * x");
+        assert_eq!(Expr::constant(0.0).cos().simplify(), Expr::Const(1.0));
+    }
+
+    #[test]
+    fn derivatives_print_in_readable_form() {
+        let x = Expr::symbol("x");
+        assert_eq!((x.powi(3) * 2.0).diff("x").to_string(), "6 * x^2");
+        assert_eq!(x.sin().diff("x").to_string(), "cos(x)");
+        assert_eq!(x.exp().diff("x").to_string(), "exp(x)");
+        assert_eq!((x.clone() * x.ln()).diff("x").to_string(), "ln(x) + 1");
+    }
+
+    #[test]
+    fn infix_printing_uses_minimal_parentheses() {
+        let x = Expr::symbol("x");
+        let y = Expr::symbol("y");
+        assert_eq!((&x - &(&x + &y)).to_string(), "x - (x + y)");
+        assert_eq!((&(&x + &y) * &x).to_string(), "(x + y) * x");
+        assert_eq!((-x.powi(2)).to_string(), "-x^2");
+        assert_eq!((-&x).powi(2).to_string(), "(-x)^2");
+        assert_eq!(x.powi(-2).to_string(), "x^(-2)");
+        assert_eq!(x.atan2(&y).to_string(), "atan2(x, y)");
+    }
+
+    #[test]
+    fn latex_printing() {
+        let x = Expr::symbol("x");
+        let y = Expr::symbol("y");
+        assert_eq!((&x / &(&x + &y)).to_latex(), "\\frac{x}{x + y}");
+        assert_eq!((&(&x + &y) * &x.sqrt()).to_latex(), "\\left(x + y\\right) \\cdot \\sqrt{x}");
+        assert_eq!(x.sin().powi(2).to_latex(), "{\\sin\\left(x\\right)}^{2}");
+        assert_eq!(x.log(&y).to_latex(), "\\log_{y}\\left(x\\right)");
+        assert_eq!(x.asin().to_latex(), "\\arcsin\\left(x\\right)");
+        assert_eq!(x.acos().to_latex(), "\\arccos\\left(x\\right)");
+        assert_eq!(x.atan().to_latex(), "\\arctan\\left(x\\right)");
+    }
+
+    #[test]
+    fn eval_reports_unbound_symbol() {
+        let expr = Expr::symbol("x") + Expr::symbol("y");
+        let result = expr.eval(&env(&[("x", 1.0)]));
+        assert_eq!(result, Err(EvalError::UnboundSymbol("y".to_string())));
+    }
+}
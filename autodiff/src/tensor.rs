@@ -0,0 +1,721 @@
+//! Reverse-mode differentiation of matrix-valued expressions.
+//!
+//! Works like [`crate::reverse`], except that every node on the [`Tape`]
+//! holds a whole [`Matrix`], so a layer of weights is a single [`Tensor`]
+//! instead of one node per entry. Vectors are 1×n rows. Elementwise
+//! operations broadcast a dimension of size 1 against any size, e.g. a 1×n
+//! bias row is added to every row of an m×n batch and a 1×1 tensor acts as a
+//! scalar. Operands with incompatible shapes panic.
+use std::cell::RefCell;
+use std::ops;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f32>,
+}
+
+impl Matrix {
+    /// Matrix from row-major `data`.
+    pub fn new(rows: usize, cols: usize, data: Vec<f32>) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "data does not fit a {rows}x{cols} matrix"
+        );
+        Self { rows, cols, data }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self::filled(rows, cols, 0.0)
+    }
+
+    pub fn filled(rows: usize, cols: usize, x: f32) -> Self {
+        Self::new(rows, cols, vec![x; rows * cols])
+    }
+
+    /// 1×1 matrix.
+    pub fn scalar(x: f32) -> Self {
+        Self::new(1, 1, vec![x])
+    }
+
+    /// 1×n matrix.
+    pub fn row(data: &[f32]) -> Self {
+        Self::new(1, data.len(), data.to_vec())
+    }
+
+    pub fn from_rows<R: AsRef<[f32]>>(rows: &[R]) -> Self {
+        let cols = rows.first().map_or(0, |r| r.as_ref().len());
+        let mut data = Vec::with_capacity(rows.len() * cols);
+        for row in rows {
+            assert_eq!(row.as_ref().len(), cols, "rows differ in length");
+            data.extend_from_slice(row.as_ref());
+        }
+        Self::new(rows.len(), cols, data)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Entries in row-major order.
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn row_slice(&self, r: usize) -> &[f32] {
+        &self.data[r * self.cols..(r + 1) * self.cols]
+    }
+
+    /// Copy of rows `start..end`.
+    pub fn slice_rows(&self, start: usize, end: usize) -> Matrix {
+        Matrix::new(
+            end - start,
+            self.cols,
+            self.data[start * self.cols..end * self.cols].to_vec(),
+        )
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut data = Vec::with_capacity(self.data.len());
+        for c in 0..self.cols {
+            data.extend((0..self.rows).map(|r| self[(r, c)]));
+        }
+        Matrix::new(self.cols, self.rows, data)
+    }
+
+    pub fn matmul(&self, rhs: &Matrix) -> Matrix {
+        assert_eq!(
+            self.cols,
+            rhs.rows,
+            "cannot multiply {:?} by {:?}",
+            self.shape(),
+            rhs.shape()
+        );
+        let mut out = Matrix::zeros(self.rows, rhs.cols);
+        for r in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self[(r, k)];
+                let out_row = &mut out.data[r * rhs.cols..(r + 1) * rhs.cols];
+                for (o, &b) in out_row.iter_mut().zip(rhs.row_slice(k)) {
+                    *o += a * b;
+                }
+            }
+        }
+        out
+    }
+
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Matrix {
+        Matrix::new(
+            self.rows,
+            self.cols,
+            self.data.iter().map(|&x| f(x)).collect(),
+        )
+    }
+
+    /// Combines two matrices entry by entry, broadcasting dimensions of size 1.
+    pub fn zip(&self, rhs: &Matrix, f: impl Fn(f32, f32) -> f32) -> Matrix {
+        let (rows, cols) = broadcast_shape(self.shape(), rhs.shape());
+        let mut data = Vec::with_capacity(rows * cols);
+        for r in 0..rows {
+            for c in 0..cols {
+                data.push(f(
+                    self[(r % self.rows, c % self.cols)],
+                    rhs[(r % rhs.rows, c % rhs.cols)],
+                ));
+            }
+        }
+        Matrix::new(rows, cols, data)
+    }
+
+    pub fn sum(&self) -> f32 {
+        self.data.iter().sum()
+    }
+
+    /// Index of the largest entry in every row.
+    pub fn argmax_rows(&self) -> Vec<usize> {
+        (0..self.rows)
+            .map(|r| {
+                let row = self.row_slice(r);
+                (0..self.cols).fold(0, |best, c| if row[c] > row[best] { c } else { best })
+            })
+            .collect()
+    }
+
+    // undoes broadcasting by summing the gradient over the stretched dimensions
+    fn reduce_to(&self, (rows, cols): (usize, usize)) -> Matrix {
+        if self.shape() == (rows, cols) {
+            return self.clone();
+        }
+        let mut out = Matrix::zeros(rows, cols);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                out.data[(r % rows) * cols + c % cols] += self[(r, c)];
+            }
+        }
+        out
+    }
+
+    // subtracts the largest entry of each row before exponentiating so that
+    // large logits do not overflow
+    fn softmax_rows(&self) -> Matrix {
+        let mut out = self.clone();
+        for r in 0..self.rows {
+            let row = &mut out.data[r * self.cols..(r + 1) * self.cols];
+            let max = row.iter().fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+            row.iter_mut().for_each(|x| *x = (*x - max).exp());
+            let total: f32 = row.iter().sum();
+            row.iter_mut().for_each(|x| *x /= total);
+        }
+        out
+    }
+
+    // x - max - ln(sum(exp(x - max))) for every row, finite even where the
+    // softmax underflows to zero
+    fn log_softmax_rows(&self) -> Matrix {
+        let mut out = self.clone();
+        for r in 0..self.rows {
+            let row = &mut out.data[r * self.cols..(r + 1) * self.cols];
+            let max = row.iter().fold(f32::NEG_INFINITY, |m, &x| m.max(x));
+            let total: f32 = row.iter().map(|x| (x - max).exp()).sum();
+            let shift = max + total.ln();
+            row.iter_mut().for_each(|x| *x -= shift);
+        }
+        out
+    }
+}
+
+impl ops::Index<(usize, usize)> for Matrix {
+    type Output = f32;
+    fn index(&self, (r, c): (usize, usize)) -> &f32 {
+        &self.data[r * self.cols + c]
+    }
+}
+
+impl ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut f32 {
+        &mut self.data[r * self.cols + c]
+    }
+}
+
+fn broadcast_shape(a: (usize, usize), b: (usize, usize)) -> (usize, usize) {
+    let dim = |x: usize, y: usize| match (x, y) {
+        _ if x == y => x,
+        (1, _) => y,
+        (_, 1) => x,
+        _ => panic!("cannot broadcast {a:?} with {b:?}"),
+    };
+    (dim(a.0, b.0), dim(a.1, b.1))
+}
+
+// parents are referred to by their position on the tape
+#[derive(Debug)]
+enum Op {
+    Leaf,
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Div(usize, usize),
+    MatMul(usize, usize),
+    Transpose(usize),
+    Scale(usize, f32),
+    // elementwise function with its derivative taken during the forward pass
+    Map(usize, Matrix),
+    // reductions spread the gradient evenly back over the input
+    Reduce(usize, f32),
+    Softmax(usize),
+    // gradient of the loss with respect to the logits
+    CrossEntropy(usize, Matrix),
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Matrix,
+    op: Op,
+}
+
+#[derive(Debug, Default)]
+pub struct Tape {
+    entries: RefCell<Vec<Entry>>,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new input on the tape.
+    pub fn var(&self, value: Matrix) -> Tensor<'_> {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Forgets all recorded operations so the tape can be reused.
+    pub fn clear(&mut self) {
+        self.entries.get_mut().clear();
+    }
+
+    fn push(&self, value: Matrix, op: Op) -> Tensor<'_> {
+        let mut entries = self.entries.borrow_mut();
+        let shape = value.shape();
+        entries.push(Entry { value, op });
+        Tensor {
+            tape: self,
+            index: entries.len() - 1,
+            shape,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tensor<'t> {
+    tape: &'t Tape,
+    index: usize,
+    shape: (usize, usize),
+}
+
+impl<'t> Tensor<'t> {
+    pub fn value(&self) -> Matrix {
+        self.tape.entries.borrow()[self.index].value.clone()
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+
+    fn unary(self, f: impl FnOnce(&Matrix) -> (Matrix, Op)) -> Tensor<'t> {
+        let (value, op) = f(&self.tape.entries.borrow()[self.index].value);
+        self.tape.push(value, op)
+    }
+
+    fn binary(
+        self,
+        rhs: Tensor<'t>,
+        f: impl FnOnce(&Matrix, &Matrix) -> (Matrix, Op),
+    ) -> Tensor<'t> {
+        assert!(
+            std::ptr::eq(self.tape, rhs.tape),
+            "tensors belong to different tapes"
+        );
+        let (value, op) = {
+            let entries = self.tape.entries.borrow();
+            f(&entries[self.index].value, &entries[rhs.index].value)
+        };
+        self.tape.push(value, op)
+    }
+
+    // elementwise function given its value and derivative at x
+    fn map(self, f: impl Fn(f32) -> (f32, f32)) -> Tensor<'t> {
+        self.unary(|x| {
+            let value = x.map(|x| f(x).0);
+            let derivative = x.map(|x| f(x).1);
+            (value, Op::Map(self.index, derivative))
+        })
+    }
+
+    /// Propagates gradients from this tensor back to every tensor on the tape.
+    /// A tensor with more than one entry is seeded with ones, which
+    /// differentiates the sum of its entries.
+    pub fn backward(&self) -> Gradients {
+        let entries = self.tape.entries.borrow();
+        let mut grads: Vec<Option<Matrix>> = vec![None; entries.len()];
+        grads[self.index] = Some(Matrix::filled(self.shape.0, self.shape.1, 1.0));
+
+        for i in (0..=self.index).rev() {
+            let Some(grad) = grads[i].take() else {
+                continue;
+            };
+            let value = |j: usize| &entries[j].value;
+            match &entries[i].op {
+                Op::Leaf => {}
+                &Op::Add(a, b) => {
+                    accumulate(&mut grads, a, grad.reduce_to(value(a).shape()));
+                    accumulate(&mut grads, b, grad.reduce_to(value(b).shape()));
+                }
+                &Op::Sub(a, b) => {
+                    accumulate(&mut grads, a, grad.reduce_to(value(a).shape()));
+                    accumulate(&mut grads, b, grad.map(|g| -g).reduce_to(value(b).shape()));
+                }
+                &Op::Mul(a, b) => {
+                    let ga = grad.zip(value(b), |g, b| g * b);
+                    let gb = grad.zip(value(a), |g, a| g * a);
+                    accumulate(&mut grads, a, ga.reduce_to(value(a).shape()));
+                    accumulate(&mut grads, b, gb.reduce_to(value(b).shape()));
+                }
+                &Op::Div(a, b) => {
+                    let ga = grad.zip(value(b), |g, b| g / b);
+                    let gb = grad.zip(&value(a).zip(value(b), |a, b| -a / (b * b)), |g, d| g * d);
+                    accumulate(&mut grads, a, ga.reduce_to(value(a).shape()));
+                    accumulate(&mut grads, b, gb.reduce_to(value(b).shape()));
+                }
+                &Op::MatMul(a, b) => {
+                    accumulate(&mut grads, a, grad.matmul(&value(b).transpose()));
+                    accumulate(&mut grads, b, value(a).transpose().matmul(&grad));
+                }
+                &Op::Transpose(a) => accumulate(&mut grads, a, grad.transpose()),
+                &Op::Scale(a, s) => accumulate(&mut grads, a, grad.map(|g| g * s)),
+                Op::Map(a, derivative) => {
+                    accumulate(&mut grads, *a, grad.zip(derivative, |g, d| g * d));
+                }
+                &Op::Reduce(a, scale) => {
+                    let (rows, cols) = value(a).shape();
+                    let spread = Matrix::zeros(rows, cols).zip(&grad, |_, g| g * scale);
+                    accumulate(&mut grads, a, spread);
+                }
+                &Op::Softmax(a) => {
+                    // J^T g = y * (g - <g, y>) for every row
+                    let y = value(i);
+                    let mut ga = Matrix::zeros(y.rows, y.cols);
+                    for r in 0..y.rows {
+                        let dot: f32 = grad
+                            .row_slice(r)
+                            .iter()
+                            .zip(y.row_slice(r))
+                            .map(|(g, y)| g * y)
+                            .sum();
+                        for c in 0..y.cols {
+                            ga[(r, c)] = y[(r, c)] * (grad[(r, c)] - dot);
+                        }
+                    }
+                    accumulate(&mut grads, a, ga);
+                }
+                Op::CrossEntropy(a, dlogits) => {
+                    let g = grad[(0, 0)];
+                    accumulate(&mut grads, *a, dlogits.map(|d| d * g));
+                }
+            }
+            grads[i] = Some(grad);
+        }
+
+        Gradients { grads }
+    }
+
+    pub fn matmul(self, rhs: Tensor<'t>) -> Self {
+        self.binary(rhs, |a, b| (a.matmul(b), Op::MatMul(self.index, rhs.index)))
+    }
+
+    pub fn transpose(self) -> Self {
+        self.unary(|x| (x.transpose(), Op::Transpose(self.index)))
+    }
+
+    pub fn exp(self) -> Self {
+        self.map(|x| (x.exp(), x.exp()))
+    }
+    pub fn ln(self) -> Self {
+        self.map(|x| (x.ln(), 1.0 / x))
+    }
+    pub fn powi(self, n: i32) -> Self {
+        self.map(|x| (x.powi(n), n as f32 * x.powi(n - 1)))
+    }
+    pub fn sqrt(self) -> Self {
+        self.map(|x| (x.sqrt(), 0.5 / x.sqrt()))
+    }
+    pub fn tanh(self) -> Self {
+        self.map(|x| (x.tanh(), 1.0 - x.tanh().powi(2)))
+    }
+    pub fn sigmoid(self) -> Self {
+        self.map(|x| {
+            let s = 1.0 / (1.0 + (-x).exp());
+            (s, s * (1.0 - s))
+        })
+    }
+    /// Uses the subgradient 0 at x = 0.
+    pub fn relu(self) -> Self {
+        self.map(|x| if x > 0.0 { (x, 1.0) } else { (0.0, 0.0) })
+    }
+
+    /// Sum of all entries as a 1×1 tensor.
+    pub fn sum(self) -> Self {
+        self.unary(|x| (Matrix::scalar(x.sum()), Op::Reduce(self.index, 1.0)))
+    }
+
+    /// Mean of all entries as a 1×1 tensor.
+    pub fn mean(self) -> Self {
+        self.unary(|x| {
+            let n = x.data.len() as f32;
+            (Matrix::scalar(x.sum() / n), Op::Reduce(self.index, 1.0 / n))
+        })
+    }
+
+    /// Sums every column over the rows into a 1×n tensor.
+    pub fn sum_rows(self) -> Self {
+        self.unary(|x| (x.reduce_to((1, x.cols)), Op::Reduce(self.index, 1.0)))
+    }
+
+    /// Averages every column over the rows into a 1×n tensor.
+    pub fn mean_rows(self) -> Self {
+        self.unary(|x| {
+            let scale = 1.0 / x.rows as f32;
+            (
+                x.reduce_to((1, x.cols)).map(|s| s * scale),
+                Op::Reduce(self.index, scale),
+            )
+        })
+    }
+
+    /// Normalises every row into a probability distribution.
+    pub fn softmax(self) -> Self {
+        self.unary(|x| (x.softmax_rows(), Op::Softmax(self.index)))
+    }
+
+    /// Mean cross-entropy between the softmax of the rows of `self` and the
+    /// rows of `targets`, usually one-hot labels, as a 1×1 tensor. Works on the
+    /// log-softmax directly, so it is cheaper than composing
+    /// [`Tensor::softmax`] with [`Tensor::ln`] and stays finite where the
+    /// softmax underflows.
+    pub fn cross_entropy(self, targets: &Matrix) -> Self {
+        self.unary(|logits| {
+            assert_eq!(
+                logits.shape(),
+                targets.shape(),
+                "targets do not match the logits"
+            );
+            let log_probs = logits.log_softmax_rows();
+            let rows = logits.rows as f32;
+            let loss = -log_probs
+                .zip(targets, |lp, t| if t == 0.0 { 0.0 } else { t * lp })
+                .sum()
+                / rows;
+            let dlogits = log_probs.zip(targets, |lp, t| (lp.exp() - t) / rows);
+            (Matrix::scalar(loss), Op::CrossEntropy(self.index, dlogits))
+        })
+    }
+}
+
+fn accumulate(grads: &mut [Option<Matrix>], index: usize, grad: Matrix) {
+    match &mut grads[index] {
+        Some(sum) => sum
+            .data
+            .iter_mut()
+            .zip(grad.data)
+            .for_each(|(s, g)| *s += g),
+        slot => *slot = Some(grad),
+    }
+}
+
+macro_rules! impl_elementwise_op {
+    ($trait:ident, $method:ident, $variant:ident, $op:tt) => {
+        impl<'t> ops::$trait for Tensor<'t> {
+            type Output = Tensor<'t>;
+            fn $method(self, rhs: Self) -> Self::Output {
+                self.binary(rhs, |a, b| (a.zip(b, |a, b| a $op b), Op::$variant(self.index, rhs.index)))
+            }
+        }
+    };
+}
+
+impl_elementwise_op!(Add, add, Add, +);
+impl_elementwise_op!(Sub, sub, Sub, -);
+impl_elementwise_op!(Mul, mul, Mul, *);
+impl_elementwise_op!(Div, div, Div, /);
+
+impl<'t> ops::Mul<f32> for Tensor<'t> {
+    type Output = Tensor<'t>;
+    fn mul(self, rhs: f32) -> Self::Output {
+        self.unary(|x| (x.map(|x| x * rhs), Op::Scale(self.index, rhs)))
+    }
+}
+
+impl<'t> ops::Mul<Tensor<'t>> for f32 {
+    type Output = Tensor<'t>;
+    fn mul(self, rhs: Tensor<'t>) -> Self::Output {
+        rhs * self
+    }
+}
+
+impl<'t> ops::Neg for Tensor<'t> {
+    type Output = Tensor<'t>;
+    fn neg(self) -> Self::Output {
+        self * -1.0
+    }
+}
+
+/// Gradients of every tensor on the tape with respect to the tensor
+/// [`Tensor::backward`] was called on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradients {
+    grads: Vec<Option<Matrix>>,
+}
+
+impl Gradients {
+    /// Gradient with the shape of `tensor`, zero if the output does not
+    /// depend on it.
+    pub fn wrt(&self, tensor: &Tensor) -> Matrix {
+        let (rows, cols) = tensor.shape;
+        self.grads
+            .get(tensor.index)
+            .and_then(Option::clone)
+            .unwrap_or_else(|| Matrix::zeros(rows, cols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    // central differences of a scalar function of one matrix entry at a time
+    fn numeric_gradient(f: impl Fn(&Matrix) -> f32, x: &Matrix) -> Matrix {
+        let h = 1e-2;
+        let mut grad = Matrix::zeros(x.rows(), x.cols());
+        let mut shifted = x.clone();
+        for i in 0..x.data.len() {
+            shifted.data[i] = x.data[i] + h;
+            let forward = f(&shifted);
+            shifted.data[i] = x.data[i] - h;
+            let backward = f(&shifted);
+            shifted.data[i] = x.data[i];
+            grad.data[i] = (forward - backward) / (2.0 * h);
+        }
+        grad
+    }
+
+    fn assert_matrix_approx_eq(a: &Matrix, b: &Matrix, tol: f32) {
+        assert_eq!(a.shape(), b.shape());
+        for (x, y) in a.data().iter().zip(b.data()) {
+            assert_approx_eq!(x, y, tol);
+        }
+    }
+
+    #[test]
+    fn matmul_and_transpose() {
+        let a = Matrix::from_rows(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        let b = Matrix::from_rows(&[[1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+        assert_eq!(a.matmul(&b), Matrix::from_rows(&[[4.0, 5.0], [10.0, 11.0]]));
+        assert_eq!(a.transpose().shape(), (3, 2));
+        assert_eq!(a.transpose()[(2, 1)], 6.0);
+    }
+
+    #[test]
+    fn broadcasting_row_and_scalar() {
+        let a = Matrix::from_rows(&[[1.0, 2.0], [3.0, 4.0]]);
+        let sum = a.zip(&Matrix::row(&[10.0, 20.0]), |a, b| a + b);
+        assert_eq!(sum, Matrix::from_rows(&[[11.0, 22.0], [13.0, 24.0]]));
+        assert_eq!(
+            a.zip(&Matrix::scalar(2.0), |a, b| a * b).data(),
+            &[2.0, 4.0, 6.0, 8.0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot broadcast")]
+    fn incompatible_shapes_panic() {
+        let _ = Matrix::zeros(2, 3).zip(&Matrix::zeros(3, 2), |a, b| a + b);
+    }
+
+    #[test]
+    fn broadcast_gradient_is_summed_back() {
+        let tape = Tape::new();
+        let x = tape.var(Matrix::from_rows(&[[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]));
+        let bias = tape.var(Matrix::row(&[0.5, -0.5]));
+        let scale = tape.var(Matrix::scalar(2.0));
+        let y = ((x + bias) * scale).sum();
+        let grads = y.backward();
+
+        assert_eq!(y.value(), Matrix::scalar(42.0));
+        assert_eq!(grads.wrt(&bias), Matrix::row(&[6.0, 6.0]));
+        assert_eq!(grads.wrt(&scale), Matrix::scalar(21.0));
+        assert_eq!(grads.wrt(&x), Matrix::filled(3, 2, 2.0));
+    }
+
+    #[test]
+    fn operations_match_finite_differences() {
+        let w0 = Matrix::from_rows(&[[0.3, -0.2, 0.5], [0.1, 0.4, -0.6]]);
+        let x = Matrix::from_rows(&[[1.0, 2.0], [-1.0, 0.5], [0.2, 0.3], [0.7, -0.4]]);
+        let targets = Matrix::from_rows(&[
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0],
+        ]);
+
+        // every operation at least once
+        fn loss<'t>(tape: &'t Tape, x: &Matrix, w: Tensor<'t>, targets: &Matrix) -> Tensor<'t> {
+            let one = tape.var(Matrix::scalar(1.0));
+            let h = tape.var(x.clone()).matmul(w);
+            let a = h.tanh() + h.sigmoid() * h.relu() - h.exp().sqrt() / (h.powi(2) + one);
+            let b = (a.softmax() + one).ln().mean_rows().sum();
+            let c = (-a).transpose().sum_rows().mean() * 0.5;
+            a.cross_entropy(targets) + b + c
+        }
+
+        let tape = Tape::new();
+        let w = tape.var(w0.clone());
+        let analytic = loss(&tape, &x, w, &targets).backward().wrt(&w);
+        let numeric = numeric_gradient(
+            |w| {
+                let tape = Tape::new();
+                loss(&tape, &x, tape.var(w.clone()), &targets).value()[(0, 0)]
+            },
+            &w0,
+        );
+        assert_matrix_approx_eq(&analytic, &numeric, 2e-3);
+    }
+
+    #[test]
+    fn softmax_rows_are_distributions() {
+        let tape = Tape::new();
+        let logits = tape.var(Matrix::from_rows(&[[1000.0, 1000.0], [0.0, 2f32.ln()]]));
+        let p = logits.softmax().value();
+        assert_eq!(p.row_slice(0), &[0.5, 0.5]);
+        assert_approx_eq!(p[(1, 1)], 2.0 / 3.0);
+    }
+
+    #[test]
+    fn cross_entropy_gradient_is_probabilities_minus_targets() {
+        let tape = Tape::new();
+        let logits = tape.var(Matrix::from_rows(&[[0.0, 0.0], [1.0, -1.0]]));
+        let targets = Matrix::from_rows(&[[1.0, 0.0], [0.0, 1.0]]);
+        let loss = logits.cross_entropy(&targets);
+        let grad = loss.backward().wrt(&logits);
+
+        let p = 1.0 / (1.0 + 2f32.exp());
+        assert_approx_eq!(loss.value()[(0, 0)], (2f32.ln() - p.ln()) / 2.0);
+        assert_approx_eq!(grad[(0, 0)], -0.25);
+        assert_approx_eq!(grad[(1, 1)], (p - 1.0) / 2.0);
+    }
+
+    #[test]
+    fn cross_entropy_stays_finite_when_the_target_probability_underflows() {
+        let tape = Tape::new();
+        // exp(-200) is zero in f32
+        let logits = tape.var(Matrix::from_rows(&[[200.0, 0.0], [0.0, 0.0]]));
+        let targets = Matrix::from_rows(&[[0.0, 1.0], [1.0, 0.0]]);
+        let loss = logits.cross_entropy(&targets);
+        let grad = loss.backward().wrt(&logits);
+
+        assert_approx_eq!(loss.value()[(0, 0)], (200.0 + 2f32.ln()) / 2.0, 1e-3);
+        assert!(grad.data().iter().all(|g| g.is_finite()));
+        assert_matrix_approx_eq(
+            &grad,
+            &Matrix::from_rows(&[[0.5, -0.5], [-0.25, 0.25]]),
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn unused_tensor_has_zero_gradient() {
+        let tape = Tape::new();
+        let x = tape.var(Matrix::row(&[1.0, 2.0]));
+        let unused = tape.var(Matrix::zeros(2, 2));
+        let grads = (x * x).sum().backward();
+        assert_eq!(grads.wrt(&x), Matrix::row(&[2.0, 4.0]));
+        assert_eq!(grads.wrt(&unused), Matrix::zeros(2, 2));
+    }
+}
@@ -0,0 +1,101 @@
+//! Task throughput of the work-stealing pool against the previous design,
+//! where every worker took its tasks from one channel behind a mutex.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kata_threadpool1::Pool;
+use std::sync::mpsc;
+use std::thread;
+
+mod channel_pool {
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    type Task = Box<dyn FnOnce() + Send + 'static>;
+
+    pub struct ChannelPool {
+        handles: Vec<JoinHandle<()>>,
+        sender: Option<Sender<Task>>,
+    }
+
+    impl ChannelPool {
+        pub fn with_thread_count(n: usize) -> Self {
+            let (sender, rx) = mpsc::channel::<Task>();
+            let rx: Arc<Mutex<Receiver<Task>>> = Arc::new(Mutex::new(rx));
+            let handles = (0..n)
+                .map(|_| {
+                    let rx = rx.clone();
+                    thread::spawn(move || loop {
+                        // the lock is held while waiting for the next task
+                        let task = match rx.lock().unwrap().recv() {
+                            Ok(task) => task,
+                            Err(_) => return,
+                        };
+                        task();
+                    })
+                })
+                .collect();
+            Self {
+                handles,
+                sender: Some(sender),
+            }
+        }
+
+        pub fn spawn(&self, f: impl FnOnce() + Send + 'static) {
+            let _ = self.sender.as_ref().unwrap().send(Box::new(f));
+        }
+    }
+
+    impl Drop for ChannelPool {
+        fn drop(&mut self) {
+            drop(self.sender.take());
+            for handle in self.handles.drain(..) {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
+
+use channel_pool::ChannelPool;
+
+// spins for roughly `iterations` steps so the task cannot be optimised away
+fn work(iterations: u64) -> u64 {
+    (0..iterations).fold(0u64, |acc, i| {
+        black_box(acc.wrapping_mul(31).wrapping_add(i))
+    })
+}
+
+// spawns `tasks` tasks and waits for all of them to finish
+fn run_batch(spawn: impl Fn(Box<dyn FnOnce() + Send>), tasks: usize, iterations: u64) {
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..tasks {
+        let sender = sender.clone();
+        spawn(Box::new(move || sender.send(work(iterations)).unwrap()));
+    }
+    for _ in 0..tasks {
+        black_box(receiver.recv().unwrap());
+    }
+}
+
+fn throughput(c: &mut Criterion) {
+    // one thread per core, and as many threads as the pool_find example
+    let cores = thread::available_parallelism().map_or(4, |n| n.get());
+    for threads in [cores, 40] {
+        let stealing = Pool::with_thread_count(threads).unwrap();
+        let channel = ChannelPool::with_thread_count(threads);
+
+        // (name, tasks per batch, iterations per task)
+        for (name, tasks, iterations) in [("tiny", 10_000, 10), ("coarse", 64, 200_000)] {
+            let mut group = c.benchmark_group(name);
+            group.bench_function(BenchmarkId::new("work_stealing", threads), |b| {
+                b.iter(|| run_batch(|task| stealing.spawn(task), tasks, iterations))
+            });
+            group.bench_function(BenchmarkId::new("mutex_channel", threads), |b| {
+                b.iter(|| run_batch(|task| channel.spawn(task), tasks, iterations))
+            });
+            group.finish();
+        }
+    }
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
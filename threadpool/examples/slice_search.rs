@@ -0,0 +1,39 @@
+//! Searches a slice on the stack in parallel, without copying it into an
+//! `Arc` first:
+//!
+//!     cargo run --release --example slice_search -- 10000000 1234567
+use kata_threadpool1::Pool;
+use std::env;
+use std::thread;
+
+// first index of `target`, searching every chunk in its own task
+fn find(pool: &Pool, haystack: &[u64], target: u64, chunk_size: usize) -> Option<usize> {
+    pool.scope(|s| {
+        let handles: Vec<_> = haystack
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn_with_handle(move || chunk.iter().position(|&x| x == target)))
+            .collect();
+
+        handles.into_iter().enumerate().find_map(|(i, handle)| {
+            let found = handle.join().expect("search task panicked");
+            found.map(|offset| i * chunk_size + offset)
+        })
+    })
+}
+
+fn main() {
+    let mut args = env::args()
+        .skip(1)
+        .map(|a| a.parse::<u64>().expect("expected a number"));
+    let len = args.next().unwrap_or(10_000_000);
+    let target = args.next().unwrap_or(len / 3);
+
+    let haystack: Vec<u64> = (0..len).map(|x| x * 7 % len).collect();
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    let pool = Pool::with_thread_count(threads).expect("Unable to create pool");
+
+    match find(&pool, &haystack, target, 64 * 1024) {
+        Some(index) => println!("found {target} at index {index}"),
+        None => println!("{target} not found"),
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+use std::{fmt, io, thread};
+
+use crate::{lock, timer, Owner, Pool, Shared};
+
+/// What spawning does while the queue set with
+/// [`PoolBuilder::queue_capacity`] is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until a worker takes a task off the queue. A task spawned from a
+    /// worker runs right away on that worker instead, which could otherwise
+    /// be waiting for itself.
+    #[default]
+    Block,
+    /// Refuse the task, [`Pool::try_spawn`] returns
+    /// [`crate::SpawnError::QueueFull`] and [`Pool::spawn`] drops it.
+    Reject,
+    /// Run the task on the spawning thread.
+    CallerRuns,
+    /// Drop the task that has been waiting the longest to make room.
+    DropOldest,
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// The maximum number of threads was zero.
+    NoThreads,
+    MinAboveMax {
+        min: usize,
+        max: usize,
+    },
+    /// A queue capacity of zero was asked for.
+    ZeroCapacity,
+    /// The operating system refused to start a worker thread.
+    Spawn(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoThreads => write!(f, "a pool needs at least one thread"),
+            BuildError::MinAboveMax { min, max } => {
+                write!(f, "minimum of {min} threads is above the maximum of {max}")
+            }
+            BuildError::ZeroCapacity => write!(f, "queue capacity must be positive"),
+            BuildError::Spawn(error) => write!(f, "failed to start a worker thread: {error}"),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Spawn(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Configures a [`Pool`]. Without any settings it has one thread per core
+/// and an unbounded queue.
+#[derive(Clone, Debug, Default)]
+pub struct PoolBuilder {
+    min_threads: Option<usize>,
+    max_threads: Option<usize>,
+    keep_alive: Option<Duration>,
+    queue_capacity: Option<usize>,
+    overflow: OverflowPolicy,
+    thread_name: Option<String>,
+}
+
+impl PoolBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Threads that are started right away and kept while idle. Defaults to
+    /// the maximum, which gives a pool of fixed size.
+    pub fn min_threads(mut self, n: usize) -> Self {
+        self.min_threads = Some(n);
+        self
+    }
+
+    /// Threads are added up to this number while all of them are busy.
+    /// Defaults to the number of cores, or the minimum if that is larger.
+    pub fn max_threads(mut self, n: usize) -> Self {
+        self.max_threads = Some(n);
+        self
+    }
+
+    /// How long a thread above the minimum waits for a task before it
+    /// exits, one minute by default.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Bounds the number of queued tasks, `overflow` decides what happens to
+    /// tasks spawned while the queue is full. Timers that fire and futures
+    /// that are woken are queued regardless, so they may take the queue past
+    /// `capacity`.
+    pub fn queue_capacity(mut self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.queue_capacity = Some(capacity);
+        self.overflow = overflow;
+        self
+    }
+
+    /// Workers are named `{prefix}-{index}`, the timer thread
+    /// `{prefix}-timer`.
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name = Some(prefix.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Pool, BuildError> {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let max = self
+            .max_threads
+            .unwrap_or_else(|| cores.max(self.min_threads.unwrap_or(0)));
+        let min = self.min_threads.unwrap_or(max);
+        if max == 0 {
+            return Err(BuildError::NoThreads);
+        }
+        if min > max {
+            return Err(BuildError::MinAboveMax { min, max });
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(BuildError::ZeroCapacity);
+        }
+
+        let shared = Arc::new(Shared {
+            queues: (0..max).map(|_| Mutex::new(VecDeque::new())).collect(),
+            high: Mutex::new(VecDeque::new()),
+            high_queued: AtomicUsize::new(0),
+            low: Mutex::new(VecDeque::new()),
+            queued: AtomicUsize::new(0),
+            next_queue: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            capacity: self.queue_capacity,
+            overflow: self.overflow,
+            blocked: AtomicUsize::new(0),
+            full: Mutex::new(()),
+            space: Condvar::new(),
+            searching: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            min_threads: min,
+            keep_alive: self.keep_alive.unwrap_or(Duration::from_secs(60)),
+            thread_name: self.thread_name,
+            slots: Mutex::new(vec![false; max]),
+            live: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            panics: AtomicUsize::new(0),
+            latency: AtomicU64::new(0),
+            panic_handler: RwLock::new(None),
+            workers: Mutex::new(Vec::new()),
+            joining: Mutex::new(()),
+            timers: timer::Timers::default(),
+        });
+        // shuts down the workers started so far if one of them fails
+        let pool = Pool {
+            _owner: Arc::new(Owner(shared.clone())),
+            shared,
+        };
+
+        let mut slots = lock(&pool.shared.slots);
+        for index in 0..min {
+            pool.shared
+                .start_worker(&mut slots, index)
+                .map_err(BuildError::Spawn)?;
+        }
+        drop(slots);
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SpawnError, TaskError};
+    use std::sync::mpsc::{self, Sender};
+    use std::time::Instant;
+
+    // one worker that is kept busy until the sender is used, room for two
+    // queued tasks
+    fn busy_pool(overflow: OverflowPolicy) -> (Pool, Sender<()>) {
+        let pool = PoolBuilder::new()
+            .max_threads(1)
+            .queue_capacity(2, overflow)
+            .build()
+            .unwrap();
+        let (started, running) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        (pool, release)
+    }
+
+    fn wait_until(mut condition: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_errors() {
+        let error = PoolBuilder::new().max_threads(0).build().unwrap_err();
+        assert!(matches!(error, BuildError::NoThreads));
+        let error = PoolBuilder::new()
+            .min_threads(3)
+            .max_threads(2)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "minimum of 3 threads is above the maximum of 2"
+        );
+        let error = PoolBuilder::new()
+            .queue_capacity(0, OverflowPolicy::Reject)
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, BuildError::ZeroCapacity));
+    }
+
+    #[test]
+    fn grows_while_busy_and_shrinks_when_idle() {
+        let pool = PoolBuilder::new()
+            .min_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        assert_eq!((pool.thread_count(), pool.max_thread_count()), (1, 3));
+
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        for _ in 0..3 {
+            let blocked = blocked.clone();
+            pool.spawn(move || lock(&blocked).recv().unwrap());
+        }
+        // every task blocks its thread, so all of them running at once takes
+        // two more threads
+        wait_until(|| pool.stats().active == 3);
+        assert_eq!(pool.thread_count(), 3);
+
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        wait_until(|| pool.thread_count() == 1);
+        assert_eq!(pool.spawn_with_handle(|| 5).join().unwrap(), 5);
+    }
+
+    #[test]
+    fn threads_start_on_demand() {
+        let pool = PoolBuilder::new()
+            .min_threads(0)
+            .max_threads(2)
+            .build()
+            .unwrap();
+        assert_eq!(pool.thread_count(), 0);
+        assert_eq!(pool.par_map(&[1, 2, 3], |x| x * 2), vec![2, 4, 6]);
+        assert!(pool.thread_count() > 0);
+    }
+
+    #[test]
+    fn workers_are_named() {
+        let pool = PoolBuilder::new()
+            .max_threads(1)
+            .thread_name("maintenance")
+            .build()
+            .unwrap();
+        let name = pool.spawn_with_handle(|| thread::current().name().map(String::from));
+        assert_eq!(name.join().unwrap().as_deref(), Some("maintenance-0"));
+    }
+
+    #[test]
+    fn full_queue_rejects() {
+        let (pool, release) = busy_pool(OverflowPolicy::Reject);
+        assert_eq!(pool.try_spawn(|| {}), Ok(()));
+        assert_eq!(pool.try_spawn(|| {}), Ok(()));
+        assert_eq!(pool.try_spawn(|| {}), Err(SpawnError::QueueFull));
+        let handle = pool.spawn_with_handle(|| 1);
+        assert!(matches!(handle.join(), Err(TaskError::Cancelled)));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn full_queue_runs_on_the_caller() {
+        let (pool, release) = busy_pool(OverflowPolicy::CallerRuns);
+        pool.spawn(|| {});
+        pool.spawn(|| {});
+        let caller = thread::current().id();
+        let handle = pool.spawn_with_handle(|| thread::current().id());
+        assert_eq!(handle.join().unwrap(), caller);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let (pool, release) = busy_pool(OverflowPolicy::DropOldest);
+        let oldest = pool.spawn_with_handle(|| "oldest");
+        let middle = pool.spawn_with_handle(|| "middle");
+        let newest = pool.spawn_with_handle(|| "newest");
+        release.send(()).unwrap();
+
+        assert!(matches!(oldest.join(), Err(TaskError::Cancelled)));
+        assert_eq!(middle.join().unwrap(), "middle");
+        assert_eq!(newest.join().unwrap(), "newest");
+    }
+
+    #[test]
+    fn full_queue_blocks_until_there_is_room() {
+        let (pool, release) = busy_pool(OverflowPolicy::Block);
+        pool.spawn(|| {});
+        pool.spawn(|| {});
+
+        let (spawned, returned) = mpsc::channel();
+        let spawner = pool.clone();
+        thread::spawn(move || {
+            spawner.spawn(|| {});
+            spawned.send(()).unwrap();
+        });
+        assert!(returned.recv_timeout(Duration::from_millis(50)).is_err());
+        release.send(()).unwrap();
+        returned.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn timers_are_queued_past_the_capacity() {
+        let (pool, release) = busy_pool(OverflowPolicy::Block);
+        pool.spawn(|| {});
+        pool.spawn(|| {});
+
+        let (fired, ran) = mpsc::channel();
+        for _ in 0..2 {
+            let fired = fired.clone();
+            pool.spawn_after(Duration::from_millis(1), move || fired.send(()).unwrap());
+        }
+        // both are queued past the capacity instead of running on the timer
+        // thread
+        wait_until(|| pool.stats().queued == 4);
+        assert!(ran.try_recv().is_err());
+        release.send(()).unwrap();
+        for _ in 0..2 {
+            ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+}
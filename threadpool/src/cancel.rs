@@ -0,0 +1,91 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use crate::{Pool, TaskHandle};
+
+/// Flag shared between the tasks doing some work and whoever may want to
+/// stop it. Cancelling is cooperative, running tasks have to check the token
+/// and return early themselves.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every clone of this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Pool {
+    /// Like [`Pool::spawn_with_handle`], but `f` gets a clone of `token` to
+    /// check while it runs. If the token is cancelled before the task starts,
+    /// `f` is not called and the handle returns [`crate::TaskError::Cancelled`].
+    pub fn spawn_cancellable<F, T>(&self, token: &CancellationToken, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let token = token.clone();
+        self.spawn(move || {
+            // dropping the sender is what cancels the handle
+            if token.is_cancelled() {
+                return;
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&token)));
+            let _ = sender.send(result);
+        });
+        TaskHandle::new(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TaskError;
+    use std::time::Duration;
+
+    #[test]
+    fn cancelled_before_start_is_not_run() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || blocked.recv().unwrap());
+
+        let token = CancellationToken::new();
+        let handle = pool.spawn_cancellable(&token, |_| panic!("should not run"));
+        token.clone().cancel();
+        release.send(()).unwrap();
+
+        assert!(matches!(handle.join(), Err(TaskError::Cancelled)));
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn running_task_stops_when_cancelled() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let token = CancellationToken::new();
+        let handle = pool.spawn_cancellable(&token, |token| {
+            let mut rounds = 0u64;
+            while !token.is_cancelled() {
+                rounds += 1;
+                std::thread::yield_now();
+            }
+            rounds
+        });
+
+        let handle = handle.join_timeout(Duration::from_millis(20)).unwrap_err();
+        token.cancel();
+        assert!(handle.join().unwrap() > 0);
+    }
+}
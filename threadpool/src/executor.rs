@@ -0,0 +1,453 @@
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use crate::{lock, Origin, Pool, Priority, Shared, TaskError};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+// Where the result of a task waits for its `TaskFuture`. The task side
+// closes it when it is dropped, with or without having left a result.
+struct Slot<T> {
+    result: Option<thread::Result<T>>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Resolves to the result of a task started with [`Pool::spawn_future`] or
+/// [`Pool::spawn_blocking`]. Dropping it detaches the task, it still runs.
+pub struct TaskFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+struct Completer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+fn oneshot<T>() -> (Completer<T>, TaskFuture<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        result: None,
+        closed: false,
+        waker: None,
+    }));
+    (Completer { slot: slot.clone() }, TaskFuture { slot })
+}
+
+impl<T> Completer<T> {
+    fn complete(self, result: thread::Result<T>) {
+        lock(&self.slot).result = Some(result);
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut slot = lock(&self.slot);
+            slot.closed = true;
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for TaskFuture<T> {
+    type Output = Result<T, TaskError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = lock(&self.slot);
+        if let Some(result) = slot.result.take() {
+            return Poll::Ready(result.map_err(TaskError::Panicked));
+        }
+        if slot.closed {
+            return Poll::Ready(Err(TaskError::Cancelled));
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for TaskFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskFuture")
+            .field("closed", &lock(&self.slot).closed)
+            .finish_non_exhaustive()
+    }
+}
+
+// Polls the spawned future and sends its output, or its panic, to the
+// `TaskFuture`.
+struct Catching<T> {
+    future: Pin<Box<dyn Future<Output = T> + Send + 'static>>,
+    completer: Option<Completer<T>>,
+}
+
+impl<T> Future for Catching<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| this.future.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(output)) => Ok(output),
+            Err(payload) => Err(payload),
+        };
+        if let Some(completer) = this.completer.take() {
+            completer.complete(result);
+        }
+        Poll::Ready(())
+    }
+}
+
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+// woken while it was being polled
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+// A spawned future and its waker in one. Waking queues a pool task that
+// polls the future once, a future that is already queued is not queued again.
+struct FutureTask {
+    state: AtomicU8,
+    // only locked by the one poll that is running at a time
+    future: Mutex<Option<BoxFuture>>,
+    shared: Arc<Shared>,
+}
+
+impl FutureTask {
+    // a future that was let in once must not lose a wake-up to a full queue
+    fn schedule(self: Arc<Self>) {
+        self.queue(Origin::Woken);
+    }
+
+    fn queue(self: Arc<Self>, origin: Origin) {
+        let task = self.clone();
+        let pushed = self
+            .shared
+            .push_from(Box::new(move || task.poll()), Priority::Normal, origin);
+        if pushed.is_err() {
+            // nothing would ever poll it again, dropping it tells the
+            // `TaskFuture` that it was cancelled
+            self.state.store(DONE, Ordering::SeqCst);
+            let future = lock(&self.future).take();
+            drop(future);
+        }
+    }
+
+    fn poll(self: &Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+
+        let mut future = lock(&self.future);
+        let Some(pinned) = future.as_mut() else {
+            return;
+        };
+        if pinned.as_mut().poll(&mut context).is_ready() {
+            self.state.store(DONE, Ordering::SeqCst);
+            // dropped outside the lock, it may wake itself on the way out
+            let finished = future.take();
+            drop(future);
+            drop(finished);
+            return;
+        }
+        drop(future);
+
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // woken while running, go to the back of the queue so other
+            // tasks get a turn
+            self.state.store(SCHEDULED, Ordering::SeqCst);
+            self.clone().schedule();
+        }
+    }
+}
+
+impl Wake for FutureTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self
+                .state
+                .compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) if next == SCHEDULED => return self.clone().schedule(),
+                Ok(_) => return,
+                Err(current) => state = current,
+            }
+        }
+    }
+}
+
+impl Pool {
+    /// Runs `future` on the workers. Whenever its waker is woken the future
+    /// is queued to be polled again like any other task. A future that is
+    /// pending and cannot be woken anymore is dropped, as is one that is
+    /// woken after the pool was shut down, and the [`TaskFuture`] returns
+    /// [`TaskError::Cancelled`]. Only spawning counts against a bounded
+    /// queue, a woken future is queued even while it is full.
+    pub fn spawn_future<F>(&self, future: F) -> TaskFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (completer, result) = oneshot();
+        let task = Arc::new(FutureTask {
+            state: AtomicU8::new(SCHEDULED),
+            future: Mutex::new(Some(Box::pin(Catching {
+                future: Box::pin(future),
+                completer: Some(completer),
+            }))),
+            shared: self.shared.clone(),
+        });
+        task.queue(Origin::Caller);
+        result
+    }
+
+    /// Runs the closure on a worker and returns a future for its result, so
+    /// async code can hand off work that would block its own thread.
+    pub fn spawn_blocking<F, T>(&self, f: F) -> TaskFuture<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (completer, result) = oneshot();
+        self.spawn(move || completer.complete(panic::catch_unwind(AssertUnwindSafe(f))));
+        result
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Polls `future` on the calling thread, parking it in between, until it is
+/// ready. Called from a task it takes the worker out of the pool while it
+/// waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverflowPolicy, PoolBuilder, SpawnError};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // pending until `fire` is called from anywhere
+    #[derive(Clone, Default)]
+    struct Signal(Arc<Mutex<(bool, Option<Waker>)>>);
+
+    impl Signal {
+        fn fire(&self) {
+            let waker = {
+                let mut state = lock(&self.0);
+                state.0 = true;
+                state.1.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Future for Signal {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = lock(&self.0);
+            if state.0 {
+                return Poll::Ready(());
+            }
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    // pending once, waking itself straight away
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn future_runs_to_completion() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        assert_eq!(block_on(pool.spawn_future(async { 1 + 2 })).unwrap(), 3);
+    }
+
+    #[test]
+    fn woken_futures_are_polled_again() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counted = polls.clone();
+        let result = pool.spawn_future(async move {
+            for _ in 0..10 {
+                counted.fetch_add(1, Ordering::SeqCst);
+                YieldNow(false).await;
+            }
+            "yielded"
+        });
+        assert_eq!(block_on(result).unwrap(), "yielded");
+        assert_eq!(polls.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn waker_from_another_thread() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let signal = Signal::default();
+        let result = pool.spawn_future({
+            let signal = signal.clone();
+            async move {
+                signal.await;
+                42
+            }
+        });
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            signal.fire();
+        });
+        assert_eq!(block_on(result).unwrap(), 42);
+    }
+
+    #[test]
+    fn futures_await_blocking_work() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let inner = pool.clone();
+        let result = pool.spawn_future(async move {
+            let sums = (0..4).map(|i| inner.spawn_blocking(move || (0..=i * 1000).sum::<u64>()));
+            let mut total = 0;
+            for sum in sums.collect::<Vec<_>>() {
+                total += sum.await.unwrap();
+            }
+            total
+        });
+        assert_eq!(block_on(result).unwrap(), 500_500 + 2_001_000 + 4_501_500);
+    }
+
+    #[test]
+    fn panics_come_back_as_errors() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let error = block_on(pool.spawn_future(async { panic!("async failure") })).unwrap_err();
+        assert_eq!(error.panic_message(), Some("async failure"));
+        let error =
+            block_on(pool.spawn_blocking(|| -> u8 { panic!("blocking failure") })).unwrap_err();
+        assert_eq!(error.panic_message(), Some("blocking failure"));
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn futures_woken_on_a_full_queue_are_queued() {
+        let pool = PoolBuilder::new()
+            .max_threads(1)
+            .queue_capacity(1, OverflowPolicy::Reject)
+            .thread_name("worker")
+            .build()
+            .unwrap();
+        let signal = Signal::default();
+        let (polled, first_poll) = mpsc::channel();
+        let result = pool.spawn_future({
+            let signal = signal.clone();
+            async move {
+                polled.send(()).unwrap();
+                signal.await;
+                thread::current().name().map(String::from)
+            }
+        });
+        first_poll.recv().unwrap();
+
+        // keeps the only worker busy and the queue full
+        let (started, running) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        assert_eq!(pool.try_spawn(|| {}), Ok(()));
+        assert_eq!(pool.try_spawn(|| {}), Err(SpawnError::QueueFull));
+
+        signal.fire();
+        assert_eq!(pool.stats().queued, 2);
+        release.send(()).unwrap();
+        assert_eq!(block_on(result).unwrap().as_deref(), Some("worker-0"));
+    }
+
+    #[test]
+    fn futures_that_wake_themselves_on_a_full_queue() {
+        let pool = PoolBuilder::new()
+            .max_threads(1)
+            .queue_capacity(1, OverflowPolicy::Reject)
+            .build()
+            .unwrap();
+        let spawner = pool.clone();
+        let result = pool.spawn_future(async move {
+            // stays queued behind the future while it yields
+            spawner.try_spawn(|| {}).unwrap();
+            for _ in 0..100_000 {
+                YieldNow(false).await;
+            }
+            "yielded"
+        });
+        assert_eq!(block_on(result).unwrap(), "yielded");
+    }
+
+    #[test]
+    fn unreachable_futures_are_cancelled() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        // nothing holds on to its waker
+        let never = pool.spawn_future(std::future::pending::<()>());
+        assert!(matches!(block_on(never), Err(TaskError::Cancelled)));
+
+        // woken after the pool is gone
+        let signal = Signal::default();
+        let result = pool.spawn_future(signal.clone());
+        pool.shutdown();
+        signal.fire();
+        assert!(matches!(block_on(result), Err(TaskError::Cancelled)));
+    }
+}
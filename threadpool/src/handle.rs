@@ -0,0 +1,99 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum TaskError {
+    /// The task panicked, holds the payload passed to `panic!`.
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The task was dropped without running, because the pool was shut down
+    /// or its [`crate::CancellationToken`] was cancelled.
+    Cancelled,
+}
+
+impl TaskError {
+    /// Message of the panic, if it was a string.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            TaskError::Panicked(payload) => payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str)),
+            TaskError::Cancelled => None,
+        }
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.panic_message()) {
+            (TaskError::Panicked(_), Some(message)) => write!(f, "task panicked: {message}"),
+            (TaskError::Panicked(_), None) => write!(f, "task panicked"),
+            (TaskError::Cancelled, _) => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+impl Error for TaskError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The queue was full and the pool rejects tasks while it is.
+    QueueFull,
+    /// The pool has been shut down.
+    ShutDown,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::QueueFull => write!(f, "task queue is full"),
+            SpawnError::ShutDown => write!(f, "pool has been shut down"),
+        }
+    }
+}
+
+impl Error for SpawnError {}
+
+/// Result of a task started with [`crate::Pool::spawn_with_handle`].
+/// Dropping the handle detaches the task, it still runs.
+#[derive(Debug)]
+pub struct TaskHandle<T> {
+    receiver: Receiver<thread::Result<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    pub(crate) fn new(receiver: Receiver<thread::Result<T>>) -> Self {
+        Self { receiver }
+    }
+
+    /// Blocks until the task has finished.
+    pub fn join(self) -> Result<T, TaskError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(TaskError::Panicked),
+            Err(_) => Err(TaskError::Cancelled),
+        }
+    }
+
+    /// Result of the task if it has finished, the handle back otherwise.
+    pub fn try_join(self) -> Result<Result<T, TaskError>, Self> {
+        match self.receiver.try_recv() {
+            Ok(result) => Ok(result.map_err(TaskError::Panicked)),
+            Err(TryRecvError::Disconnected) => Ok(Err(TaskError::Cancelled)),
+            Err(TryRecvError::Empty) => Err(self),
+        }
+    }
+
+    /// Like [`TaskHandle::join`], but gives the handle back if the task does
+    /// not finish within `timeout`.
+    pub fn join_timeout(self, timeout: Duration) -> Result<Result<T, TaskError>, Self> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Ok(result.map_err(TaskError::Panicked)),
+            Err(RecvTimeoutError::Disconnected) => Ok(Err(TaskError::Cancelled)),
+            Err(RecvTimeoutError::Timeout) => Err(self),
+        }
+    }
+}
@@ -1,81 +1,933 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
-use std::sync::mpsc::{self, Receiver, RecvError, Sender};
-use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
 use std::thread::JoinHandle;
-use std::{mem, thread};
+use std::time::{Duration, Instant};
+use std::{io, mem, thread};
 
-type Task = Box<dyn FnOnce() + Send + 'static>;
+mod builder;
+mod cancel;
+mod executor;
+mod handle;
+mod par;
+mod scope;
+mod stats;
+mod timer;
 
-#[derive(Debug, Clone)]
-pub struct Pool {
-    handles: Arc<VecDeque<JoinHandle<()>>>,
+pub use builder::{BuildError, OverflowPolicy, PoolBuilder};
+pub use cancel::CancellationToken;
+pub use executor::{block_on, TaskFuture};
+pub use handle::{SpawnError, TaskError, TaskHandle};
+pub use scope::Scope;
+pub use stats::PoolStats;
+pub use timer::TimerHandle;
+
+/// A queued task, as handed back by [`Pool::shutdown_now`].
+pub type Task = Box<dyn FnOnce() + Send + 'static>;
+type PanicHandler = Arc<dyn Fn(&TaskError) + Send + Sync + 'static>;
 
-    // Sender will send tasks to threads
-    sender: Option<Sender<Task>>,
+/// Order in which queued tasks are started, see [`Pool::spawn_with_priority`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Only runs when no other task is waiting.
+    Low,
+    /// Tasks started with [`Pool::spawn`].
+    #[default]
+    Normal,
+    /// Runs before any queued task of lower priority.
+    High,
 }
 
-impl Pool {
-    pub fn with_thread_count(n: usize) -> Option<Self> {
-        if n == 0 {
-            return None;
+struct Job {
+    task: Task,
+    spawned: Instant,
+    // spawned in a scope, so it may borrow from the stack
+    scoped: bool,
+}
+
+// Every worker owns a deque. It pushes and pops its own tasks at the back, so
+// the most recently spawned (and cache-warm) task runs first, and steals from
+// the front of the other deques once its own runs dry. Each deque has its own
+// lock, so workers only contend when they steal from the same victim. Tasks
+// with a priority other than `Normal` go to one queue per priority shared by
+// all workers.
+//
+// There is a deque for every thread the pool may grow to. Workers come and
+// go between the minimum and maximum thread count, the deque of a slot
+// without a worker is emptied by stealing.
+struct Shared {
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    high: Mutex<VecDeque<Job>>,
+    // tasks in `high`, so workers only take its lock when there are some
+    high_queued: AtomicUsize,
+    low: Mutex<VecDeque<Job>>,
+    // tasks sitting in any of the queues, or about to be queued
+    queued: AtomicUsize,
+    // round robin over the queues for tasks spawned outside the pool
+    next_queue: AtomicUsize,
+    shutdown: AtomicBool,
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+    // spawners blocked on a full queue wait here for a task to be taken
+    blocked: AtomicUsize,
+    full: Mutex<()>,
+    space: Condvar,
+    // workers that ran out of tasks and are still looking for more
+    searching: AtomicUsize,
+    // idle workers sleep here until a task is queued or the pool shuts down
+    sleeping: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    min_threads: usize,
+    keep_alive: Duration,
+    thread_name: Option<String>,
+    // which queues have a worker, `live` counts them
+    slots: Mutex<Vec<bool>>,
+    live: AtomicUsize,
+    // workers running a task right now
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    // tasks that panicked without a handle to report to
+    panics: AtomicUsize,
+    // nanoseconds from spawn to finish, summed over all finished tasks
+    latency: AtomicU64,
+    panic_handler: RwLock<Option<PanicHandler>>,
+    // worker threads, including replacements for workers that died
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    // held while waiting for the workers to exit
+    joining: Mutex<()>,
+    timers: timer::Timers,
+}
+
+thread_local! {
+    // (pool, queue index) of the worker running on this thread
+    static WORKER: Cell<Option<(*const Shared, usize)>> = const { Cell::new(None) };
+}
+
+const SPIN_ATTEMPTS: usize = 12;
+
+// tasks never run while a lock is held, so a poisoned lock still guards
+// consistent data and the pool keeps going
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn spawn_worker(shared: Arc<Shared>, index: usize) -> io::Result<JoinHandle<()>> {
+    shared.thread_builder(index).spawn(move || {
+        WORKER.set(Some((Arc::as_ptr(&shared), index)));
+        let sentinel = Sentinel { shared, index };
+        sentinel.shared.run_worker(index);
+    })
+}
+
+// Replaces the worker if its thread unwinds despite the tasks being run
+// inside `catch_unwind`, e.g. because dropping a panic payload panicked.
+struct Sentinel {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        match spawn_worker(self.shared.clone(), self.index) {
+            Ok(handle) => lock(&self.shared.workers).push(handle),
+            // the slot is free for `grow` to try again
+            Err(_) => {
+                self.shared.retire(self.index, true);
+            }
+        }
+    }
+}
+
+// who queues a task, for the ones `push` treats differently
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Caller,
+    // queued even when the queue is full, see `reserve`
+    Timer,
+    // a future that was woken, also queued past a full queue
+    Woken,
+    // scoped tasks never leave the pool, see `take_queued`
+    Scope,
+}
+
+// what to do with a task after trying to make room for it in the queue
+enum Reservation {
+    Queued,
+    RunHere,
+    Refused(SpawnError),
+}
+
+impl Shared {
+    // queue index if the calling thread is one of our workers
+    fn current_worker(self: &Arc<Self>) -> Option<usize> {
+        match WORKER.get() {
+            Some((pool, index)) if pool == Arc::as_ptr(self) => Some(index),
+            _ => None,
         }
+    }
 
-        // thread communication
-        let (sender, rx) = mpsc::channel::<Task>();
+    fn thread_builder(&self, suffix: impl std::fmt::Display) -> thread::Builder {
+        match &self.thread_name {
+            Some(prefix) => thread::Builder::new().name(format!("{prefix}-{suffix}")),
+            None => thread::Builder::new(),
+        }
+    }
 
-        // Receiver<T> !Sync -> needs mutex
-        let rx = Arc::new(Mutex::new(rx));
+    // gives the task back if the pool has been shut down or its queue is full
+    fn push(self: &Arc<Self>, task: Task, priority: Priority) -> Result<(), (SpawnError, Task)> {
+        self.push_from(task, priority, Origin::Caller)
+    }
+
+    fn push_from(
+        self: &Arc<Self>,
+        task: Task,
+        priority: Priority,
+        origin: Origin,
+    ) -> Result<(), (SpawnError, Task)> {
+        let worker = self.current_worker();
+        match self.reserve(origin, worker.is_some()) {
+            Reservation::Queued => {}
+            Reservation::RunHere => {
+                self.run_here(task);
+                return Ok(());
+            }
+            Reservation::Refused(error) => return Err((error, task)),
+        }
 
-        // generate hanging threads with receivers
-        let handles = (0..n)
-            .map(|_| {
-                let rx = rx.clone();
-                thread::spawn(move || {
-                    while let Ok(t) = get_task(&rx) {
-                        // thread is blocked if no task
-                        t()
+        let queue = match priority {
+            Priority::High => &self.high,
+            Priority::Low => &self.low,
+            Priority::Normal => {
+                let index = worker.unwrap_or_else(|| {
+                    self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len()
+                });
+                &self.queues[index]
+            }
+        };
+        {
+            let mut queue = lock(queue);
+            // running tasks can still add more while the queues are drained.
+            // Checking under the lock means `take_queued` sees every task
+            // that got past here
+            if worker.is_none() && self.shutdown.load(Ordering::SeqCst) {
+                drop(queue);
+                self.release(1);
+                return Err((SpawnError::ShutDown, task));
+            }
+            queue.push_back(Job {
+                task,
+                spawned: Instant::now(),
+                scoped: origin == Origin::Scope,
+            });
+            if priority == Priority::High {
+                self.high_queued.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // a worker that is awake and looking for tasks will pick this one up
+        if self.searching.load(Ordering::SeqCst) == 0 {
+            self.wake_one();
+        }
+        Ok(())
+    }
+
+    // Counts the task in `queued` before it is queued, so spawners never
+    // exceed the capacity. Timers go past it, waiting for room or running the
+    // task on the timer thread would hold up every later timer. So do woken
+    // futures, polling one in place could recurse once it wakes itself, or
+    // run it inside whatever called `wake`
+    fn reserve(&self, origin: Origin, on_worker: bool) -> Reservation {
+        let bypass = matches!(origin, Origin::Timer | Origin::Woken);
+        let Some(capacity) = self.capacity.filter(|_| !bypass) else {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            return Reservation::Queued;
+        };
+        loop {
+            let queued = self.queued.load(Ordering::SeqCst);
+            if queued < capacity {
+                let next = queued + 1;
+                if self
+                    .queued
+                    .compare_exchange_weak(queued, next, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Reservation::Queued;
+                }
+                continue;
+            }
+            match self.overflow {
+                OverflowPolicy::Reject => return Reservation::Refused(SpawnError::QueueFull),
+                OverflowPolicy::CallerRuns => return Reservation::RunHere,
+                // the worker might be the one that has to make room
+                OverflowPolicy::Block if on_worker => return Reservation::RunHere,
+                OverflowPolicy::Block => {
+                    let mut guard = lock(&self.full);
+                    self.blocked.fetch_add(1, Ordering::SeqCst);
+                    while self.queued.load(Ordering::SeqCst) >= capacity
+                        && !self.shutdown.load(Ordering::SeqCst)
+                    {
+                        guard = self
+                            .space
+                            .wait(guard)
+                            .unwrap_or_else(PoisonError::into_inner);
+                    }
+                    self.blocked.fetch_sub(1, Ordering::SeqCst);
+                    if self.shutdown.load(Ordering::SeqCst) {
+                        return Reservation::Refused(SpawnError::ShutDown);
                     }
-                })
+                }
+                // dropped outside of any lock, it may spawn in its destructor
+                OverflowPolicy::DropOldest => drop(self.take_oldest()),
+            }
+        }
+    }
+
+    // gives back places in the queue for spawners waiting on a full one
+    fn release(&self, count: usize) {
+        self.queued.fetch_sub(count, Ordering::SeqCst);
+        // a blocked spawner announces itself before checking `queued`, the
+        // same way sleeping workers do
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            drop(lock(&self.full));
+            self.space.notify_all();
+        }
+    }
+
+    fn wake_one(self: &Arc<Self>) {
+        // a worker announces itself in `sleeping` before checking `queued`,
+        // so either it sees the new task or we see it and wake it up. Taking
+        // the lock waits until it actually sleeps
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let guard = lock(&self.sleep);
+            // it may have retired instead of going to sleep
+            if self.sleeping.load(Ordering::SeqCst) > 0 {
+                drop(guard);
+                self.wake.notify_one();
+                return;
+            }
+        }
+        // every worker is busy
+        self.grow();
+    }
+
+    fn grow(self: &Arc<Self>) {
+        // `live` only changes under the slots lock, a pool at its maximum
+        // does not need to take it on every spawn
+        if self.shutdown.load(Ordering::SeqCst)
+            || self.live.load(Ordering::SeqCst) == self.queues.len()
+        {
+            return;
+        }
+        let mut slots = lock(&self.slots);
+        if let Some(index) = slots.iter().position(|used| !used) {
+            // the workers that are already running get to the task eventually
+            let _ = self.start_worker(&mut slots, index);
+        }
+    }
+
+    fn start_worker(self: &Arc<Self>, slots: &mut [bool], index: usize) -> io::Result<()> {
+        let handle = spawn_worker(self.clone(), index)?;
+        slots[index] = true;
+        self.live.fetch_add(1, Ordering::SeqCst);
+        let mut workers = lock(&self.workers);
+        // workers that retired leave their handles behind
+        workers.retain(|handle| !handle.is_finished());
+        workers.push(handle);
+        Ok(())
+    }
+
+    // frees the slot of a worker that is about to exit, which only happens
+    // above the minimum unless `force` is set
+    fn retire(&self, index: usize, force: bool) -> bool {
+        let mut slots = lock(&self.slots);
+        if !force && self.live.load(Ordering::SeqCst) <= self.min_threads {
+            return false;
+        }
+        slots[index] = false;
+        self.live.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+
+    fn pop(&self, index: usize) -> Option<Job> {
+        let job = self
+            .pop_high()
+            .or_else(|| lock(&self.queues[index]).pop_back())
+            .or_else(|| {
+                // start with the right-hand neighbour so thieves spread out
+                (1..self.queues.len())
+                    .map(|offset| (index + offset) % self.queues.len())
+                    .find_map(|victim| lock(&self.queues[victim]).pop_front())
             })
-            .collect();
+            .or_else(|| lock(&self.low).pop_front())?;
+        self.release(1);
+        Some(job)
+    }
+
+    fn pop_high(&self) -> Option<Job> {
+        if self.high_queued.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let job = lock(&self.high).pop_front()?;
+        self.high_queued.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn take_oldest(&self) -> Option<Job> {
+        // the front of every queue is the task that has waited there longest
+        let queues = std::iter::once(&self.high)
+            .chain(&self.queues)
+            .chain([&self.low]);
+        let (queue, _) = queues
+            .filter_map(|queue| Some((queue, lock(queue).front()?.spawned)))
+            .min_by_key(|&(_, spawned)| spawned)?;
+        let job = lock(queue).pop_front()?;
+        if std::ptr::eq(queue, &self.high) {
+            self.high_queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.release(1);
+        Some(job)
+    }
+
+    fn take_queued(&self) -> Vec<Task> {
+        let mut jobs = Vec::new();
+        {
+            let mut high = lock(&self.high);
+            self.high_queued.fetch_sub(high.len(), Ordering::SeqCst);
+            jobs.extend(high.drain(..));
+        }
+        for queue in self.queues.iter().chain([&self.low]) {
+            jobs.extend(lock(queue).drain(..));
+        }
+        self.release(jobs.len());
+        // a scoped task only borrows safely while its scope waits for it, so
+        // it is dropped here rather than handed out as 'static. The scope
+        // counts it as finished
+        jobs.into_iter()
+            .filter(|job| !job.scoped)
+            .map(|job| job.task)
+            .collect()
+    }
+
+    fn run_here(&self, task: Task) {
+        self.run(Job {
+            task,
+            spawned: Instant::now(),
+            scoped: false,
+        });
+    }
+
+    fn run(&self, job: Job) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(job.task));
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        let latency = job.spawned.elapsed().as_nanos();
+        self.latency
+            .fetch_add(latency.try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+
+        let Err(payload) = result else {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+            return;
+        };
+        self.panics.fetch_add(1, Ordering::SeqCst);
+        let error = TaskError::Panicked(payload);
+        let handler = self
+            .panic_handler
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if let Some(handler) = handler {
+            // a panicking handler must not take the worker down either
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(&error)));
+        }
+    }
+
+    fn run_worker(self: &Arc<Self>, index: usize) {
+        let mut searching = false;
+        let mut attempts = 0;
+        loop {
+            if let Some(job) = self.pop(index) {
+                // the last worker to stop searching hands the job over to a
+                // sleeping one if there is more to do
+                if mem::take(&mut searching)
+                    && self.searching.fetch_sub(1, Ordering::SeqCst) == 1
+                    && self.queued.load(Ordering::SeqCst) > 0
+                {
+                    self.wake_one();
+                }
+                self.run(job);
+                attempts = 0;
+                continue;
+            }
+            if !searching {
+                searching = true;
+                self.searching.fetch_add(1, Ordering::SeqCst);
+            }
+            // going to sleep and being woken up costs far more than a task
+            // that is about to arrive, so look again a few times first
+            if attempts < SPIN_ATTEMPTS {
+                attempts += 1;
+                thread::yield_now();
+                continue;
+            }
+            attempts = 0;
+            self.searching.fetch_sub(1, Ordering::SeqCst);
+
+            let mut guard = lock(&self.sleep);
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            // remaining tasks are drained before shutting down
+            let idle = || self.queued.load(Ordering::SeqCst) == 0;
+            if idle() && self.shutdown.load(Ordering::SeqCst) {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                self.retire(index, true);
+                return;
+            }
+            if idle() {
+                let timeout;
+                (guard, timeout) = self
+                    .wake
+                    .wait_timeout(guard, self.keep_alive)
+                    .unwrap_or_else(PoisonError::into_inner);
+                // `sleeping` goes down under the lock, `wake_one` relies on it
+                if timeout.timed_out() && idle() && self.retire(index, false) {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            }
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+            drop(guard);
+            // a woken worker searches, so pushes do not wake more of them
+            searching = true;
+            self.searching.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Stops accepting tasks from outside the pool and lets the workers exit
+    // once the queues are empty. Without `drain` the queues are emptied
+    // first and the tasks returned.
+    fn shut_down(self: &Arc<Self>, drain: bool) -> Vec<Task> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let mut pending = if drain {
+            Vec::new()
+        } else {
+            self.take_queued()
+        };
+        drop(lock(&self.sleep));
+        self.wake.notify_all();
+        drop(lock(&self.full));
+        self.space.notify_all();
+        self.stop_timers();
+
+        // a worker cannot wait for itself, it exits after its current task
+        if self.current_worker().is_some() {
+            return pending;
+        }
+        let _joining = lock(&self.joining);
+        // replacements can show up while joining, so keep collecting them
+        loop {
+            let handle = lock(&self.workers).pop();
+            let Some(handle) = handle else { break };
+            let _ = handle.join();
+        }
+        // tasks that raced with the shutdown flag and found no worker left
+        pending.extend(self.take_queued());
+        pending
+    }
+}
+
+// Shuts the pool down once the last clone of a `Pool` is gone. Workers only
+// hold on to `Shared`, so they do not keep the pool alive themselves.
+struct Owner(Arc<Shared>);
 
-        Some(Self {
-            handles: Arc::new(handles),
-            sender: Some(sender),
-        })
+impl Drop for Owner {
+    fn drop(&mut self) {
+        self.0.shut_down(true);
     }
+}
+
+/// Handle to a set of worker threads. Clones are cheap and all refer to the
+/// same pool, which shuts down when the last clone is dropped or when any
+/// of them calls [`Pool::shutdown`].
+#[derive(Clone)]
+pub struct Pool {
+    // queues shared with the worker threads
+    shared: Arc<Shared>,
+    _owner: Arc<Owner>,
+}
 
+impl std::fmt::Debug for Pool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pool")
+            .field("threads", &self.thread_count())
+            .field("queued", &self.shared.queued.load(Ordering::Relaxed))
+            .field("panics", &self.panic_count())
+            .field("shutdown", &self.is_shutdown())
+            .finish()
+    }
+}
+
+impl Pool {
+    /// Pool with exactly `n` threads and an unbounded queue.
+    pub fn with_thread_count(n: usize) -> Option<Self> {
+        Self::builder().min_threads(n).max_threads(n).build().ok()
+    }
+
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    /// Number of worker threads right now.
+    pub fn thread_count(&self) -> usize {
+        self.shared.live.load(Ordering::SeqCst)
+    }
+
+    /// Number of threads the pool can grow to.
+    pub fn max_thread_count(&self) -> usize {
+        self.shared.queues.len()
+    }
+
+    /// Queues `f` to run on one of the worker threads. Tasks spawned from a
+    /// worker go to that worker's own queue.
+    ///
+    /// Once the pool is shut down, `f` is dropped without running unless it
+    /// is spawned by another task of this pool. The same goes for a full
+    /// queue that rejects tasks, see [`Pool::try_spawn`].
     pub fn spawn<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let task = Box::new(f);
-        // sender .as_ref() does &Option<T> to Option<&T>
-        let _ = self.sender.as_ref().unwrap().send(task);
+        self.spawn_with_priority(Priority::Normal, f);
+    }
+
+    /// Like [`Pool::spawn`], but queued tasks with a higher `priority` start
+    /// first. Tasks of the same priority start in no particular order.
+    pub fn spawn_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // dropping the task tells its handle or scope that it was cancelled
+        let _ = self.shared.push(Box::new(f), priority);
+    }
+
+    /// Like [`Pool::spawn`], but says why `f` was dropped instead of queued.
+    pub fn try_spawn<F>(&self, f: F) -> Result<(), SpawnError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .push(Box::new(f), Priority::Normal)
+            .map_err(|(error, _)| error)
+    }
+
+    /// Like [`Pool::spawn`], but hands back the return value of `f`. A panic
+    /// in `f` is caught and returned as [`TaskError::Panicked`].
+    pub fn spawn_with_handle<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            // nobody is interested in the result once the handle is dropped
+            let _ = sender.send(result);
+        });
+        TaskHandle::new(receiver)
+    }
+
+    /// Calls `handler` on the worker thread whenever a task started with
+    /// [`Pool::spawn`] panics, replacing the previous handler. The worker
+    /// carries on with the next task either way.
+    pub fn set_panic_handler<H>(&self, handler: H)
+    where
+        H: Fn(&TaskError) + Send + Sync + 'static,
+    {
+        *self
+            .shared
+            .panic_handler
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(handler));
+    }
+
+    /// Number of tasks that panicked with nobody to report to, such as those
+    /// started with [`Pool::spawn`] or a timer. Panics handed to a
+    /// [`TaskHandle`], a [`TaskFuture`] or a scope are not counted.
+    pub fn panic_count(&self) -> usize {
+        self.shared.panics.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting new tasks, runs the ones already queued and waits for
+    /// the workers to exit. Called from inside a task, it returns right away
+    /// and the workers exit once they are done.
+    pub fn shutdown(&self) {
+        // only tasks that raced with the shutdown can be left over
+        drop(self.shared.shut_down(true));
+    }
+
+    /// Like [`Pool::shutdown`], but takes the queued tasks out instead of
+    /// running them. Tasks that are already running still finish. Queued
+    /// tasks of a [`Pool::scope`] are dropped rather than handed back.
+    pub fn shutdown_now(&self) -> Vec<Task> {
+        self.shared.shut_down(false)
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
     }
 }
 
-impl Drop for Pool {
-    fn drop(&mut self) {
-        // will own arc and put default vector into self.handles
-        //let arc = mem::replace(&mut self.handles, Arc::new(Default::default()));
-        let arc = mem::take(&mut self.handles);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
 
-        // success if its the last copy
-        if let Ok(handles) = Arc::try_unwrap(arc) {
-            mem::drop(self.sender.take()); // if sender lives the receiver will block thread
-            for jh in handles {
-                jh.join().unwrap();
+    #[test]
+    fn zero_threads_is_none() {
+        assert!(Pool::with_thread_count(0).is_none());
+    }
+
+    #[test]
+    fn drop_runs_every_queued_task() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = Pool::with_thread_count(4).unwrap();
+            for _ in 0..10_000 {
+                let counter = counter.clone();
+                pool.spawn(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                });
             }
         }
+        assert_eq!(counter.load(Ordering::Relaxed), 10_000);
     }
-}
 
-pub fn get_task(rx: &Mutex<Receiver<Task>>) -> Result<Task, RecvError> {
-    // blocks the thread if no task
-    if let Ok(inner) = rx.lock() {
-        inner.recv().map_err(|_| RecvError)
-    } else {
-        Err(RecvError)
+    #[test]
+    fn tasks_spawned_from_workers_run() {
+        let (sender, receiver) = mpsc::channel();
+        let pool = Pool::with_thread_count(2).unwrap();
+        let inner = pool.clone();
+        pool.spawn(move || {
+            for i in 0..100 {
+                let sender = sender.clone();
+                inner.spawn(move || sender.send(i).unwrap());
+            }
+        });
+
+        let mut received: Vec<i32> = receiver.iter().take(100).collect();
+        received.sort();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn idle_workers_steal_from_a_busy_one() {
+        let pool = Pool::with_thread_count(4).unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        let (done, finished) = mpsc::channel();
+
+        // the first task blocks its worker until all others are done, some of
+        // which were queued behind it and can only run if they get stolen
+        pool.spawn(move || blocked.recv_timeout(Duration::from_secs(10)).unwrap());
+        for _ in 0..100 {
+            let done = done.clone();
+            pool.spawn(move || done.send(()).unwrap());
+        }
+
+        for _ in 0..100 {
+            finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn panicking_tasks_are_counted_and_reported() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let (sender, reports) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        pool.set_panic_handler(move |error| {
+            let message = error.panic_message().unwrap_or_default().to_string();
+            lock(&sender).send(message).unwrap();
+        });
+
+        for i in 0..4 {
+            pool.spawn(move || panic!("task {i} failed"));
+        }
+        let mut messages: Vec<String> = reports.iter().take(4).collect();
+        messages.sort();
+        assert_eq!(messages[0], "task 0 failed");
+        assert_eq!(pool.panic_count(), 4);
+
+        // both workers are still alive
+        let handles: Vec<_> = (0..8).map(|i| pool.spawn_with_handle(move || i)).collect();
+        let sum: i32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sum, 28);
+    }
+
+    #[test]
+    fn panicking_handler_is_contained() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        pool.set_panic_handler(|_| panic!("handler failed"));
+        pool.spawn(|| panic!("task failed"));
+        assert_eq!(pool.spawn_with_handle(|| 1).join().unwrap(), 1);
+        assert_eq!(pool.panic_count(), 1);
+    }
+
+    #[test]
+    fn dead_worker_is_replaced() {
+        // a payload that panics again when dropped kills the worker thread
+        struct Bomb;
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("payload dropped");
+            }
+        }
+
+        let pool = Pool::with_thread_count(1).unwrap();
+        pool.spawn(|| panic::panic_any(Bomb));
+        let handle = pool.spawn_with_handle(|| "still running");
+        assert_eq!(handle.join().unwrap(), "still running");
+    }
+
+    #[test]
+    fn last_copy_dropped_inside_a_task() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let inner = pool.clone();
+        drop(pool);
+        let copy = inner.clone();
+        inner.spawn(move || {
+            drop(copy);
+            sender.send(()).unwrap();
+        });
+        drop(inner);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn handle_returns_the_result() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let handles: Vec<_> = (0..10)
+            .map(|i| pool.spawn_with_handle(move || i * i))
+            .collect();
+        let squares: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(squares, (0..10).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panic_comes_back_as_error() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let error = pool
+            .spawn_with_handle(|| -> u32 { panic!("boom {}", 42) })
+            .join()
+            .unwrap_err();
+        assert_eq!(error.panic_message(), Some("boom 42"));
+        assert_eq!(error.to_string(), "task panicked: boom 42");
+
+        // the worker survives and runs the next task
+        assert_eq!(pool.spawn_with_handle(|| 7).join().unwrap(), 7);
+    }
+
+    #[test]
+    fn try_join_and_timeout_give_the_handle_back() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        let handle = pool.spawn_with_handle(move || {
+            blocked.recv().unwrap();
+            "done"
+        });
+
+        let handle = handle.try_join().unwrap_err();
+        let handle = handle.join_timeout(Duration::from_millis(20)).unwrap_err();
+        release.send(()).unwrap();
+        let result = handle.join_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(result.unwrap(), "done");
+    }
+
+    #[test]
+    fn shutdown_runs_queued_tasks_and_refuses_new_ones() {
+        let pool = Pool::with_thread_count(3).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..1000 {
+            let counter = counter.clone();
+            pool.spawn(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::Relaxed), 1000);
+
+        assert!(pool.is_shutdown());
+        let handle = pool.spawn_with_handle(|| 1);
+        assert!(matches!(handle.join(), Err(TaskError::Cancelled)));
+        pool.shutdown();
+    }
+
+    #[test]
+    fn shutdown_now_hands_back_queued_tasks() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (go, ready) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        let probe = pool.clone();
+        // occupies the only worker until the queue has been emptied
+        pool.spawn(move || {
+            started.send(()).unwrap();
+            ready.recv().unwrap();
+            while probe.stats().queued > 0 {
+                thread::yield_now();
+            }
+        });
+        running.recv().unwrap();
+        for _ in 0..10 {
+            let counter = counter.clone();
+            pool.spawn(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        go.send(()).unwrap();
+
+        let pending = pool.shutdown_now();
+        assert_eq!(pending.len(), 10);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        pending.into_iter().for_each(|task| task());
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn clones_share_one_pool() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let copy = pool.clone();
+        drop(pool.clone());
+        assert_eq!(copy.spawn_with_handle(|| 3).join().unwrap(), 3);
+
+        copy.shutdown();
+        assert!(pool.is_shutdown());
+        drop(copy);
+        assert_eq!(pool.stats().completed, 1);
+    }
+
+    #[test]
+    fn higher_priority_tasks_start_first() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || blocked.recv().unwrap());
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            for _ in 0..2 {
+                let order = order.clone();
+                pool.spawn_with_priority(priority, move || lock(&order).push(priority));
+            }
+        }
+        release.send(()).unwrap();
+        pool.shutdown();
+
+        let expected = [Priority::High, Priority::Normal, Priority::Low];
+        assert_eq!(*lock(&order), expected.map(|p| [p, p]).concat());
     }
 }
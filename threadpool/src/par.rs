@@ -0,0 +1,198 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Pool;
+
+// chunks per worker when the caller does not pick a chunk size, more than
+// one so that stealing can even out chunks that take longer than others
+const CHUNKS_PER_THREAD: usize = 4;
+
+impl Pool {
+    fn chunk_size_for(&self, len: usize) -> usize {
+        len.div_ceil(self.max_thread_count() * CHUNKS_PER_THREAD)
+            .max(1)
+    }
+
+    // runs `f` on every chunk with the offset of the chunk into `data`, the
+    // results are in the order of the chunks
+    fn run_chunks<'a, T, R, F>(&self, data: &'a [T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(usize, &'a [T]) -> R + Sync,
+    {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        let mut results: Vec<Option<R>> = data.chunks(chunk_size).map(|_| None).collect();
+        self.scope(|s| {
+            let f = &f;
+            let chunks = data.chunks(chunk_size).enumerate();
+            // workers run their own tasks newest first, spawning the chunks
+            // back to front makes the first ones run first
+            for ((i, chunk), slot) in chunks.zip(results.iter_mut()).rev() {
+                s.spawn(move || *slot = Some(f(i * chunk_size, chunk)));
+            }
+        });
+        // the scope re-raises panics, so a slot can only be empty if its task
+        // was dropped by a pool that was shut down. The caller runs those
+        // chunks itself
+        let chunks = data.chunks(chunk_size).enumerate();
+        chunks
+            .zip(results)
+            .map(|((i, chunk), r)| r.unwrap_or_else(|| f(i * chunk_size, chunk)))
+            .collect()
+    }
+
+    /// Calls `f` on every chunk of `chunk_size` elements in parallel, returns
+    /// the results in the order of the chunks. Like the other `par_` helpers
+    /// it runs the chunks on the calling thread once the pool is shut down.
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is zero, or `f` panics on any chunk.
+    pub fn par_chunks<T, R, F>(&self, data: &[T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&[T]) -> R + Sync,
+    {
+        self.run_chunks(data, chunk_size, |_, chunk| f(chunk))
+    }
+
+    /// `data.iter().map(f).collect()` in parallel.
+    pub fn par_map<T, R, F>(&self, data: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        let size = self.chunk_size_for(data.len());
+        let mapped = self.run_chunks(data, size, |_, chunk| {
+            chunk.iter().map(&f).collect::<Vec<_>>()
+        });
+        mapped.into_iter().flatten().collect()
+    }
+
+    pub fn par_for_each<T, F>(&self, data: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        let size = self.chunk_size_for(data.len());
+        self.run_chunks(data, size, |_, chunk| chunk.iter().for_each(&f));
+    }
+
+    /// Combines all elements with `op`, which must be associative. Every
+    /// chunk starts from a copy of `identity`, so `op(identity, x)` must be `x`.
+    pub fn par_reduce<T, F>(&self, data: &[T], identity: T, op: F) -> T
+    where
+        T: Clone + Send + Sync,
+        F: Fn(T, T) -> T + Sync,
+    {
+        let size = self.chunk_size_for(data.len());
+        let partial = self.run_chunks(data, size, |_, chunk| {
+            chunk.iter().cloned().fold(identity.clone(), &op)
+        });
+        partial.into_iter().fold(identity, &op)
+    }
+
+    /// Index of some element that matches `predicate`, not necessarily the
+    /// first one. All chunks stop looking as soon as one finds a match.
+    pub fn par_find_any<T, F>(&self, data: &[T], predicate: F) -> Option<usize>
+    where
+        T: Sync,
+        F: Fn(&T) -> bool + Sync,
+    {
+        let found = AtomicBool::new(false);
+        let size = self.chunk_size_for(data.len());
+        let hits = self.run_chunks(data, size, |offset, chunk| {
+            for (i, x) in chunk.iter().enumerate() {
+                if found.load(Ordering::Relaxed) {
+                    return None;
+                }
+                if predicate(x) {
+                    found.store(true, Ordering::Relaxed);
+                    return Some(offset + i);
+                }
+            }
+            None
+        });
+        hits.into_iter().flatten().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicUsize;
+
+    fn pool() -> Pool {
+        Pool::with_thread_count(4).unwrap()
+    }
+
+    #[test]
+    fn chunks_keep_their_order() {
+        let data: Vec<u32> = (0..10).collect();
+        let sums = pool().par_chunks(&data, 3, |chunk| chunk.iter().sum::<u32>());
+        assert_eq!(sums, vec![3, 12, 21, 9]);
+    }
+
+    #[test]
+    fn map_matches_sequential_map() {
+        let data: Vec<u64> = (0..10_001).collect();
+        let squares = pool().par_map(&data, |x| x * x);
+        assert_eq!(squares, data.iter().map(|x| x * x).collect::<Vec<_>>());
+        assert!(pool().par_map(&[] as &[u64], |x| *x).is_empty());
+    }
+
+    #[test]
+    fn for_each_visits_every_element() {
+        let visited = AtomicUsize::new(0);
+        pool().par_for_each(&vec![1usize; 5000], |x| {
+            visited.fetch_add(*x, Ordering::Relaxed);
+        });
+        assert_eq!(visited.into_inner(), 5000);
+    }
+
+    #[test]
+    fn reduce_with_identity() {
+        let data: Vec<u64> = (1..=100).collect();
+        let pool = pool();
+        assert_eq!(pool.par_reduce(&data, 0, |a, b| a + b), 5050);
+        assert_eq!(pool.par_reduce(&data, 0, u64::max), 100);
+        assert_eq!(pool.par_reduce(&[], 1, |a: u64, b| a * b), 1);
+    }
+
+    #[test]
+    fn find_any_stops_early() {
+        let data: Vec<u32> = (0..1_000_000).collect();
+        let examined = AtomicUsize::new(0);
+        let found = pool().par_find_any(&data, |&x| {
+            examined.fetch_add(1, Ordering::Relaxed);
+            x == 10
+        });
+
+        assert_eq!(found, Some(10));
+        assert!(examined.into_inner() < data.len() / 2);
+        assert_eq!(pool().par_find_any(&data, |&x| x > 2_000_000), None);
+    }
+
+    #[test]
+    fn panics_reach_the_caller() {
+        let pool = pool();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.par_for_each(&[1, 2, 3], |&x| assert_ne!(x, 2, "bad element"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(pool.par_map(&[1, 2], |x| x + 1), vec![2, 3]);
+    }
+
+    #[test]
+    fn shut_down_pool_runs_chunks_on_the_caller() {
+        let pool = pool();
+        pool.shutdown();
+        let data: Vec<u64> = (1..=100).collect();
+        assert_eq!(pool.par_map(&data, |x| x * 2)[99], 200);
+        assert_eq!(pool.par_reduce(&data, 0, |a, b| a + b), 5050);
+        assert_eq!(pool.par_find_any(&data, |&x| x == 42), Some(41));
+    }
+}
@@ -0,0 +1,273 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::{lock, Origin, Pool, Priority, Shared, SpawnError, Task, TaskHandle};
+
+// how often a waiting worker looks for tasks it can help out with
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    // first panic of a task started with `Scope::spawn`
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+// Counts a task as finished when dropped, so a task that is dropped without
+// running cannot keep the scope waiting forever.
+struct Finish(Arc<ScopeState>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        let mut pending = lock(&self.0.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// Spawns tasks that may borrow data living outside [`Pool::scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    // invariant in both lifetimes, like `std::thread::Scope`
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Queues `f` on the pool. A panic in `f` is raised again once the
+    /// scope has finished.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *lock(&self.state.pending) += 1;
+        let finish = Finish(self.state.clone());
+        let task: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                lock(&finish.0.panic).get_or_insert(payload);
+            }
+            drop(finish);
+        });
+        // SAFETY: `Pool::scope` only returns once every task spawned here has
+        // run or been dropped, so nothing borrowed for 'scope is used later
+        let task: Task = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Task>(task) };
+        // a scope has to run all of its tasks, so it does not take no for an
+        // answer from a full queue. A pool that was shut down drops the task,
+        // which counts as finished
+        let pushed = self.shared.push_from(task, Priority::Normal, Origin::Scope);
+        if let Err((SpawnError::QueueFull, task)) = pushed {
+            self.shared.run_here(task);
+        }
+    }
+
+    /// Like [`Scope::spawn`], but hands back the return value of `f`. A panic
+    /// in `f` goes to the handle instead of the scope.
+    pub fn spawn_with_handle<F, T>(&'scope self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.spawn(move || {
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        TaskHandle::new(receiver)
+    }
+
+    fn wait(&self) {
+        let worker = self.shared.current_worker();
+        let mut pending = lock(&self.state.pending);
+        while *pending > 0 {
+            let Some(index) = worker else {
+                pending = self
+                    .state
+                    .done
+                    .wait(pending)
+                    .unwrap_or_else(PoisonError::into_inner);
+                continue;
+            };
+            // a worker that blocks here takes itself out of the pool, if every
+            // worker did that the tasks of the scope would never run
+            drop(pending);
+            match self.shared.pop(index) {
+                Some(task) => self.shared.run(task),
+                None => {
+                    let guard = lock(&self.state.pending);
+                    if *guard > 0 {
+                        let _ = self.state.done.wait_timeout(guard, HELP_INTERVAL);
+                    }
+                }
+            }
+            pending = lock(&self.state.pending);
+        }
+    }
+}
+
+impl Pool {
+    /// Runs `f` with a [`Scope`] whose tasks can borrow from the caller's
+    /// stack, like [`std::thread::scope`]. Returns once all of them have
+    /// finished, then raises the first panic of a task, if any.
+    ///
+    /// Called from inside a task, the worker runs queued tasks while it waits.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            shared: self.shared.clone(),
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        // tasks may still borrow from the stack when `f` panics, so wait for
+        // them before unwinding any further
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        if let Some(payload) = lock(&scope.state.panic).take() {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn tasks_borrow_from_the_stack() {
+        let pool = Pool::with_thread_count(4).unwrap();
+        let data: Vec<u64> = (1..=1000).collect();
+        let total = AtomicUsize::new(0);
+
+        pool.scope(|s| {
+            for chunk in data.chunks(100) {
+                let total = &total;
+                s.spawn(move || {
+                    total.fetch_add(chunk.iter().sum::<u64>() as usize, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(total.into_inner(), 500_500);
+    }
+
+    #[test]
+    fn handles_return_borrowed_results() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let words = ["pool".to_string(), "scope".to_string(), "task".to_string()];
+
+        let longest = pool.scope(|s| {
+            let handles: Vec<_> = words
+                .iter()
+                .map(|w| s.spawn_with_handle(move || (w.len(), w.as_str())))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .max()
+                .unwrap()
+        });
+        assert_eq!(longest, (5, "scope"));
+    }
+
+    #[test]
+    fn mutable_borrows_of_disjoint_chunks() {
+        let pool = Pool::with_thread_count(3).unwrap();
+        let mut data = vec![1; 64];
+        pool.scope(|s| {
+            for (i, chunk) in data.chunks_mut(16).enumerate() {
+                s.spawn(move || chunk.iter_mut().for_each(|x| *x *= i + 1));
+            }
+        });
+        assert_eq!(data.iter().sum::<usize>(), 16 * (1 + 2 + 3 + 4));
+    }
+
+    #[test]
+    fn task_panic_is_raised_after_all_tasks_finished() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("scoped failure"));
+                for _ in 0..10 {
+                    s.spawn(|| {
+                        std::thread::sleep(Duration::from_millis(1));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"scoped failure"));
+        assert_eq!(finished.load(Ordering::SeqCst), 10);
+        assert_eq!(pool.panic_count(), 0);
+    }
+
+    #[test]
+    fn shutdown_now_keeps_scoped_tasks() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let hits = AtomicUsize::new(0);
+        let (go, ready) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        let probe = pool.clone();
+
+        let pending = pool.scope(|s| {
+            // occupies the only worker until the queue has been emptied
+            s.spawn(move || {
+                started.send(()).unwrap();
+                ready.recv().unwrap();
+                while probe.stats().queued > 0 {
+                    std::thread::yield_now();
+                }
+            });
+            running.recv().unwrap();
+            for _ in 0..3 {
+                s.spawn(|| {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            go.send(()).unwrap();
+            pool.shutdown_now()
+        });
+        assert!(pending.is_empty());
+        assert_eq!(hits.into_inner(), 0);
+    }
+
+    #[test]
+    fn nested_scopes_on_a_single_worker_do_not_deadlock() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let inner_pool = pool.clone();
+        let count = AtomicUsize::new(0);
+        pool.scope(|s| {
+            s.spawn(|| {
+                inner_pool.scope(|inner| {
+                    for _ in 0..5 {
+                        inner.spawn(|| {
+                            count.fetch_add(1, Ordering::SeqCst);
+                        });
+                    }
+                });
+            });
+        });
+        assert_eq!(count.into_inner(), 5);
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::Pool;
+
+/// Snapshot of what a [`Pool`] is doing, see [`Pool::stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    pub threads: usize,
+    /// Tasks waiting in the queues.
+    pub queued: usize,
+    /// Workers running a task right now.
+    pub active: usize,
+    /// Tasks that returned without panicking. A task whose panic is handed
+    /// to a handle, a future or a scope counts as completed.
+    pub completed: usize,
+    /// Tasks that panicked with nobody to report to, as in
+    /// [`Pool::panic_count`].
+    pub panicked: usize,
+    /// Mean time from spawning a task until it finished.
+    pub average_latency: Duration,
+}
+
+impl Pool {
+    /// Counters are read one after the other while the workers keep going,
+    /// so they only add up once the pool is idle.
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        let completed = shared.completed.load(Ordering::SeqCst);
+        let panicked = shared.panics.load(Ordering::SeqCst);
+        let latency = shared.latency.load(Ordering::Relaxed);
+        let finished = (completed + panicked) as u64;
+        PoolStats {
+            threads: self.thread_count(),
+            queued: shared.queued.load(Ordering::SeqCst),
+            active: shared.active.load(Ordering::SeqCst),
+            completed,
+            panicked,
+            average_latency: Duration::from_nanos(latency.checked_div(finished).unwrap_or(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn counts_finished_tasks() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        assert_eq!(pool.stats().average_latency, Duration::ZERO);
+        for i in 0..12 {
+            pool.spawn(move || {
+                std::thread::sleep(Duration::from_millis(1));
+                assert!(i % 6 != 0, "task {i} failed");
+            });
+        }
+        pool.shutdown();
+
+        let stats = pool.stats();
+        assert_eq!((stats.queued, stats.active), (0, 0));
+        assert_eq!((stats.completed, stats.panicked), (10, 2));
+        assert!(stats.average_latency >= Duration::from_millis(1));
+    }
+
+    #[test]
+    fn panics_handed_to_a_handle_count_as_completed() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let handle = pool.spawn_with_handle(|| panic!("reported"));
+        assert!(handle.join().is_err());
+        pool.shutdown();
+
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.panicked), (1, 0));
+    }
+
+    #[test]
+    fn running_and_queued_tasks() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (started, running) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        for _ in 0..3 {
+            pool.spawn(|| {});
+        }
+
+        let stats = pool.stats();
+        assert_eq!((stats.threads, stats.active, stats.queued), (1, 1, 3));
+        release.send(()).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::{lock, CancellationToken, Origin, Pool, Priority, Shared, Task};
+
+type Periodic = Box<dyn FnMut() + Send + 'static>;
+
+enum Action {
+    Once(Task),
+    Every(Periodic, Duration),
+}
+
+struct Entry {
+    deadline: Instant,
+    // breaks ties between equal deadlines in the order they were scheduled
+    seq: u64,
+    token: CancellationToken,
+    action: Action,
+}
+
+// `BinaryHeap` is a max-heap, so the earliest deadline compares greatest
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+#[derive(Default)]
+struct TimerQueue {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    // the timer thread is only started once the first timer is set
+    running: bool,
+}
+
+// A single thread sleeps until the earliest deadline and then queues the
+// task on the workers like any other.
+#[derive(Default)]
+pub(crate) struct Timers {
+    queue: Mutex<TimerQueue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    // drops the entry if the pool has been shut down
+    fn schedule(self: &Arc<Self>, deadline: Instant, token: CancellationToken, action: Action) {
+        let mut queue = lock(&self.timers.queue);
+        // checked under the lock, so the timer thread cannot miss the entry
+        // on its way out
+        if self.shutdown.load(Ordering::SeqCst) {
+            drop(queue);
+            return;
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.entries.push(Entry {
+            deadline,
+            seq,
+            token,
+            action,
+        });
+        if !queue.running {
+            let shared = self.clone();
+            let started = self
+                .thread_builder("timer")
+                .spawn(move || shared.run_timers());
+            // otherwise the next timer tries again
+            if let Ok(handle) = started {
+                queue.running = true;
+                lock(&self.workers).push(handle);
+            }
+        }
+        drop(queue);
+        self.timers.changed.notify_one();
+    }
+
+    fn run_timers(self: &Arc<Self>) {
+        let mut queue = lock(&self.timers.queue);
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                // the tasks are dropped outside the lock, they may set timers
+                let entries = mem::take(&mut queue.entries);
+                drop(queue);
+                drop(entries);
+                return;
+            }
+            let now = Instant::now();
+            match queue.entries.peek().map(|entry| entry.deadline) {
+                Some(deadline) if deadline <= now => {
+                    let entry = queue.entries.pop().expect("peeked entry is due");
+                    drop(queue);
+                    self.fire(entry);
+                    queue = lock(&self.timers.queue);
+                }
+                Some(deadline) => {
+                    queue = self
+                        .timers
+                        .changed
+                        .wait_timeout(queue, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+                None => {
+                    queue = self
+                        .timers
+                        .changed
+                        .wait(queue)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+
+    fn fire(self: &Arc<Self>, entry: Entry) {
+        let Entry {
+            deadline,
+            token,
+            action,
+            ..
+        } = entry;
+        if token.is_cancelled() {
+            return;
+        }
+        let task: Task = match action {
+            Action::Once(task) => Box::new(move || {
+                if !token.is_cancelled() {
+                    task();
+                }
+            }),
+            Action::Every(mut f, period) => {
+                let shared = self.clone();
+                Box::new(move || {
+                    if token.is_cancelled() {
+                        return;
+                    }
+                    f();
+                    // the next run is set once this one is done, so runs never
+                    // overlap, and one that fell behind is not made up for
+                    let next = (deadline + period).max(Instant::now());
+                    shared.schedule(next, token, Action::Every(f, period));
+                })
+            }
+        };
+        // refused once the pool is shut down, which drops the task
+        let _ = self.push_from(task, Priority::Normal, Origin::Timer);
+    }
+
+    pub(crate) fn stop_timers(&self) {
+        drop(lock(&self.timers.queue));
+        self.timers.changed.notify_all();
+    }
+}
+
+/// Handle to a task started with [`Pool::spawn_after`] or
+/// [`Pool::spawn_every`]. Dropping it leaves the task scheduled.
+#[derive(Clone, Debug)]
+pub struct TimerHandle {
+    token: CancellationToken,
+}
+
+impl TimerHandle {
+    /// Keeps the task from running again. A run that has already started
+    /// is not interrupted.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl Pool {
+    /// Runs `f` on the workers once `delay` has passed. Timers that have not
+    /// fired yet are dropped when the pool shuts down.
+    pub fn spawn_after<F>(&self, delay: Duration, f: F) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let deadline = Instant::now() + delay;
+        self.shared
+            .schedule(deadline, token.clone(), Action::Once(Box::new(f)));
+        TimerHandle { token }
+    }
+
+    /// Runs `f` on the workers every `period`, starting one `period` from
+    /// now, until the handle is cancelled or `f` panics. A run that takes
+    /// longer than `period` delays the next one rather than overlapping it.
+    pub fn spawn_every<F>(&self, period: Duration, f: F) -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let deadline = Instant::now() + period;
+        self.shared
+            .schedule(deadline, token.clone(), Action::Every(Box::new(f), period));
+        TimerHandle { token }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn delayed_task_waits_for_its_deadline() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let start = Instant::now();
+        pool.spawn_after(Duration::from_millis(40), move || {
+            sender.send(Instant::now()).unwrap()
+        });
+        pool.spawn_after(Duration::from_millis(10), || {});
+
+        let ran = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ran - start >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn earlier_deadlines_fire_first() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        for delay in [30, 10, 20] {
+            let sender = sender.clone();
+            pool.spawn_after(Duration::from_millis(delay), move || {
+                sender.send(delay).unwrap()
+            });
+        }
+        let order: Vec<u64> = receiver.iter().take(3).collect();
+        assert_eq!(order, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn cancelled_timer_does_not_fire() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = pool.spawn_after(Duration::from_millis(20), move || sender.send(()).unwrap());
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        // the task is dropped once the deadline passes, which hangs up
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(pool.stats().completed, 0);
+    }
+
+    #[test]
+    fn periodic_task_runs_until_cancelled() {
+        let pool = Pool::with_thread_count(2).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let mut runs = 0;
+        let handle = pool.spawn_every(Duration::from_millis(2), move || {
+            runs += 1;
+            let _ = sender.send(runs);
+        });
+
+        let first: Vec<u32> = receiver.iter().take(3).collect();
+        assert_eq!(first, vec![1, 2, 3]);
+        handle.cancel();
+        // a run may have been under way while cancelling, after that the
+        // task is dropped and hangs up
+        let rest: Vec<u32> = receiver.iter().collect();
+        assert!(rest.len() <= 1);
+    }
+
+    #[test]
+    fn shutdown_drops_pending_timers() {
+        let pool = Pool::with_thread_count(1).unwrap();
+        let (sender, receiver) = mpsc::channel::<()>();
+        pool.spawn_after(Duration::from_secs(3600), move || sender.send(()).unwrap());
+        pool.shutdown();
+        assert_eq!(receiver.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    }
+}
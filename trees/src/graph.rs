@@ -1 +1,636 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::error::Error;
+use std::fmt;
 
+pub type NodeIndex = usize;
+pub type EdgeIndex = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphError {
+    /// The graph has a cycle, so it has no topological order.
+    Cycle,
+    /// The algorithm only works on directed graphs.
+    Undirected,
+    /// The algorithm only works on undirected graphs.
+    Directed,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Cycle => write!(f, "graph has a cycle"),
+            GraphError::Undirected => write!(f, "graph must be directed"),
+            GraphError::Directed => write!(f, "graph must be undirected"),
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+#[derive(Debug)]
+struct Node<N> {
+    payload: N,
+    // outgoing edges, for undirected graphs every edge touching the node
+    edges: Vec<EdgeIndex>,
+}
+
+#[derive(Debug)]
+struct Edge<E> {
+    payload: E,
+    from: NodeIndex,
+    to: NodeIndex,
+}
+
+/// Graph with a payload on every node and edge, both kept in arenas and
+/// addressed by their position.
+#[derive(Debug)]
+pub struct Graph<N, E> {
+    nodes: Vec<Node<N>>,
+    edges: Vec<Edge<E>>,
+    directed: bool,
+}
+
+impl<N, E> Graph<N, E> {
+    pub fn directed() -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            directed: true,
+        }
+    }
+
+    pub fn undirected() -> Self {
+        Self {
+            directed: false,
+            ..Self::directed()
+        }
+    }
+
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn add_node(&mut self, payload: N) -> NodeIndex {
+        self.nodes.push(Node {
+            payload,
+            edges: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Panics if either end is not a node of the graph.
+    pub fn add_edge(&mut self, from: NodeIndex, to: NodeIndex, payload: E) -> EdgeIndex {
+        assert!(
+            from < self.nodes.len() && to < self.nodes.len(),
+            "edge {from} -> {to} ends outside of the graph"
+        );
+        let index = self.edges.len();
+        self.edges.push(Edge { payload, from, to });
+        self.nodes[from].edges.push(index);
+        if !self.directed && from != to {
+            self.nodes[to].edges.push(index);
+        }
+        index
+    }
+
+    pub fn node(&self, index: NodeIndex) -> Option<&N> {
+        self.nodes.get(index).map(|node| &node.payload)
+    }
+
+    pub fn node_mut(&mut self, index: NodeIndex) -> Option<&mut N> {
+        self.nodes.get_mut(index).map(|node| &mut node.payload)
+    }
+
+    pub fn edge(&self, index: EdgeIndex) -> Option<&E> {
+        self.edges.get(index).map(|edge| &edge.payload)
+    }
+
+    pub fn edge_mut(&mut self, index: EdgeIndex) -> Option<&mut E> {
+        self.edges.get_mut(index).map(|edge| &mut edge.payload)
+    }
+
+    /// Start and end of an edge, in the order it was added.
+    pub fn endpoints(&self, index: EdgeIndex) -> Option<(NodeIndex, NodeIndex)> {
+        self.edges.get(index).map(|edge| (edge.from, edge.to))
+    }
+
+    /// Edges leaving `node` together with the node at their other end.
+    pub fn edges(&self, node: NodeIndex) -> impl Iterator<Item = (EdgeIndex, NodeIndex, &E)> {
+        self.nodes[node]
+            .edges
+            .iter()
+            .map(move |&edge| (edge, self.other_end(node, edge), &self.edges[edge].payload))
+    }
+
+    pub fn neighbors(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.nodes[node]
+            .edges
+            .iter()
+            .map(move |&edge| self.other_end(node, edge))
+    }
+
+    fn other_end(&self, node: NodeIndex, edge: EdgeIndex) -> NodeIndex {
+        let edge = &self.edges[edge];
+        if edge.from == node {
+            edge.to
+        } else {
+            edge.from
+        }
+    }
+
+    /// Nodes reachable from `start`, closest first. Panics if `start` is not
+    /// a node of the graph.
+    pub fn bfs(&self, start: NodeIndex) -> Vec<NodeIndex> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut order = vec![];
+        let mut queue = VecDeque::from([start]);
+        seen[start] = true;
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+            for next in self.neighbors(node) {
+                if !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        order
+    }
+
+    /// Nodes reachable from `start` in depth first preorder, neighbors in the
+    /// order their edges were added. Panics if `start` is not a node of the
+    /// graph.
+    pub fn dfs(&self, start: NodeIndex) -> Vec<NodeIndex> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut order = vec![];
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            if seen[node] {
+                continue;
+            }
+            seen[node] = true;
+            order.push(node);
+            // reversed so the first neighbor is popped first
+            let unseen: Vec<_> = self.neighbors(node).filter(|&n| !seen[n]).collect();
+            stack.extend(unseen.into_iter().rev());
+        }
+        order
+    }
+
+    /// Orders the nodes so every edge points forward, by Kahn's algorithm.
+    pub fn topological_sort(&self) -> Result<Vec<NodeIndex>, GraphError> {
+        if !self.directed {
+            return Err(GraphError::Undirected);
+        }
+        let mut incoming = vec![0; self.nodes.len()];
+        for edge in &self.edges {
+            incoming[edge.to] += 1;
+        }
+        let mut ready: VecDeque<_> = (0..self.nodes.len())
+            .filter(|&node| incoming[node] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+            for next in self.neighbors(node) {
+                incoming[next] -= 1;
+                if incoming[next] == 0 {
+                    ready.push_back(next);
+                }
+            }
+        }
+        // nodes on a cycle never run out of incoming edges
+        if order.len() == self.nodes.len() {
+            Ok(order)
+        } else {
+            Err(GraphError::Cycle)
+        }
+    }
+
+    /// For undirected graphs a self loop or a second edge between the same
+    /// two nodes counts as a cycle.
+    pub fn has_cycle(&self) -> bool {
+        if self.directed {
+            self.edges.iter().any(|edge| edge.from == edge.to)
+                || self
+                    .strongly_connected_components()
+                    .iter()
+                    .any(|component| component.len() > 1)
+        } else {
+            let mut sets = DisjointSets::new(self.nodes.len());
+            self.edges
+                .iter()
+                .any(|edge| !sets.union(edge.from, edge.to))
+        }
+    }
+
+    /// Shortest distance from `start` to every node, `None` for the nodes it
+    /// cannot reach. `cost` gives the length of an edge, lengths that add up
+    /// past `u64::MAX` saturate. Panics if `start` is not a node of the graph.
+    pub fn dijkstra(&self, start: NodeIndex, cost: impl Fn(&E) -> u64) -> Vec<Option<u64>> {
+        let mut distances = vec![None; self.nodes.len()];
+        let mut heap = BinaryHeap::from([Reverse((0u64, start))]);
+        distances[start] = Some(0);
+        while let Some(Reverse((distance, node))) = heap.pop() {
+            // a shorter way here was found after this entry was pushed
+            if distances[node].is_some_and(|best| best < distance) {
+                continue;
+            }
+            for (_, next, payload) in self.edges(node) {
+                let through = distance.saturating_add(cost(payload));
+                if distances[next].is_none_or(|best| through < best) {
+                    distances[next] = Some(through);
+                    heap.push(Reverse((through, next)));
+                }
+            }
+        }
+        distances
+    }
+
+    /// Shortest path from `start` to `goal` and its length. `heuristic`
+    /// estimates the distance left from a node and must never overestimate
+    /// it, otherwise the path found may not be the shortest. Lengths saturate
+    /// at `u64::MAX`. Panics if `start` is not a node of the graph.
+    pub fn astar(
+        &self,
+        start: NodeIndex,
+        goal: NodeIndex,
+        cost: impl Fn(&E) -> u64,
+        heuristic: impl Fn(NodeIndex) -> u64,
+    ) -> Option<(u64, Vec<NodeIndex>)> {
+        let mut distances = vec![None; self.nodes.len()];
+        let mut previous = vec![None; self.nodes.len()];
+        let mut heap = BinaryHeap::from([Reverse((heuristic(start), 0u64, start))]);
+        distances[start] = Some(0);
+        while let Some(Reverse((_, distance, node))) = heap.pop() {
+            if node == goal {
+                let mut path = vec![goal];
+                while let Some(prev) = previous[*path.last()?] {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some((distance, path));
+            }
+            if distances[node].is_some_and(|best| best < distance) {
+                continue;
+            }
+            for (_, next, payload) in self.edges(node) {
+                let through = distance.saturating_add(cost(payload));
+                if distances[next].is_none_or(|best| through < best) {
+                    distances[next] = Some(through);
+                    previous[next] = Some(node);
+                    heap.push(Reverse((
+                        through.saturating_add(heuristic(next)),
+                        through,
+                        next,
+                    )));
+                }
+            }
+        }
+        None
+    }
+
+    /// Groups of nodes that can all reach each other, by Tarjan's algorithm.
+    /// Components come out in reverse topological order. For undirected
+    /// graphs these are the connected components.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<NodeIndex>> {
+        let count = self.nodes.len();
+        let mut order = vec![None; count];
+        // lowest order reachable through the subtree and one back edge
+        let mut low = vec![0; count];
+        let mut on_stack = vec![false; count];
+        let mut stack = vec![];
+        let mut next_order = 0;
+        let mut components = vec![];
+
+        for root in 0..count {
+            if order[root].is_some() {
+                continue;
+            }
+            // nodes being visited and the position of the next edge to follow
+            let mut calls = vec![(root, 0)];
+            order[root] = Some(next_order);
+            low[root] = next_order;
+            next_order += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some((node, position)) = calls.last_mut() {
+                let node = *node;
+                if let Some(&edge) = self.nodes[node].edges.get(*position) {
+                    *position += 1;
+                    let next = self.other_end(node, edge);
+                    match order[next] {
+                        None => {
+                            order[next] = Some(next_order);
+                            low[next] = next_order;
+                            next_order += 1;
+                            stack.push(next);
+                            on_stack[next] = true;
+                            calls.push((next, 0));
+                        }
+                        Some(seen) if on_stack[next] => low[node] = low[node].min(seen),
+                        Some(_) => {}
+                    }
+                    continue;
+                }
+
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low[parent] = low[parent].min(low[node]);
+                }
+                if Some(low[node]) == order[node] {
+                    let mut component = vec![];
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    components.push(component);
+                }
+            }
+        }
+        components
+    }
+
+    /// Edges of a minimum spanning forest, by Kruskal's algorithm, one tree
+    /// for every connected component.
+    pub fn minimum_spanning_tree(
+        &self,
+        cost: impl Fn(&E) -> u64,
+    ) -> Result<Vec<EdgeIndex>, GraphError> {
+        if self.directed {
+            return Err(GraphError::Directed);
+        }
+        let mut by_cost: Vec<_> = (0..self.edges.len()).collect();
+        by_cost.sort_by_key(|&edge| cost(&self.edges[edge].payload));
+        let mut sets = DisjointSets::new(self.nodes.len());
+        Ok(by_cost
+            .into_iter()
+            .filter(|&edge| sets.union(self.edges[edge].from, self.edges[edge].to))
+            .collect())
+    }
+}
+
+// union-find over node indices
+struct DisjointSets {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(count: usize) -> Self {
+        Self {
+            parents: (0..count).collect(),
+            sizes: vec![1; count],
+        }
+    }
+
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parents[node] != node {
+            // path halving
+            self.parents[node] = self.parents[self.parents[node]];
+            node = self.parents[node];
+        }
+        node
+    }
+
+    /// Returns false if both were in the same set already.
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        if self.sizes[a] < self.sizes[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a -> b -> d, a -> c -> d, d -> e
+    fn diamond() -> Graph<&'static str, u64> {
+        let mut graph = Graph::directed();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        let c = graph.add_node("c");
+        let d = graph.add_node("d");
+        let e = graph.add_node("e");
+        graph.add_edge(a, b, 1);
+        graph.add_edge(a, c, 4);
+        graph.add_edge(b, d, 5);
+        graph.add_edge(c, d, 1);
+        graph.add_edge(d, e, 2);
+        graph
+    }
+
+    // two triangles joined by one edge, plus a node on its own
+    fn triangles() -> Graph<(), u64> {
+        let mut graph = Graph::undirected();
+        for _ in 0..7 {
+            graph.add_node(());
+        }
+        graph.add_edge(0, 1, 3);
+        graph.add_edge(1, 2, 1);
+        graph.add_edge(2, 0, 2);
+        graph.add_edge(2, 3, 7);
+        graph.add_edge(3, 4, 2);
+        graph.add_edge(4, 5, 4);
+        graph.add_edge(5, 3, 1);
+        graph
+    }
+
+    fn sorted(mut components: Vec<Vec<NodeIndex>>) -> Vec<Vec<NodeIndex>> {
+        for component in components.iter_mut() {
+            component.sort();
+        }
+        components.sort();
+        components
+    }
+
+    #[test]
+    fn payloads_and_adjacency() {
+        let mut graph = diamond();
+        assert_eq!((graph.node_count(), graph.edge_count()), (5, 5));
+        assert_eq!(graph.node(3), Some(&"d"));
+        assert_eq!(graph.node(5), None);
+        *graph.edge_mut(0).unwrap() = 10;
+        assert_eq!(graph.edge(0), Some(&10));
+        assert_eq!(graph.endpoints(4), Some((3, 4)));
+        assert_eq!(graph.neighbors(0).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(graph.neighbors(4).count(), 0);
+
+        // undirected edges are followed from both ends
+        let graph = triangles();
+        assert_eq!(graph.neighbors(2).collect::<Vec<_>>(), vec![1, 0, 3]);
+        let edges: Vec<_> = graph.edges(3).map(|(e, n, &w)| (e, n, w)).collect();
+        assert_eq!(edges, vec![(3, 2, 7), (4, 4, 2), (6, 5, 1)]);
+    }
+
+    #[test]
+    fn bfs_and_dfs_orders() {
+        let graph = diamond();
+        assert_eq!(graph.bfs(0), vec![0, 1, 2, 3, 4]);
+        assert_eq!(graph.dfs(0), vec![0, 1, 3, 4, 2]);
+        assert_eq!(graph.bfs(2), vec![2, 3, 4]);
+
+        let graph = triangles();
+        assert_eq!(graph.bfs(0), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(graph.dfs(0), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(graph.dfs(6), vec![6]);
+    }
+
+    #[test]
+    fn topological_sort_and_cycles() {
+        let mut graph = diamond();
+        assert_eq!(graph.topological_sort(), Ok(vec![0, 1, 2, 3, 4]));
+        assert!(!graph.has_cycle());
+
+        graph.add_edge(4, 1, 0);
+        assert_eq!(graph.topological_sort(), Err(GraphError::Cycle));
+        assert!(graph.has_cycle());
+
+        let mut graph: Graph<(), ()> = Graph::directed();
+        let a = graph.add_node(());
+        assert!(!graph.has_cycle());
+        graph.add_edge(a, a, ());
+        assert!(graph.has_cycle());
+
+        let mut graph = triangles();
+        assert_eq!(graph.topological_sort(), Err(GraphError::Undirected));
+        assert!(graph.has_cycle());
+        let mut tree: Graph<(), u64> = Graph::undirected();
+        for _ in 0..3 {
+            tree.add_node(());
+        }
+        tree.add_edge(0, 1, 0);
+        tree.add_edge(1, 2, 0);
+        assert!(!tree.has_cycle());
+        graph.add_edge(6, 6, 0);
+        assert!(graph.has_cycle());
+    }
+
+    #[test]
+    fn dijkstra_distances() {
+        let graph = diamond();
+        assert_eq!(
+            graph.dijkstra(0, |&w| w),
+            vec![Some(0), Some(1), Some(4), Some(5), Some(7)]
+        );
+        assert_eq!(
+            graph.dijkstra(1, |&w| w),
+            vec![None, Some(0), None, Some(5), Some(7)]
+        );
+
+        let graph = triangles();
+        let distances = graph.dijkstra(0, |&w| w);
+        assert_eq!(distances[..6], [0, 3, 2, 9, 11, 10].map(Some));
+        assert_eq!(distances[6], None);
+    }
+
+    #[test]
+    fn long_paths_saturate() {
+        let graph = diamond();
+        let distances = graph.dijkstra(0, |_| u64::MAX / 2);
+        assert_eq!(distances[4], Some(u64::MAX));
+        let (length, path) = graph.astar(0, 4, |_| u64::MAX / 2, |_| u64::MAX).unwrap();
+        assert_eq!((length, path.len()), (u64::MAX, 4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn search_from_outside_the_graph_panics() {
+        diamond().dijkstra(5, |&w| w);
+    }
+
+    #[test]
+    fn astar_paths() {
+        // 3x3 grid, every step costs 1 except into the middle
+        let mut grid = Graph::undirected();
+        for y in 0..3 {
+            for x in 0..3 {
+                grid.add_node((x, y));
+            }
+        }
+        for y in 0..3 {
+            for x in 0..3 {
+                let node = y * 3 + x;
+                let cost = |to: usize| if to == 4 { 10 } else { 1 };
+                if x < 2 {
+                    grid.add_edge(node, node + 1, cost(node + 1).max(cost(node)));
+                }
+                if y < 2 {
+                    grid.add_edge(node, node + 3, cost(node + 3).max(cost(node)));
+                }
+            }
+        }
+        let manhattan = |node: NodeIndex| {
+            let (x, y) = grid.node(node).copied().unwrap();
+            (2 - x as u64) + (2 - y as u64)
+        };
+        let (length, path) = grid.astar(0, 8, |&w: &u64| w, manhattan).unwrap();
+        assert_eq!(length, 4);
+        assert_eq!(path.len(), 5);
+        assert!(!path.contains(&4));
+        assert_eq!((path[0], path[4]), (0, 8));
+
+        let graph = diamond();
+        assert_eq!(
+            graph.astar(0, 4, |&w| w, |_| 0),
+            Some((7, vec![0, 2, 3, 4]))
+        );
+        assert_eq!(graph.astar(4, 0, |&w| w, |_| 0), None);
+        assert_eq!(graph.astar(2, 2, |&w| w, |_| 0), Some((0, vec![2])));
+    }
+
+    #[test]
+    fn strongly_connected_components() {
+        // 0 <-> 1 -> 2 -> 3 -> 4 -> 2, 5 alone
+        let mut graph: Graph<(), ()> = Graph::directed();
+        for _ in 0..6 {
+            graph.add_node(());
+        }
+        for (from, to) in [(0, 1), (1, 0), (1, 2), (2, 3), (3, 4), (4, 2)] {
+            graph.add_edge(from, to, ());
+        }
+        let components = graph.strongly_connected_components();
+        // sinks come first
+        assert_eq!(components[0].len(), 3);
+        assert_eq!(sorted(components), vec![vec![0, 1], vec![2, 3, 4], vec![5]]);
+
+        let components = triangles().strongly_connected_components();
+        assert_eq!(sorted(components), vec![vec![0, 1, 2, 3, 4, 5], vec![6]]);
+    }
+
+    #[test]
+    fn minimum_spanning_tree() {
+        let graph = triangles();
+        let mut edges = graph.minimum_spanning_tree(|&w| w).unwrap();
+        edges.sort();
+        assert_eq!(edges, vec![1, 2, 3, 4, 6]);
+        let total: u64 = edges.iter().map(|&e| graph.edge(e).unwrap()).sum();
+        assert_eq!(total, 13);
+
+        assert_eq!(
+            diamond().minimum_spanning_tree(|&w| w),
+            Err(GraphError::Directed)
+        );
+    }
+}
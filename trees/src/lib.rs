@@ -1,2 +1,3 @@
 pub mod graph;
+pub mod ordered_map;
 pub mod tree;
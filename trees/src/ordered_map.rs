@@ -0,0 +1,651 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+
+// plain slot positions, the map never hands them out
+type NodeIndex = usize;
+
+#[derive(Debug)]
+struct MapNode<K, V> {
+    key: K,
+    value: V,
+    left: Option<NodeIndex>,
+    right: Option<NodeIndex>,
+    // longest path down to a leaf, counting this node
+    height: u8,
+}
+
+/// Map sorted by key, kept as an AVL tree in an arena like [`crate::tree::Tree`].
+#[derive(Debug)]
+pub struct OrderedMap<K, V> {
+    // removed nodes leave a `None` behind, its slot is reused by the next insert
+    arena: Vec<Option<MapNode<K, V>>>,
+    free: Vec<NodeIndex>,
+    root: Option<NodeIndex>,
+    len: usize,
+}
+
+impl<K, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            arena: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            len: 0,
+        }
+    }
+}
+
+impl<K: Ord, V> OrderedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the previous value if `key` was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (root, old) = self.insert_at(self.root, key, value);
+        self.root = Some(root);
+        old
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (root, removed) = self.remove_at(self.root, key);
+        self.root = root;
+        removed
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).map(|index| &self.node(index).value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let index = self.find(key)?;
+        Some(&mut self.node_mut(index).value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    pub fn min(&self) -> Option<(&K, &V)> {
+        self.min_index().map(|index| self.entry(index))
+    }
+
+    pub fn max(&self) -> Option<(&K, &V)> {
+        self.max_index().map(|index| self.entry(index))
+    }
+
+    /// Entry with the largest key that is not greater than `key`.
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.floor_index(key, true).map(|index| self.entry(index))
+    }
+
+    /// Entry with the smallest key that is not less than `key`.
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.ceiling_index(key).map(|index| self.entry(index))
+    }
+
+    /// Entries in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Entries with a key inside `range`, in key order.
+    pub fn range<Q, R>(&self, range: R) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let last = match range.end_bound() {
+            Bound::Included(end) => self.floor_index(end, true),
+            Bound::Excluded(end) => self.floor_index(end, false),
+            Bound::Unbounded => self.max_index(),
+        };
+        let mut iter = Iter {
+            map: self,
+            stack: Vec::new(),
+            last,
+        };
+
+        // the path down to the first key in range, skipping the subtrees
+        // that lie below it
+        let mut at = self.root;
+        while let Some(index) = at {
+            let node = self.node(index);
+            let key = node.key.borrow();
+            let below = match range.start_bound() {
+                Bound::Included(start) => key < start,
+                Bound::Excluded(start) => key <= start,
+                Bound::Unbounded => false,
+            };
+            if below {
+                at = node.right;
+            } else {
+                iter.stack.push(index);
+                at = node.left;
+            }
+        }
+
+        // a start beyond the end leaves nothing to visit
+        match (iter.stack.last(), last) {
+            (Some(&first), Some(last)) if self.node(first).key <= self.node(last).key => {}
+            _ => iter.stack.clear(),
+        }
+        iter
+    }
+
+    fn node(&self, index: NodeIndex) -> &MapNode<K, V> {
+        self.arena[index]
+            .as_ref()
+            .expect("tree links point at live nodes")
+    }
+
+    fn node_mut(&mut self, index: NodeIndex) -> &mut MapNode<K, V> {
+        self.arena[index]
+            .as_mut()
+            .expect("tree links point at live nodes")
+    }
+
+    fn entry(&self, index: NodeIndex) -> (&K, &V) {
+        let node = self.node(index);
+        (&node.key, &node.value)
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<NodeIndex>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut at = self.root;
+        while let Some(index) = at {
+            let node = self.node(index);
+            at = match key.cmp(node.key.borrow()) {
+                Ordering::Less => node.left,
+                Ordering::Greater => node.right,
+                Ordering::Equal => return Some(index),
+            };
+        }
+        None
+    }
+
+    fn min_index(&self) -> Option<NodeIndex> {
+        let mut index = self.root?;
+        while let Some(left) = self.node(index).left {
+            index = left;
+        }
+        Some(index)
+    }
+
+    fn max_index(&self) -> Option<NodeIndex> {
+        let mut index = self.root?;
+        while let Some(right) = self.node(index).right {
+            index = right;
+        }
+        Some(index)
+    }
+
+    // largest key below `key`, or equal to it if `inclusive`
+    fn floor_index<Q>(&self, key: &Q, inclusive: bool) -> Option<NodeIndex>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut best = None;
+        let mut at = self.root;
+        while let Some(index) = at {
+            let node = self.node(index);
+            match node.key.borrow().cmp(key) {
+                Ordering::Equal if inclusive => return Some(index),
+                Ordering::Less => {
+                    best = Some(index);
+                    at = node.right;
+                }
+                _ => at = node.left,
+            }
+        }
+        best
+    }
+
+    fn ceiling_index<Q>(&self, key: &Q) -> Option<NodeIndex>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut best = None;
+        let mut at = self.root;
+        while let Some(index) = at {
+            let node = self.node(index);
+            match node.key.borrow().cmp(key) {
+                Ordering::Equal => return Some(index),
+                Ordering::Greater => {
+                    best = Some(index);
+                    at = node.left;
+                }
+                _ => at = node.right,
+            }
+        }
+        best
+    }
+
+    fn add_node(&mut self, node: MapNode<K, V>) -> NodeIndex {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            self.arena[index] = Some(node);
+            return index;
+        }
+        self.arena.push(Some(node));
+        self.arena.len() - 1
+    }
+
+    fn remove_node_at(&mut self, index: NodeIndex) -> MapNode<K, V> {
+        self.len -= 1;
+        self.free.push(index);
+        self.arena[index]
+            .take()
+            .expect("tree links point at live nodes")
+    }
+
+    fn height(&self, index: Option<NodeIndex>) -> u8 {
+        index.map_or(0, |index| self.node(index).height)
+    }
+
+    // left height minus right height
+    fn balance(&self, index: NodeIndex) -> i16 {
+        let node = self.node(index);
+        i16::from(self.height(node.left)) - i16::from(self.height(node.right))
+    }
+
+    fn update_height(&mut self, index: NodeIndex) {
+        let node = self.node(index);
+        let height = 1 + self.height(node.left).max(self.height(node.right));
+        self.node_mut(index).height = height;
+    }
+
+    //     i          l
+    //    / \        / \
+    //   l   c  ->  a   i
+    //  / \            / \
+    // a   b          b   c
+    fn rotate_right(&mut self, index: NodeIndex) -> NodeIndex {
+        let left = self
+            .node(index)
+            .left
+            .expect("rotated node has a left child");
+        self.node_mut(index).left = self.node(left).right;
+        self.node_mut(left).right = Some(index);
+        self.update_height(index);
+        self.update_height(left);
+        left
+    }
+
+    fn rotate_left(&mut self, index: NodeIndex) -> NodeIndex {
+        let right = self
+            .node(index)
+            .right
+            .expect("rotated node has a right child");
+        self.node_mut(index).right = self.node(right).left;
+        self.node_mut(right).left = Some(index);
+        self.update_height(index);
+        self.update_height(right);
+        right
+    }
+
+    // restores the AVL property at `index` after one of its subtrees changed
+    // height by one, returns the new root of the subtree
+    fn rebalance(&mut self, index: NodeIndex) -> NodeIndex {
+        self.update_height(index);
+        match self.balance(index) {
+            2.. => {
+                let left = self
+                    .node(index)
+                    .left
+                    .expect("left-heavy node has a left child");
+                if self.balance(left) < 0 {
+                    let left = self.rotate_left(left);
+                    self.node_mut(index).left = Some(left);
+                }
+                self.rotate_right(index)
+            }
+            ..=-2 => {
+                let right = self
+                    .node(index)
+                    .right
+                    .expect("right-heavy node has a right child");
+                if self.balance(right) > 0 {
+                    let right = self.rotate_right(right);
+                    self.node_mut(index).right = Some(right);
+                }
+                self.rotate_left(index)
+            }
+            _ => index,
+        }
+    }
+
+    fn insert_at(&mut self, at: Option<NodeIndex>, key: K, value: V) -> (NodeIndex, Option<V>) {
+        let Some(index) = at else {
+            let node = MapNode {
+                key,
+                value,
+                left: None,
+                right: None,
+                height: 1,
+            };
+            return (self.add_node(node), None);
+        };
+        match key.cmp(&self.node(index).key) {
+            Ordering::Less => {
+                let (left, old) = self.insert_at(self.node(index).left, key, value);
+                self.node_mut(index).left = Some(left);
+                (self.rebalance(index), old)
+            }
+            Ordering::Greater => {
+                let (right, old) = self.insert_at(self.node(index).right, key, value);
+                self.node_mut(index).right = Some(right);
+                (self.rebalance(index), old)
+            }
+            Ordering::Equal => {
+                let old = mem::replace(&mut self.node_mut(index).value, value);
+                (index, Some(old))
+            }
+        }
+    }
+
+    fn remove_at<Q>(&mut self, at: Option<NodeIndex>, key: &Q) -> (Option<NodeIndex>, Option<V>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let Some(index) = at else {
+            return (None, None);
+        };
+        match key.cmp(self.node(index).key.borrow()) {
+            Ordering::Less => {
+                let (left, removed) = self.remove_at(self.node(index).left, key);
+                self.node_mut(index).left = left;
+                (Some(self.rebalance(index)), removed)
+            }
+            Ordering::Greater => {
+                let (right, removed) = self.remove_at(self.node(index).right, key);
+                self.node_mut(index).right = right;
+                (Some(self.rebalance(index)), removed)
+            }
+            Ordering::Equal => {
+                let node = self.remove_node_at(index);
+                let replacement = match (node.left, node.right) {
+                    (left, None) => left,
+                    (None, right) => right,
+                    // the smallest node on the right takes the removed one's place
+                    (Some(left), Some(right)) => {
+                        let (rest, successor) = self.detach_min(right);
+                        let moved = self.node_mut(successor);
+                        moved.left = Some(left);
+                        moved.right = rest;
+                        Some(self.rebalance(successor))
+                    }
+                };
+                (replacement, Some(node.value))
+            }
+        }
+    }
+
+    // unlinks the smallest node of the subtree, returns the rest of the
+    // subtree and the unlinked node
+    fn detach_min(&mut self, index: NodeIndex) -> (Option<NodeIndex>, NodeIndex) {
+        match self.node(index).left {
+            None => (self.node(index).right, index),
+            Some(left) => {
+                let (rest, min) = self.detach_min(left);
+                self.node_mut(index).left = rest;
+                (Some(self.rebalance(index)), min)
+            }
+        }
+    }
+}
+
+/// In-order iterator over an [`OrderedMap`], see [`OrderedMap::range`].
+pub struct Iter<'a, K, V> {
+    map: &'a OrderedMap<K, V>,
+    // nodes whose left subtree has been visited, the next one on top
+    stack: Vec<NodeIndex>,
+    // last node in range
+    last: Option<NodeIndex>,
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.stack.pop()?;
+        if Some(index) == self.last {
+            self.stack.clear();
+        } else {
+            let mut at = self.map.node(index).right;
+            while let Some(child) = at {
+                self.stack.push(child);
+                at = self.map.node(child).left;
+            }
+        }
+        Some(self.map.entry(index))
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a OrderedMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    // checks order, heights and balance below `at`, returns the height and
+    // the number of nodes
+    fn check_subtree<K: Ord, V>(
+        map: &OrderedMap<K, V>,
+        at: Option<NodeIndex>,
+        low: Option<&K>,
+        high: Option<&K>,
+    ) -> (u8, usize) {
+        let Some(index) = at else {
+            return (0, 0);
+        };
+        let node = map.node(index);
+        assert!(low.is_none_or(|low| *low < node.key), "order violated");
+        assert!(high.is_none_or(|high| node.key < *high), "order violated");
+
+        let (left, left_count) = check_subtree(map, node.left, low, Some(&node.key));
+        let (right, right_count) = check_subtree(map, node.right, Some(&node.key), high);
+        assert!(left.abs_diff(right) <= 1, "unbalanced node");
+        assert_eq!(node.height, 1 + left.max(right), "stale height");
+        (node.height, 1 + left_count + right_count)
+    }
+
+    fn check_invariants<K: Ord, V>(map: &OrderedMap<K, V>) {
+        let (_, count) = check_subtree(map, map.root, None, None);
+        assert_eq!(count, map.len());
+        assert_eq!(map.arena.len(), map.len() + map.free.len());
+        assert!(map.free.iter().all(|&index| map.arena[index].is_none()));
+    }
+
+    fn get_map() -> OrderedMap<i32, &'static str> {
+        [
+            (50, "e"),
+            (20, "b"),
+            (80, "h"),
+            (10, "a"),
+            (30, "c"),
+            (70, "g"),
+            (40, "d"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn insert_get_and_remove() {
+        let mut map = get_map();
+        assert_eq!(map.len(), 7);
+        assert_eq!(map.get(&30), Some(&"c"));
+        assert_eq!(map.get(&35), None);
+
+        assert_eq!(map.insert(30, "C"), Some("c"));
+        *map.get_mut(&40).unwrap() = "D";
+        assert_eq!(map.remove(&50), Some("e"));
+        assert_eq!(map.remove(&50), None);
+        assert!(!map.contains_key(&50));
+
+        let entries: Vec<_> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (10, "a"),
+                (20, "b"),
+                (30, "C"),
+                (40, "D"),
+                (70, "g"),
+                (80, "h")
+            ]
+        );
+        check_invariants(&map);
+    }
+
+    #[test]
+    fn min_max_floor_and_ceiling() {
+        let map = get_map();
+        assert_eq!(map.min(), Some((&10, &"a")));
+        assert_eq!(map.max(), Some((&80, &"h")));
+        assert_eq!(map.floor(&45), Some((&40, &"d")));
+        assert_eq!(map.floor(&40), Some((&40, &"d")));
+        assert_eq!(map.floor(&5), None);
+        assert_eq!(map.ceiling(&45), Some((&50, &"e")));
+        assert_eq!(map.ceiling(&80), Some((&80, &"h")));
+        assert_eq!(map.ceiling(&81), None);
+
+        let empty: OrderedMap<i32, ()> = OrderedMap::new();
+        assert!(empty.min().is_none() && empty.floor(&1).is_none());
+    }
+
+    #[test]
+    fn range_queries() {
+        let map = get_map();
+        let keys = |iter: Iter<'_, i32, &str>| iter.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(map.range(20..50)), vec![20, 30, 40]);
+        assert_eq!(keys(map.range(25..=70)), vec![30, 40, 50, 70]);
+        assert_eq!(keys(map.range(..30)), vec![10, 20]);
+        assert_eq!(keys(map.range(71..)), vec![80]);
+        assert_eq!(
+            keys(map.range((Bound::Excluded(10), Bound::Excluded(30)))),
+            vec![20]
+        );
+        assert!(keys(map.range(41..49)).is_empty());
+        assert!(keys(map.range(90..)).is_empty());
+    }
+
+    #[test]
+    fn borrowed_keys() {
+        let mut map = OrderedMap::new();
+        map.insert("pear".to_string(), 3);
+        map.insert("apple".to_string(), 1);
+        assert_eq!(map.get("apple"), Some(&1));
+        assert_eq!(map.ceiling("b").map(|(k, _)| k.as_str()), Some("pear"));
+        assert_eq!(map.remove("pear"), Some(3));
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut map: OrderedMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        for i in 0..50 {
+            map.remove(&i);
+        }
+        for i in 100..150 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.arena.len(), 100);
+        check_invariants(&map);
+    }
+
+    #[test]
+    fn sorted_inserts_stay_balanced() {
+        let map: OrderedMap<u32, ()> = (0..1023).map(|i| (i, ())).collect();
+        assert_eq!(map.height(map.root), 10);
+        check_invariants(&map);
+    }
+
+    #[test]
+    fn random_operations_match_btree_map() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = OrderedMap::new();
+            let mut expected = BTreeMap::new();
+
+            for _ in 0..2000 {
+                let key: u16 = rng.gen_range(0..300);
+                if rng.gen_bool(0.6) {
+                    assert_eq!(map.insert(key, seed), expected.insert(key, seed));
+                } else {
+                    assert_eq!(map.remove(&key), expected.remove(&key));
+                }
+                assert_eq!(map.floor(&key), expected.range(..=key).next_back());
+                assert_eq!(map.ceiling(&key), expected.range(key..).next());
+                check_invariants(&map);
+            }
+
+            assert!(map.iter().eq(expected.iter()));
+            let (low, high) = (rng.gen_range(0..150), rng.gen_range(150..300));
+            assert!(map.range(low..high).eq(expected.range(low..high)));
+            assert_eq!(map.min(), expected.iter().next());
+            assert_eq!(map.max(), expected.iter().next_back());
+        }
+    }
+}
@@ -1,4 +1,12 @@
-pub type TreeIndex = usize;
+use std::collections::{HashMap, VecDeque};
+
+/// Handle to a node in a [`Tree`]. The generation tells apart handles to a
+/// removed node from handles to the node that later reused its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeIndex {
+    slot: usize,
+    generation: u32,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct TreeNode {
@@ -13,57 +21,204 @@ impl TreeNode {
     }
 }
 
+#[derive(Debug)]
+struct Slot {
+    // bumped whenever the node in this slot is removed
+    generation: u32,
+    node: Option<TreeNode>,
+}
+
 #[derive(Debug)]
 pub struct Tree {
-    // stable arena, removing nodes are not popped, just converted from Some to None
-    arena: Vec<Option<TreeNode>>,
+    // removed nodes leave an empty slot behind, it is reused by the next added node
+    arena: Vec<Slot>,
+    free: Vec<usize>,
+    // generation of newly pushed slots, raised by `compact` above every
+    // generation handed out before so that old handles to truncated slots miss
+    generation: u32,
     root: Option<TreeIndex>,
 }
 
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Tree {
     pub fn new() -> Self {
         Self {
             arena: Vec::new(),
+            free: Vec::new(),
+            generation: 0,
             root: None,
         }
     }
 
+    /// Detached preorder cursor, see [`Tree::preorder`] for an [`Iterator`].
     pub fn iter(&self) -> PreorderIter {
         PreorderIter::new(self.root)
     }
 
+    pub fn preorder(&self) -> Walk<'_, PreorderIter> {
+        Walk::new(self, PreorderIter::new(self.root))
+    }
+
+    pub fn inorder(&self) -> Walk<'_, InorderIter> {
+        Walk::new(self, InorderIter::new(self.root))
+    }
+
+    pub fn postorder(&self) -> Walk<'_, PostorderIter> {
+        Walk::new(self, PostorderIter::new(self.root))
+    }
+
+    /// Breadth first, each level from left to right.
+    pub fn level_order(&self) -> Walk<'_, LevelOrderIter> {
+        Walk::new(self, LevelOrderIter::new(self.root))
+    }
+
+    pub fn preorder_mut(&mut self, visit: impl FnMut(TreeIndex, &mut TreeNode)) {
+        self.visit_mut(PreorderIter::new(self.root), visit)
+    }
+
+    pub fn inorder_mut(&mut self, visit: impl FnMut(TreeIndex, &mut TreeNode)) {
+        self.visit_mut(InorderIter::new(self.root), visit)
+    }
+
+    pub fn postorder_mut(&mut self, visit: impl FnMut(TreeIndex, &mut TreeNode)) {
+        self.visit_mut(PostorderIter::new(self.root), visit)
+    }
+
+    pub fn level_order_mut(&mut self, visit: impl FnMut(TreeIndex, &mut TreeNode)) {
+        self.visit_mut(LevelOrderIter::new(self.root), visit)
+    }
+
+    /// Calls `visit` on every node `cursor` steps to. The links of a node are
+    /// followed before it is visited, changing them only affects later walks.
+    pub fn visit_mut<C: TreeCursor>(
+        &mut self,
+        mut cursor: C,
+        mut visit: impl FnMut(TreeIndex, &mut TreeNode),
+    ) {
+        while let Some(index) = cursor.next(self) {
+            if let Some(node) = self.node_at_mut(index) {
+                visit(index, node)
+            }
+        }
+    }
+
+    pub fn root(&self) -> Option<TreeIndex> {
+        self.root
+    }
+
     pub fn set_root(&mut self, root: Option<TreeIndex>) {
         self.root = root
     }
 
+    /// Number of nodes in the arena.
+    pub fn len(&self) -> usize {
+        self.arena.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn add_node(&mut self, node: TreeNode) -> TreeIndex {
-        let index = self.arena.len();
-        self.arena.push(Some(node));
-        return index;
+        if let Some(slot) = self.free.pop() {
+            let entry = &mut self.arena[slot];
+            entry.node = Some(node);
+            return TreeIndex {
+                slot,
+                generation: entry.generation,
+            };
+        }
+        self.arena.push(Slot {
+            generation: self.generation,
+            node: Some(node),
+        });
+        TreeIndex {
+            slot: self.arena.len() - 1,
+            generation: self.generation,
+        }
     }
 
+    /// Links to the removed node are left as they are, they become stale.
     pub fn remove_node_at(&mut self, index: TreeIndex) -> Option<TreeNode> {
-        if let Some(node) = self.arena.get_mut(index) {
-            node.take()
-        } else {
-            None
+        let entry = self.arena.get_mut(index.slot)?;
+        if entry.generation != index.generation {
+            return None;
         }
+        let node = entry.node.take()?;
+        entry.generation = entry.generation.wrapping_add(1);
+        self.free.push(index.slot);
+        Some(node)
+    }
+
+    /// Whether `index` points at a node, rather than at one that was removed.
+    pub fn contains(&self, index: TreeIndex) -> bool {
+        self.node_at(index).is_some()
     }
 
     pub fn node_at(&self, index: TreeIndex) -> Option<&TreeNode> {
-        return if let Some(node) = self.arena.get(index) {
-            node.as_ref()
-        } else {
-            None
-        };
+        match self.arena.get(index.slot) {
+            Some(entry) if entry.generation == index.generation => entry.node.as_ref(),
+            _ => None,
+        }
     }
 
     pub fn node_at_mut(&mut self, index: TreeIndex) -> Option<&mut TreeNode> {
-        return if let Some(node) = self.arena.get_mut(index) {
-            node.as_mut()
-        } else {
-            None
-        };
+        match self.arena.get_mut(index.slot) {
+            Some(entry) if entry.generation == index.generation => entry.node.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Moves all nodes to the front of the arena and drops the empty slots.
+    /// Returns where every node went, links between nodes and the root are
+    /// rewritten already, links to removed nodes are cleared. Every handle
+    /// from before is stale afterwards.
+    pub fn compact(&mut self) -> HashMap<TreeIndex, TreeIndex> {
+        // above every generation handed out so far
+        let generation = self
+            .arena
+            .iter()
+            .map(|entry| entry.generation)
+            .fold(self.generation, u32::max)
+            .wrapping_add(1);
+        self.generation = generation;
+
+        let mut remap = HashMap::with_capacity(self.len());
+        let mut arena = Vec::with_capacity(self.len());
+        for (slot, entry) in self.arena.drain(..).enumerate() {
+            if let Some(node) = entry.node {
+                let old = TreeIndex {
+                    slot,
+                    generation: entry.generation,
+                };
+                let new = TreeIndex {
+                    slot: arena.len(),
+                    generation,
+                };
+                remap.insert(old, new);
+                arena.push(Slot {
+                    generation,
+                    node: Some(node),
+                });
+            }
+        }
+
+        let relink = |link: Option<TreeIndex>| link.and_then(|index| remap.get(&index).copied());
+        for entry in arena.iter_mut() {
+            if let Some(node) = entry.node.as_mut() {
+                node.left = relink(node.left);
+                node.right = relink(node.right);
+            }
+        }
+        self.root = relink(self.root);
+        self.arena = arena;
+        self.free.clear();
+        remap
     }
 }
 
@@ -95,10 +250,166 @@ impl PreorderIter {
             }
         }
 
-        return None;
+        None
     } // immutable borrow &Tree ends here
 }
 
+impl TreeCursor for PreorderIter {
+    fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        PreorderIter::next(self, tree)
+    }
+}
+
+pub struct InorderIter {
+    stack: Vec<TreeIndex>,
+    // next subtree to go down the left side of
+    current: Option<TreeIndex>,
+}
+
+impl InorderIter {
+    pub fn new(root: Option<TreeIndex>) -> Self {
+        InorderIter {
+            stack: vec![],
+            current: root,
+        }
+    }
+
+    pub fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        loop {
+            while let Some(index) = self.current {
+                self.current = tree.node_at(index).and_then(|node| {
+                    self.stack.push(index);
+                    node.left
+                });
+            }
+
+            let node_index = self.stack.pop()?;
+            if let Some(node) = tree.node_at(node_index) {
+                self.current = node.right;
+                return Some(node_index);
+            }
+        }
+    }
+}
+
+impl TreeCursor for InorderIter {
+    fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        InorderIter::next(self, tree)
+    }
+}
+
+pub struct PostorderIter {
+    // nodes are pushed a second time, marked, once their children are on the stack
+    stack: Vec<(TreeIndex, bool)>,
+}
+
+impl PostorderIter {
+    pub fn new(root: Option<TreeIndex>) -> Self {
+        PostorderIter {
+            stack: root.into_iter().map(|index| (index, false)).collect(),
+        }
+    }
+
+    pub fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        while let Some((node_index, expanded)) = self.stack.pop() {
+            let Some(node) = tree.node_at(node_index) else {
+                continue;
+            };
+            if expanded {
+                return Some(node_index);
+            }
+
+            self.stack.push((node_index, true));
+            if let Some(right) = node.right {
+                self.stack.push((right, false))
+            }
+
+            if let Some(left) = node.left {
+                self.stack.push((left, false))
+            }
+        }
+
+        None
+    }
+}
+
+impl TreeCursor for PostorderIter {
+    fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        PostorderIter::next(self, tree)
+    }
+}
+
+pub struct LevelOrderIter {
+    queue: VecDeque<TreeIndex>,
+}
+
+impl LevelOrderIter {
+    pub fn new(root: Option<TreeIndex>) -> Self {
+        LevelOrderIter {
+            queue: root.into_iter().collect(),
+        }
+    }
+
+    pub fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        while let Some(node_index) = self.queue.pop_front() {
+            if let Some(node) = tree.node_at(node_index) {
+                self.queue.extend(node.left);
+                self.queue.extend(node.right);
+                return Some(node_index);
+            }
+        }
+
+        None
+    }
+}
+
+impl TreeCursor for LevelOrderIter {
+    fn next(&mut self, tree: &Tree) -> Option<TreeIndex> {
+        LevelOrderIter::next(self, tree)
+    }
+}
+
+/// A traversal that keeps no borrow of the tree between steps, so the tree
+/// can be changed while walking it. Removed nodes are skipped.
+pub trait TreeCursor {
+    fn next(&mut self, tree: &Tree) -> Option<TreeIndex>;
+}
+
+/// Borrowing [`Iterator`] over a tree, driven by one of the cursors.
+pub struct Walk<'a, C> {
+    tree: &'a Tree,
+    cursor: C,
+}
+
+impl<'a, C: TreeCursor> Walk<'a, C> {
+    pub fn new(tree: &'a Tree, cursor: C) -> Self {
+        Walk { tree, cursor }
+    }
+
+    /// Gives up the borrow, the walk can be continued with the cursor.
+    pub fn into_cursor(self) -> C {
+        self.cursor
+    }
+}
+
+impl<'a, C: TreeCursor> Iterator for Walk<'a, C> {
+    type Item = (TreeIndex, &'a TreeNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.cursor.next(self.tree)?;
+        self.tree.node_at(index).map(|node| (index, node))
+    }
+}
+
+impl<'a> IntoIterator for &'a Tree {
+    type Item = (TreeIndex, &'a TreeNode);
+    type IntoIter = Walk<'a, PreorderIter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.preorder()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,4 +438,122 @@ mod tests {
         let expected_values: Vec<usize> = vec![1, 2, 4, 5, 3];
         assert_eq!(values, expected_values);
     }
+
+    #[test]
+    fn removed_slots_are_reused_and_stale_indices_miss() {
+        let mut tree = get_tree();
+        let root = tree.root().unwrap();
+        let leaf = tree.node_at(root).unwrap().right.unwrap();
+        assert_eq!(tree.remove_node_at(leaf).map(|n| n.value), Some(3));
+        assert_eq!(tree.remove_node_at(leaf), None);
+        assert_eq!(tree.len(), 4);
+
+        let reused = tree.add_node(TreeNode::new(6, None, None));
+        assert_eq!(tree.len(), 5);
+        assert!(!tree.contains(leaf));
+        assert!(tree.node_at(leaf).is_none());
+        assert!(tree.node_at_mut(leaf).is_none());
+        assert_eq!(tree.node_at(reused).map(|n| n.value), Some(6));
+    }
+
+    #[test]
+    fn compact_drops_empty_slots_and_remaps_links() {
+        let mut tree = Tree::new();
+        let gap = tree.add_node(TreeNode::new(0, None, None));
+        let a = tree.add_node(TreeNode::new(4, None, None));
+        let b = tree.add_node(TreeNode::new(5, None, None));
+        let removed = tree.add_node(TreeNode::new(9, None, None));
+        let c = tree.add_node(TreeNode::new(2, Some(a), Some(b)));
+        let d = tree.add_node(TreeNode::new(3, None, Some(removed)));
+        let e = tree.add_node(TreeNode::new(1, Some(c), Some(d)));
+        tree.set_root(Some(e));
+        tree.remove_node_at(gap);
+        tree.remove_node_at(removed);
+
+        let remap = tree.compact();
+        assert_eq!(remap.len(), 5);
+        assert_eq!(tree.len(), 5);
+        assert!(!remap.contains_key(&gap));
+        for old in [a, b, c, d, e] {
+            assert!(tree.node_at(old).is_none());
+        }
+        assert_eq!(tree.root(), Some(remap[&e]));
+        assert_eq!(tree.node_at(remap[&c]).unwrap().left, Some(remap[&a]));
+        assert_eq!(tree.node_at(remap[&d]).unwrap().right, None);
+
+        let mut preorder = tree.iter();
+        let mut values = vec![];
+        while let Some(i) = preorder.next(&tree) {
+            values.push(tree.node_at(i).unwrap().value);
+        }
+        assert_eq!(values, vec![1, 2, 4, 5, 3]);
+
+        // new nodes go after the compacted ones, into the slots `d` and `e`
+        // had before, without reviving those handles
+        let f = tree.add_node(TreeNode::new(7, None, None));
+        let g = tree.add_node(TreeNode::new(8, None, None));
+        assert_eq!(tree.node_at(f).map(|n| n.value), Some(7));
+        assert_eq!(tree.node_at(g).map(|n| n.value), Some(8));
+        assert_ne!((f, g), (d, e));
+        assert!(tree.node_at(d).is_none());
+        assert!(tree.node_at(e).is_none());
+        assert_eq!(tree.len(), 7);
+    }
+
+    fn values<'a>(walk: impl Iterator<Item = (TreeIndex, &'a TreeNode)>) -> Vec<usize> {
+        walk.map(|(_, node)| node.value).collect()
+    }
+
+    #[test]
+    fn borrowing_iterators_visit_in_order() {
+        let tree = get_tree();
+        assert_eq!(values(tree.preorder()), vec![1, 2, 4, 5, 3]);
+        assert_eq!(values(tree.inorder()), vec![4, 2, 5, 1, 3]);
+        assert_eq!(values(tree.postorder()), vec![4, 5, 2, 3, 1]);
+        assert_eq!(values(tree.level_order()), vec![1, 2, 3, 4, 5]);
+        assert_eq!(values((&tree).into_iter()), vec![1, 2, 4, 5, 3]);
+        assert_eq!(values(Tree::new().inorder()), vec![]);
+    }
+
+    #[test]
+    fn mutable_visitors_change_every_node() {
+        let mut tree = get_tree();
+        let mut order = vec![];
+        tree.postorder_mut(|_, node| {
+            order.push(node.value);
+            node.value *= 10;
+        });
+        assert_eq!(order, vec![4, 5, 2, 3, 1]);
+        assert_eq!(values(tree.level_order()), vec![10, 20, 30, 40, 50]);
+
+        // pruning the children of the root does not change the current walk
+        let mut visited = 0;
+        tree.preorder_mut(|_, node| {
+            node.left = None;
+            node.right = None;
+            visited += 1;
+        });
+        assert_eq!(visited, 5);
+        assert_eq!(values(tree.inorder()), vec![10]);
+    }
+
+    #[test]
+    fn detached_cursor_allows_changes_between_steps() {
+        let mut tree = get_tree();
+        let mut cursor = tree.inorder().into_cursor();
+        let first = cursor.next(&tree).unwrap();
+        assert_eq!(tree.node_at(first).map(|n| n.value), Some(4));
+
+        // the parent is already on the cursor stack, its subtree is skipped
+        let root = tree.root().unwrap();
+        let parent = tree.node_at(root).unwrap().left.unwrap();
+        tree.remove_node_at(parent);
+        tree.node_at_mut(root).unwrap().value = 100;
+
+        let mut rest = vec![];
+        while let Some(i) = cursor.next(&tree) {
+            rest.push(tree.node_at(i).unwrap().value);
+        }
+        assert_eq!(rest, vec![100, 3]);
+    }
 }